@@ -0,0 +1,23 @@
+// Speaks the CommunicationMod protocol on stdin/stdout, backed by the simulator instead of the game.
+// Wire it to the AI with a pipe in each direction, e.g.
+//   mkfifo game && commsim < game | spireai > game
+use spireai::comm::simulator::Simulator;
+use std::io::{stdin, stdout, BufRead, Write};
+
+fn main() {
+    let mut simulator = Simulator::new();
+    let stdin = stdin();
+    let mut stdout = stdout();
+
+    for line in stdin.lock().lines() {
+        let line = line.expect("Communication failed!");
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = simulator.handle(&line);
+        let serialized = serde_json::to_string(&request).expect("Failed to serialize state!");
+        writeln!(stdout, "{}", serialized).expect("Failed to write!");
+        stdout.flush().expect("Failed to write!");
+    }
+}
@@ -0,0 +1,75 @@
+// Plays runs inside the simulator and writes a training record for every decision, e.g.
+//   selfplay --runs 100 --out records.jsonl --iterations 200
+// The layout of the encoded states is written next to the records, in <out>.layout.ron
+// The search and evaluator settings come from the config, as they do for spireai
+use spireai::config::args::{load_config, take_flag, take_number, take_option};
+use spireai::models;
+use spireai::spireai::selfplay::{self, Policy, SelfPlayConfig};
+use spireai::state::probability;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let runs = take_number(&mut args, "--runs").unwrap_or(1) as usize;
+    let out = take_option(&mut args, "--out").unwrap_or_else(|| usage());
+    let random = take_flag(&mut args, "--random");
+    let settings = load_config(&mut args);
+    models::set_data_dir(&settings.data_dir);
+    if let Some(seed) = settings.seed {
+        probability::seed_thread(seed);
+    }
+
+    let mut config = SelfPlayConfig {
+        policy: if random {
+            Policy::Random
+        } else {
+            Policy::Search(settings.search_config())
+        },
+        ..SelfPlayConfig::default()
+    };
+    if let Some(max_decisions) = take_number(&mut args, "--max-decisions") {
+        config.max_decisions = max_decisions as usize;
+    }
+    if !args.is_empty() {
+        usage();
+    }
+
+    selfplay::write_layout(Path::new(&out))
+        .unwrap_or_else(|err| panic!("Unable to write the layout for {}: {}", out, err));
+
+    let file = File::create(&out).unwrap_or_else(|err| panic!("Unable to create {}: {}", out, err));
+    let mut writer = BufWriter::new(file);
+    let evaluator = settings
+        .evaluator()
+        .unwrap_or_else(|err| panic!("Unable to load evaluator {:?}: {}", settings.evaluator, err));
+    for run in 0..runs {
+        let records = selfplay::play_run(run, &config, evaluator.clone());
+        selfplay::write_records(&records, &mut writer)
+            .unwrap_or_else(|err| panic!("Unable to write to {}: {}", out, err));
+        writer.flush().expect("Failed to write!");
+
+        let outcome = records.first().map(|a| a.outcome).unwrap_or_default();
+        eprintln!(
+            "Run {}: {} decisions, {} on floor {}",
+            run,
+            records.len(),
+            if !outcome.finished {
+                "unfinished"
+            } else if outcome.won {
+                "won"
+            } else {
+                "died"
+            },
+            outcome.floor
+        );
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: selfplay --out <records.jsonl> [--runs <count>] [--random] [--max-decisions <count>] [--config <spireai.ron>] [--time <ms>] [--iterations <count>] [--threads <count>] [--seed <number>] [--evaluator <floor | heuristic | weights.ron> | --network <network.ron>]"
+    );
+    std::process::exit(2);
+}
@@ -0,0 +1,85 @@
+// Fits a value network to self-play records (.jsonl) or transcripts of real games, e.g.
+//   train --out network.ron --hidden 64,32 --epochs 20 records.jsonl games/*.transcript
+// The network is written in the format that spireai --network loads. The final state of each run
+// is scored with the evaluator from the config, which is the floor reached unless set otherwise.
+use spireai::config::args::{load_config, take_option};
+use spireai::models;
+use spireai::spireai::network::Activation;
+use spireai::spireai::trainer::{self, TrainConfig};
+use std::path::PathBuf;
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let out = take_option(&mut args, "--out").unwrap_or_else(|| usage());
+    let settings = load_config(&mut args);
+    models::set_data_dir(&settings.data_dir);
+
+    let mut config = TrainConfig {
+        seed: settings.seed,
+        ..TrainConfig::default()
+    };
+    if let Some(hidden) = take_option(&mut args, "--hidden") {
+        config.hidden = hidden
+            .split(',')
+            .filter(|a| !a.is_empty())
+            .map(|a| parse(a, "--hidden"))
+            .collect();
+    }
+    if let Some(activation) = take_option(&mut args, "--activation") {
+        config.activation = match activation.as_str() {
+            "relu" => Activation::Relu,
+            "tanh" => Activation::Tanh,
+            "linear" => Activation::Linear,
+            _ => usage(),
+        };
+    }
+    if let Some(epochs) = take_option(&mut args, "--epochs") {
+        config.epochs = parse(&epochs, "--epochs");
+    }
+    if let Some(learning_rate) = take_option(&mut args, "--learning-rate") {
+        config.learning_rate = parse(&learning_rate, "--learning-rate");
+    }
+    if let Some(holdout) = take_option(&mut args, "--holdout") {
+        config.holdout = parse(&holdout, "--holdout");
+    }
+    if args.is_empty() || args.iter().any(|a| a.starts_with("--")) {
+        usage();
+    }
+
+    let paths: Vec<PathBuf> = args.iter().map(PathBuf::from).collect();
+    let evaluator = settings
+        .evaluator()
+        .unwrap_or_else(|err| panic!("Unable to load evaluator {:?}: {}", settings.evaluator, err));
+    let samples = trainer::load(&paths, evaluator.as_ref())
+        .unwrap_or_else(|err| panic!("Unable to load the datasets: {}", err));
+    eprintln!("Training on {} samples", samples.len());
+
+    let network = trainer::train(&samples, &config, |loss| match loss.holdout {
+        Some(holdout) => eprintln!(
+            "Epoch {}: training loss {:.2}, held out loss {:.2}",
+            loss.epoch, loss.train, holdout
+        ),
+        None => eprintln!("Epoch {}: training loss {:.2}", loss.epoch, loss.train),
+    });
+    network
+        .validate()
+        .unwrap_or_else(|err| panic!("Trained an invalid network: {}", err));
+
+    let serialized = ron::ser::to_string_pretty(&network, ron::ser::PrettyConfig::default())
+        .expect("Failed to serialize the network!");
+    std::fs::write(&out, serialized)
+        .unwrap_or_else(|err| panic!("Unable to write {}: {}", out, err));
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: train --out <network.ron> [--hidden <size,size,..>] [--activation <relu | tanh | linear>] [--epochs <count>] [--learning-rate <rate>] [--holdout <fraction>] [--seed <number>] [--config <spireai.ron>] [--evaluator <floor | heuristic | weights.ron> | --network <network.ron>] <dataset>..."
+    );
+    std::process::exit(2);
+}
+
+fn parse<T: std::str::FromStr>(value: &str, name: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| panic!("Unable to parse {} for {}", value, name))
+}
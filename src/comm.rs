@@ -1,3 +1,6 @@
+pub mod diagnostics;
 pub mod interop;
 pub mod request;
 pub mod response;
+pub mod simulator;
+pub mod transcript;
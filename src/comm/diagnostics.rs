@@ -0,0 +1,528 @@
+use std::fmt::{self, Debug, Write};
+
+use itertools::Itertools;
+use uuid::Uuid;
+
+use crate::comm::interop;
+use crate::comm::request as external;
+use crate::state as internal;
+use crate::state::floor::FloorState;
+use crate::state::HashMap;
+
+// Only the closest outcomes are worth reading when a prediction goes wrong
+const REPORTED_OUTCOMES: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub path: String,
+    pub game: String,
+    pub predicted: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: game {}, predicted {}",
+            self.path, self.game, self.predicted
+        )
+    }
+}
+
+// Lists the failed checks for each predicted outcome, closest outcome first
+pub fn report(
+    external: &Option<external::GameState>,
+    outcomes: &[&FloorState],
+    uuid_map: &HashMap<String, Uuid>,
+) -> String {
+    if outcomes.is_empty() {
+        return String::from("No outcomes were predicted for the last choice");
+    }
+
+    let diagnosed: Vec<Vec<Mismatch>> = outcomes
+        .iter()
+        .map(|outcome| state_mismatches(external, outcome, uuid_map))
+        .sorted_by_key(|mismatches| mismatches.len())
+        .collect();
+
+    let mut report = String::new();
+    for (index, mismatches) in diagnosed.iter().take(REPORTED_OUTCOMES).enumerate() {
+        writeln!(
+            report,
+            "Outcome {} of {}, {} mismatches:",
+            index + 1,
+            outcomes.len(),
+            mismatches.len()
+        )
+        .unwrap();
+        for mismatch in mismatches {
+            writeln!(report, "  {}", mismatch).unwrap();
+        }
+    }
+
+    report
+}
+
+// Mirrors the checks in interop::state_matches
+pub fn state_mismatches(
+    external: &Option<external::GameState>,
+    internal: &FloorState,
+    uuid_map: &HashMap<String, Uuid>,
+) -> Vec<Mismatch> {
+    let mut mismatches = Mismatches(Vec::new());
+
+    let external = match external {
+        Some(external) => external,
+        None => {
+            mismatches.check("state", "Menu", floor_name(internal));
+            return mismatches.0;
+        }
+    };
+
+    if let Some(combat_state) = &external.combat_state {
+        if let FloorState::Battle(battle_state) = internal {
+            battle_mismatches(combat_state, battle_state, &mut mismatches);
+        } else {
+            mismatches.check("state", "Battle", floor_name(internal));
+        }
+        return mismatches.0;
+    }
+
+    if matches!(internal, FloorState::Menu | FloorState::GameOver(..)) {
+        mismatches.check(
+            "state",
+            screen_name(&external.screen_state),
+            floor_name(internal),
+        );
+        return mismatches.0;
+    }
+
+    let game = internal.game_state();
+    if !interop::class_matches(&external.class, game.class) {
+        mismatches.push(
+            "class",
+            format!("{:?}", external.class),
+            format!("{:?}", game.class),
+        );
+    }
+    mismatches.check("hp", external.current_hp as u16, game.hp.amount);
+    mismatches.check("max_hp", external.max_hp as u16, game.hp.max);
+    mismatches.check("gold", external.gold as u16, game.gold);
+    mismatches.check("floor", external.floor as i8, game.map.floor);
+    if !interop::floor_state_matches(external, internal, &mut uuid_map.clone()) {
+        screen_mismatches(external, internal, &mut mismatches);
+    }
+    mismatches.check_contents(
+        "deck",
+        external.deck.iter().map(external_card_label),
+        game.deck.values().map(internal_card_label),
+    );
+    mismatches.check(
+        "potions",
+        external
+            .potions
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect_vec(),
+        game.potions
+            .iter()
+            .map(|a| a.map_or("Potion Slot", |p| p.name.as_str()))
+            .collect_vec(),
+    );
+    mismatches.check_contents(
+        "relics",
+        external.relics.iter().map(|a| a.name.to_string()),
+        game.relics.iter().map(|a| a.base.name.to_string()),
+    );
+    mismatches.check("act", external.act as u8, game.act);
+    mismatches.check("ascension", external.ascension_level as u8, game.asc);
+
+    mismatches.0
+}
+
+fn battle_mismatches(
+    external: &external::CombatState,
+    internal: &internal::battle::BattleState,
+    mismatches: &mut Mismatches,
+) {
+    mismatches.check_contents(
+        "player.buffs",
+        external.player.powers.iter().map(external_buff_label),
+        internal.player.buffs.iter().map(internal_buff_label),
+    );
+    mismatches.check(
+        "player.block",
+        external.player.block as u16,
+        internal.player.block,
+    );
+
+    let piles = [
+        ("hand", &external.hand, internal.hand.iter().collect_vec()),
+        (
+            "draw",
+            &external.draw_pile,
+            internal.draw.iter().collect_vec(),
+        ),
+        (
+            "discard",
+            &external.discard_pile,
+            internal.discard.iter().collect_vec(),
+        ),
+        (
+            "exhaust",
+            &external.exhaust_pile,
+            internal.exhaust.iter().collect_vec(),
+        ),
+    ];
+    for (name, external_pile, internal_pile) in piles.iter() {
+        mismatches.check_contents(
+            name,
+            external_pile.iter().map(external_card_label),
+            internal_pile
+                .iter()
+                .map(|uuid| internal_card_label(&internal.cards[*uuid])),
+        );
+    }
+
+    mismatches.check(
+        "discard_count",
+        external.cards_discarded_this_turn as u8,
+        internal.discard_count,
+    );
+
+    monster_mismatches(&external.monsters, &internal.monsters, mismatches);
+
+    mismatches.check(
+        "player.energy",
+        external.player.energy as u8,
+        internal.energy,
+    );
+    if !interop::orbs_match(&external.player.orbs, &internal.orbs) {
+        mismatches.push(
+            "player.orbs",
+            external
+                .player
+                .orbs
+                .iter()
+                .map(|a| format!("{} {}", a.name, a.evoke_amount))
+                .join(", "),
+            internal
+                .orbs
+                .iter()
+                .map(|a| format!("{:?} {}", a.base, a.n))
+                .join(", "),
+        );
+    }
+}
+
+fn monster_mismatches(
+    external: &[external::Monster],
+    internal: &HashMap<Uuid, internal::core::Monster>,
+    mismatches: &mut Mismatches,
+) {
+    if external.len() != internal.len() {
+        mismatches.check("monsters.len", external.len(), internal.len());
+        return;
+    }
+
+    for monster in internal.values().sorted_by_key(|a| a.position) {
+        let path = |field: &str| format!("monsters[{}].{}", monster.position, field);
+        // Positions can have gaps, e.g. once a monster has split
+        let game_monster = match external.get(monster.position) {
+            Some(game_monster) => game_monster,
+            None => {
+                mismatches.push(
+                    &path("name"),
+                    String::from("missing"),
+                    monster.base.name.clone(),
+                );
+                continue;
+            }
+        };
+        mismatches.check(
+            &path("name"),
+            game_monster.name.as_str(),
+            monster.base.name.as_str(),
+        );
+        mismatches.check(
+            &path("hp"),
+            game_monster.current_hp as u16,
+            monster.creature.hp.amount,
+        );
+        mismatches.check(
+            &path("max_hp"),
+            game_monster.max_hp as u16,
+            monster.creature.hp.max,
+        );
+        mismatches.check(
+            &path("block"),
+            game_monster.block as u16,
+            monster.creature.block,
+        );
+        mismatches.check(
+            &path("targetable"),
+            !game_monster.is_gone,
+            monster.targetable,
+        );
+        if !interop::intent_matches(&game_monster.intent, monster.intent) {
+            mismatches.push(
+                &path("intent"),
+                format!("{:?}", game_monster.intent),
+                format!("{:?}", monster.intent),
+            );
+        }
+        mismatches.check_contents(
+            &path("buffs"),
+            game_monster.powers.iter().map(external_buff_label),
+            monster.creature.buffs.iter().map(internal_buff_label),
+        );
+    }
+}
+
+fn screen_mismatches(
+    external: &external::GameState,
+    internal: &FloorState,
+    mismatches: &mut Mismatches,
+) {
+    match (&external.screen_state, internal) {
+        (external::ScreenState::Event(event), FloorState::Event(event_state)) => {
+            mismatches.check(
+                "event.name",
+                event.event_name.as_str(),
+                event_state.base.name.as_str(),
+            );
+            mismatches.check(
+                "event.options",
+                event.options.iter().map(|a| a.label.as_str()).collect_vec(),
+                event_state
+                    .available_choices
+                    .iter()
+                    .map(|a| a.as_str())
+                    .collect_vec(),
+            );
+        }
+        (external::ScreenState::CombatReward(rewards), FloorState::BattleRewards(battle_over)) => {
+            if !interop::rewards_match(rewards, &battle_over.rewards.rewards) {
+                mismatches.push(
+                    "rewards",
+                    format!("{:?}", rewards.rewards),
+                    format!("{:?}", battle_over.rewards.rewards),
+                );
+            }
+        }
+        (external::ScreenState::ShopScreen(shop), FloorState::Shop(shop_state)) => {
+            mismatches.check_contents(
+                "shop.cards",
+                shop.cards
+                    .iter()
+                    .map(|a| format!("{} {}", a.name, a.price.unwrap_or_default())),
+                shop_state.cards.iter().map(|(offer, price)| {
+                    let plus = if offer.upgraded { "+" } else { "" };
+                    format!("{}{} {}", offer.base.name, plus, price)
+                }),
+            );
+            mismatches.check_contents(
+                "shop.relics",
+                shop.relics
+                    .iter()
+                    .map(|a| format!("{} {}", a.name, a.price.unwrap_or_default())),
+                shop_state
+                    .relics
+                    .iter()
+                    .map(|(relic, price)| format!("{} {}", relic.name, price)),
+            );
+            mismatches.check_contents(
+                "shop.potions",
+                shop.potions
+                    .iter()
+                    .map(|a| format!("{} {}", a.name, a.price.unwrap_or_default())),
+                shop_state
+                    .potions
+                    .iter()
+                    .map(|(potion, price)| format!("{} {}", potion.name, price)),
+            );
+            mismatches.check("shop.purge", shop.purge_available, shop_state.can_purge);
+        }
+        (external::ScreenState::Chest(chest), FloorState::Chest(chest_state)) => {
+            if !interop::chest_matches(chest, chest_state.chest) {
+                mismatches.push(
+                    "chest",
+                    format!("{:?}", chest.chest_type),
+                    format!("{:?}", chest_state.chest),
+                );
+            }
+        }
+        (screen, _) => mismatches.push(
+            "screen",
+            screen_name(screen).to_string(),
+            floor_name(internal).to_string(),
+        ),
+    }
+}
+
+struct Mismatches(Vec<Mismatch>);
+
+impl Mismatches {
+    fn push(&mut self, path: &str, game: String, predicted: String) {
+        self.0.push(Mismatch {
+            path: path.to_string(),
+            game,
+            predicted,
+        })
+    }
+
+    fn check<T: PartialEq + Debug>(&mut self, path: &str, game: T, predicted: T) {
+        if game != predicted {
+            self.push(path, format!("{:?}", game), format!("{:?}", predicted))
+        }
+    }
+
+    // Compares as multisets, and only reports the items that are not on both sides
+    fn check_contents(
+        &mut self,
+        path: &str,
+        game: impl Iterator<Item = String>,
+        predicted: impl Iterator<Item = String>,
+    ) {
+        let mut game_only: Vec<String> = game.sorted().collect();
+        let mut predicted_only: Vec<String> = Vec::new();
+        for item in predicted.sorted() {
+            match game_only.iter().position(|a| a == &item) {
+                Some(position) => {
+                    game_only.remove(position);
+                }
+                None => predicted_only.push(item),
+            }
+        }
+
+        if !game_only.is_empty() || !predicted_only.is_empty() {
+            self.push(
+                path,
+                format!("[{}]", game_only.join(", ")),
+                format!("[{}]", predicted_only.join(", ")),
+            )
+        }
+    }
+}
+
+// Cards are labelled with what interop::card_matches compares
+fn external_card_label(card: &external::Card) -> String {
+    let name = card.name.strip_suffix('+').unwrap_or(&card.name);
+    card_label(name, card.upgrades as u8, interop::import_cost(card.cost))
+}
+
+fn internal_card_label(card: &internal::core::Card) -> String {
+    card_label(&card.base.name, card.upgrades, card.cost)
+}
+
+fn card_label(name: &str, upgrades: u8, cost: u8) -> String {
+    format!("{}{} ({})", name, "+".repeat(upgrades as usize), cost)
+}
+
+fn external_buff_label(power: &external::Power) -> String {
+    format!("{} {}", power.name, power.amount)
+}
+
+fn internal_buff_label(buff: &internal::core::Buff) -> String {
+    format!("{} {}", buff.base.name, buff.vars.x)
+}
+
+fn floor_name(state: &FloorState) -> &'static str {
+    match state {
+        FloorState::Event(_) => "Event",
+        FloorState::Rest(_) => "Rest",
+        FloorState::Chest(_) => "Chest",
+        FloorState::Battle(_) => "Battle",
+        FloorState::BattleRewards(_) => "BattleRewards",
+        FloorState::GameOver(..) => "GameOver",
+        FloorState::Shop(_) => "Shop",
+        FloorState::Map(_) => "Map",
+        FloorState::Menu => "Menu",
+    }
+}
+
+fn screen_name(screen: &external::ScreenState) -> &'static str {
+    match screen {
+        external::ScreenState::None {} => "None",
+        external::ScreenState::Event(_) => "Event",
+        external::ScreenState::Chest(_) => "Chest",
+        external::ScreenState::ShopRoom {} => "ShopRoom",
+        external::ScreenState::Rest(_) => "Rest",
+        external::ScreenState::CardReward(_) => "CardReward",
+        external::ScreenState::CombatReward(_) => "CombatReward",
+        external::ScreenState::Map(_) => "Map",
+        external::ScreenState::BossReward(_) => "BossReward",
+        external::ScreenState::ShopScreen(_) => "ShopScreen",
+        external::ScreenState::Grid(_) => "Grid",
+        external::ScreenState::HandSelect(_) => "HandSelect",
+        external::ScreenState::GameOver(_) => "GameOver",
+        external::ScreenState::Complete {} => "Complete",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::vector;
+
+    use crate::comm::simulator::export_state;
+    use crate::models::core::{Class, FightType};
+    use crate::state::battle::BattleState;
+    use crate::state::core::{Reward, RewardState};
+    use crate::state::floor::{BattleRewardsState, FloorState};
+    use crate::state::game::GameState;
+    use crate::state::probability::Probability;
+    use crate::state::HashMap;
+
+    use super::Mismatches;
+
+    #[test]
+    fn reports_only_failed_checks() {
+        let state = FloorState::Map(GameState::new(Class::Ironclad, 0));
+        let mut external = export_state(&state, &None);
+        assert!(super::state_mismatches(&external, &state, &HashMap::default()).is_empty());
+
+        external.as_mut().unwrap().current_hp -= 5;
+        let mismatches = super::state_mismatches(&external, &state, &HashMap::default());
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].path, "hp");
+        assert_eq!(mismatches[0].game, "75");
+        assert_eq!(mismatches[0].predicted, "80");
+    }
+
+    #[test]
+    fn matching_rewards_report_nothing() {
+        let state = FloorState::BattleRewards(BattleRewardsState {
+            boss: false,
+            game_state: GameState::new(Class::Ironclad, 0),
+            rewards: RewardState {
+                rewards: vector![Reward::Gold(25)],
+                deck_operation: None,
+                viewing_reward: None,
+            },
+        });
+        let external = export_state(&state, &None);
+        assert!(super::state_mismatches(&external, &state, &HashMap::default()).is_empty());
+    }
+
+    #[test]
+    fn reports_missing_monsters() {
+        let mut battle = BattleState::new(
+            GameState::new(Class::Ironclad, 0),
+            &[String::from("Jaw Worm")],
+            FightType::Common,
+            &mut Probability::new(),
+        );
+        let state = FloorState::Battle(battle.clone());
+        let external = export_state(&state, &None).unwrap();
+        let external_monsters = external.combat_state.unwrap().monsters;
+
+        for (_, monster) in battle.monsters.iter_mut() {
+            monster.position = 1;
+        }
+        let mut mismatches = Mismatches(vec![]);
+        super::monster_mismatches(&external_monsters, &battle.monsters, &mut mismatches);
+        assert_eq!(mismatches.0.len(), 1);
+        assert_eq!(mismatches.0[0].path, "monsters[1].name");
+        assert_eq!(mismatches.0[0].game, "missing");
+    }
+}
@@ -1,10 +1,15 @@
-use im::{HashMap, HashSet, Vector};
+use std::error::Error;
+
+use im::{vector, Vector};
 use uuid::Uuid;
 
 use crate::comm::request as external;
 use crate::models::monsters::Intent as NewIntent;
 use crate::models::{self, core as internal_core};
+use crate::spireai::references::MonsterReference;
 use crate::state as internal;
+use crate::state::probability::new_uuid;
+use crate::state::{HashMap, HashSet};
 
 pub fn state_matches(
     external: &Option<external::GameState>,
@@ -63,6 +68,612 @@ pub fn update_state(
     }
 }
 
+// Builds the whole floor from what the game reports, so that the AI can pick up a run in progress
+// or start over from the game's state when its predictions went wrong. Hidden state such as
+// monster move histories and the map history can't be recovered and starts out fresh.
+// The game doesn't report the stance either, so it is taken from the predicted state when that
+// is a battle as well.
+pub fn import_state(
+    external: &Option<external::GameState>,
+    predicted: &internal::floor::FloorState,
+    uuid_map: &mut HashMap<String, Uuid>,
+) -> Result<internal::floor::FloorState, Box<dyn Error>> {
+    let external = match external {
+        Some(external) => external,
+        None => return Ok(internal::floor::FloorState::Menu),
+    };
+
+    if let external::ScreenState::GameOver(game_over) = &external.screen_state {
+        return Ok(internal::floor::FloorState::GameOver(
+            game_over.victory,
+            false,
+        ));
+    }
+
+    let game = import_game(external, uuid_map)?;
+
+    if let Some(combat_state) = &external.combat_state {
+        let mut battle = import_battle(external, combat_state, game, uuid_map)?;
+        if let internal::floor::FloorState::Battle(predicted) = predicted {
+            battle.stance = predicted.stance;
+        }
+        battle.card_choose = import_card_choose(&external.screen_state, &battle, uuid_map)?;
+        Ok(internal::floor::FloorState::Battle(battle))
+    } else {
+        import_screen(external, game)
+    }
+}
+
+// Cards picked in the middle of a battle. What happens to them afterwards isn't reported.
+fn import_card_choose(
+    screen: &external::ScreenState,
+    battle: &internal::battle::BattleState,
+    uuid_map: &HashMap<String, Uuid>,
+) -> Result<Option<internal::battle::CardChoiceState>, Box<dyn Error>> {
+    let (cards, min, max) = match screen {
+        external::ScreenState::None {} => return Ok(None),
+        external::ScreenState::HandSelect(select) => (
+            &select.cards,
+            if select.can_pick_zero {
+                0
+            } else {
+                select.num_cards as usize
+            },
+            select.num_cards as usize,
+        ),
+        external::ScreenState::Grid(grid) => (
+            &grid.cards,
+            if grid.any_number {
+                0
+            } else {
+                grid.num_cards as usize
+            },
+            grid.num_cards as usize,
+        ),
+        other => {
+            return Err(format!(
+                "Unable to import a {} screen during combat",
+                screen_name(other)
+            )
+            .into())
+        }
+    };
+
+    let in_piles: Vec<_> = battle
+        .hand()
+        .chain(battle.draw())
+        .chain(battle.discard())
+        .chain(battle.exhaust())
+        .collect();
+    let choices: Vector<_> = cards
+        .iter()
+        .map(|card| {
+            uuid_map
+                .get(&card.id)
+                .and_then(|uuid| in_piles.iter().find(|a| a.uuid == *uuid))
+                .copied()
+                .ok_or_else(|| format!("Unable to find {} among the cards in combat", card.name))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let min = min.min(choices.len());
+    let max = max.min(choices.len()).max(min);
+    Ok(Some(internal::battle::CardChoiceState {
+        choices,
+        count_range: min..max + 1,
+        then: Vector::new(),
+        scry: false,
+    }))
+}
+
+fn import_screen(
+    external: &external::GameState,
+    game_state: internal::game::GameState,
+) -> Result<internal::floor::FloorState, Box<dyn Error>> {
+    let state = match &external.screen_state {
+        external::ScreenState::Event(event) => {
+            let mut event_state =
+                internal::event::EventState::by_name(&event.event_name, game_state);
+            event_state.available_choices = event
+                .options
+                .iter()
+                .filter(|option| !option.disabled)
+                .map(|option| option.label.to_string())
+                .collect();
+            internal::floor::FloorState::Event(event_state)
+        }
+        external::ScreenState::Chest(chest) => {
+            internal::floor::FloorState::Chest(internal::floor::ChestState {
+                chest: import_chest(&chest.chest_type)?,
+                rewards: if chest.chest_open {
+                    Some(import_rewards(vector![]))
+                } else {
+                    None
+                },
+                game_state,
+            })
+        }
+        external::ScreenState::BossReward(relics) => {
+            internal::floor::FloorState::Chest(internal::floor::ChestState {
+                chest: internal_core::ChestType::Boss,
+                rewards: Some(import_rewards(
+                    relics
+                        .iter()
+                        .map(|relic| {
+                            internal::core::Reward::Relic(models::relics::by_name(&relic.name))
+                        })
+                        .collect(),
+                )),
+                game_state,
+            })
+        }
+        external::ScreenState::ShopRoom {} => internal::floor::FloorState::Shop(import_shop(
+            game_state,
+            internal::shop::ShopScreenState::Entrance,
+        )),
+        external::ScreenState::ShopScreen(screen) => {
+            let mut shop = import_shop(game_state, internal::shop::ShopScreenState::InShop);
+            convert_shop(screen, &mut shop);
+            internal::floor::FloorState::Shop(shop)
+        }
+        external::ScreenState::Rest(rest) => {
+            internal::floor::FloorState::Rest(internal::floor::RestState {
+                screen_state: if rest.has_rested || rest.rest_options.is_empty() {
+                    internal::floor::RestScreenState::Proceed
+                } else {
+                    internal::floor::RestScreenState::IShouldRest
+                },
+                game_state,
+            })
+        }
+        external::ScreenState::CardReward(reward) => {
+            let mut rewards = import_rewards(vector![internal::core::Reward::CardChoice(
+                reward.cards.iter().map(import_offer).collect(),
+                None,
+                false
+            )]);
+            rewards.viewing_reward = Some(0);
+            internal::floor::FloorState::BattleRewards(internal::floor::BattleRewardsState {
+                boss: false,
+                game_state,
+                rewards,
+            })
+        }
+        external::ScreenState::CombatReward(rewards) => {
+            let fight_type = import_fight_type(&external.room_type);
+            internal::floor::FloorState::BattleRewards(internal::floor::BattleRewardsState {
+                boss: fight_type == internal_core::FightType::Boss,
+                game_state,
+                rewards: import_rewards(
+                    rewards
+                        .rewards
+                        .iter()
+                        .map(|reward| import_reward(reward, fight_type))
+                        .collect(),
+                ),
+            })
+        }
+        external::ScreenState::Grid(grid) => match external.room_type.as_str() {
+            "RestRoom" if grid.for_upgrade => {
+                internal::floor::FloorState::Rest(internal::floor::RestState {
+                    screen_state: internal::floor::RestScreenState::Smith,
+                    game_state,
+                })
+            }
+            "RestRoom" if grid.for_purge => {
+                internal::floor::FloorState::Rest(internal::floor::RestState {
+                    screen_state: internal::floor::RestScreenState::Toke,
+                    game_state,
+                })
+            }
+            "ShopRoom" if grid.for_purge => internal::floor::FloorState::Shop(import_shop(
+                game_state,
+                internal::shop::ShopScreenState::DeckChoose(internal_core::DeckOperation::Remove),
+            )),
+            // Only Neow can be told apart, since the game doesn't name the event behind a grid
+            _ if external.floor == 0 => {
+                let mut event = internal::event::EventState::by_name("Neow", game_state);
+                event.screen_state = Some(internal::event::EventScreenState::DeckChoose(
+                    import_grid_operation(grid)?,
+                    grid.num_cards as usize,
+                ));
+                internal::floor::FloorState::Event(event)
+            }
+            _ => {
+                return Err(
+                    format!("Unable to import a card grid in {}", external.room_type).into(),
+                )
+            }
+        },
+        external::ScreenState::Map(_)
+        | external::ScreenState::Complete {}
+        | external::ScreenState::None {} => internal::floor::FloorState::Map(game_state),
+        other => return Err(format!("Unable to import a {} screen", screen_name(other)).into()),
+    };
+
+    Ok(state)
+}
+
+fn import_grid_operation(
+    grid: &external::Grid,
+) -> Result<internal_core::DeckOperation, Box<dyn Error>> {
+    if grid.for_upgrade {
+        Ok(internal_core::DeckOperation::Upgrade)
+    } else if grid.for_transform {
+        Ok(internal_core::DeckOperation::Transform)
+    } else if grid.for_purge {
+        Ok(internal_core::DeckOperation::Remove)
+    } else {
+        Err("Unable to tell what a card grid is for".into())
+    }
+}
+
+fn screen_name(screen: &external::ScreenState) -> &'static str {
+    match screen {
+        external::ScreenState::None {} => "NONE",
+        external::ScreenState::Event(_) => "EVENT",
+        external::ScreenState::Chest(_) => "CHEST",
+        external::ScreenState::ShopRoom {} => "SHOP_ROOM",
+        external::ScreenState::Rest(_) => "REST",
+        external::ScreenState::CardReward(_) => "CARD_REWARD",
+        external::ScreenState::CombatReward(_) => "COMBAT_REWARD",
+        external::ScreenState::Map(_) => "MAP",
+        external::ScreenState::BossReward(_) => "BOSS_REWARD",
+        external::ScreenState::ShopScreen(_) => "SHOP_SCREEN",
+        external::ScreenState::Grid(_) => "GRID",
+        external::ScreenState::HandSelect(_) => "HAND_SELECT",
+        external::ScreenState::GameOver(_) => "GAME_OVER",
+        external::ScreenState::Complete {} => "COMPLETE",
+    }
+}
+
+fn import_game(
+    external: &external::GameState,
+    uuid_map: &mut HashMap<String, Uuid>,
+) -> Result<internal::game::GameState, Box<dyn Error>> {
+    // Starting from a new run keeps the counters the game doesn't report at sensible values
+    let mut game = internal::game::GameState::new(
+        import_class(&external.class)?,
+        external.ascension_level as u8,
+    );
+
+    game.act = external.act as u8;
+    game.gold = external.gold as u16;
+    game.hp = internal::core::HpRange {
+        amount: external.current_hp as u16,
+        max: external.max_hp as u16,
+    };
+    game.deck = HashMap::default();
+    for card in &external.deck {
+        let card = import_card(card, &game.deck, uuid_map);
+        game.deck.insert(card.uuid, card);
+    }
+    game.relics = external
+        .relics
+        .iter()
+        .map(|relic| import_relic(relic, uuid_map))
+        .collect();
+    game.seen_relics = game.relics.iter().map(|relic| relic.base).collect();
+    game.potions = external
+        .potions
+        .iter()
+        .map(|potion| match potion.name.as_str() {
+            "Potion Slot" => None,
+            name => Some(models::potions::by_name(name)),
+        })
+        .collect();
+    if external.act_boss.is_some() {
+        game.map = convert_map(external);
+    }
+    game.map.floor = external.floor as i8;
+    if let Some(keys) = &external.keys {
+        game.keys = Some(internal::floor::KeyState {
+            ruby: keys.ruby,
+            emerald: keys.emerald,
+            sapphire: keys.sapphire,
+        });
+    }
+
+    Ok(game)
+}
+
+fn import_battle(
+    external: &external::GameState,
+    combat: &external::CombatState,
+    game_state: internal::game::GameState,
+    uuid_map: &mut HashMap<String, Uuid>,
+) -> Result<internal::battle::BattleState, Box<dyn Error>> {
+    let mut cards = HashMap::default();
+    let mut piles: Vec<Vec<Uuid>> = Vec::new();
+    for pile in &[
+        &combat.draw_pile,
+        &combat.discard_pile,
+        &combat.exhaust_pile,
+        &combat.hand,
+    ] {
+        let mut uuids = Vec::new();
+        for card in pile.iter() {
+            let card = import_card(card, &cards, uuid_map);
+            uuids.push(card.uuid);
+            cards.insert(card.uuid, card);
+        }
+        piles.push(uuids);
+    }
+    let hand = piles.pop().unwrap();
+    let exhaust = piles.pop().unwrap();
+    let discard = piles.pop().unwrap();
+    let draw = piles.pop().unwrap();
+
+    let mut player = internal::core::Creature::player(internal::core::HpRange {
+        amount: combat.player.current_hp as u16,
+        max: combat.player.max_hp as u16,
+    });
+    player.block = combat.player.block as u16;
+    player.buffs = import_buffs(&combat.player.powers);
+
+    let mut monsters = HashMap::default();
+    for (position, monster) in combat.monsters.iter().enumerate() {
+        let monster = import_monster(monster, position, &monsters, uuid_map)?;
+        monsters.insert(monster.uuid, monster);
+    }
+
+    let mut orbs = Vector::new();
+    for orb in &combat.player.orbs {
+        if orb.name != "Orb Slot" {
+            orbs.push_back(internal::core::Orb {
+                base: import_orb_type(&orb.name)?,
+                n: orb.evoke_amount as u16,
+            });
+        }
+    }
+
+    let default_orb_slots = if game_state.class == internal_core::Class::Defect {
+        3
+    } else if game_state.has_relic(models::relics::PRISMATIC_SHARD) {
+        1
+    } else {
+        0
+    };
+
+    let base_energy = 3 + game_state
+        .relics
+        .iter()
+        .filter(|relic| relic.base.energy_relic)
+        .count() as u8;
+
+    Ok(internal::battle::BattleState {
+        deck_references: cards
+            .keys()
+            .filter(|uuid| game_state.deck.contains_key(uuid))
+            .map(|uuid| (*uuid, *uuid))
+            .collect(),
+        player,
+        draw: draw.into_iter().collect(),
+        draw_top_known: Vector::new(),
+        draw_bottom_known: Vector::new(),
+        draw_inserted: Vector::new(),
+        discard: discard.into_iter().collect(),
+        exhaust: exhaust.into_iter().collect(),
+        hand: hand.into_iter().collect(),
+        cards,
+        monsters,
+        orbs,
+        orb_slots: default_orb_slots.max(combat.player.orbs.len() as u8),
+        energy: combat.player.energy as u8,
+        base_energy,
+        stance: internal_core::Stance::None,
+        fight_type: import_fight_type(&external.room_type),
+        event_battle: None,
+        draw_visible: false,
+        discard_count: combat.cards_discarded_this_turn as u8,
+        play_count: 0,
+        hp_loss_count: 0,
+        power_count: 0,
+        last_card_played: None,
+        end_turn: false,
+        gold_recovered: 0,
+        skip_monsters: false,
+        wish: 0,
+        blizzard: 0,
+        stance_pot: false,
+        game_state,
+        card_choose: None,
+        battle_over: false,
+        skip_rewards: false,
+    })
+}
+
+// Reuses the uuid that an id was last seen with, unless it was already taken by another copy
+fn import_uuid<T>(
+    id: &str,
+    taken: &HashMap<Uuid, T>,
+    uuid_map: &mut HashMap<String, Uuid>,
+) -> Uuid {
+    match uuid_map.get(id) {
+        Some(uuid) if !taken.contains_key(uuid) => *uuid,
+        Some(_) => new_uuid(),
+        None => {
+            let uuid = new_uuid();
+            uuid_map.insert(id.to_string(), uuid);
+            uuid
+        }
+    }
+}
+
+fn import_card(
+    external: &external::Card,
+    taken: &HashMap<Uuid, internal::core::Card>,
+    uuid_map: &mut HashMap<String, Uuid>,
+) -> internal::core::Card {
+    let mut card = internal::core::Card::by_name(card_name(external));
+    for _ in 0..external.upgrades {
+        card.upgrade();
+    }
+    card.upgrades = external.upgrades as u8;
+    card.cost = import_cost(external.cost);
+    card.uuid = import_uuid(&external.id, taken, uuid_map);
+    card
+}
+
+fn import_offer(external: &external::Card) -> internal::core::CardOffer {
+    internal::core::CardOffer {
+        base: models::cards::by_name(card_name(external)),
+        upgraded: external.upgrades > 0,
+    }
+}
+
+fn import_relic(
+    external: &external::Relic,
+    uuid_map: &mut HashMap<String, Uuid>,
+) -> internal::core::Relic {
+    let mut relic = internal::core::Relic::by_name(&external.name);
+    relic.vars.x = external.counter as i16;
+    relic.uuid = *uuid_map
+        .entry(external.id.to_string())
+        .or_insert_with(new_uuid);
+    relic
+}
+
+fn import_buffs(external: &[external::Power]) -> Vec<internal::core::Buff> {
+    external
+        .iter()
+        .map(|power| internal::core::Buff::by_name(&power.name, power.amount as i16))
+        .collect()
+}
+
+fn import_monster(
+    external: &external::Monster,
+    position: usize,
+    taken: &HashMap<Uuid, internal::core::Monster>,
+    uuid_map: &mut HashMap<String, Uuid>,
+) -> Result<internal::core::Monster, Box<dyn Error>> {
+    let mut monster = internal::core::Monster::with_hp(&external.name, external.max_hp as u16);
+    monster.uuid = import_uuid(&external.id, taken, uuid_map);
+    monster.creature.monster = Some(MonsterReference {
+        base: monster.base,
+        uuid: monster.uuid,
+    });
+    monster.creature.hp.amount = external.current_hp as u16;
+    monster.creature.block = external.block as u16;
+    monster.creature.buffs = import_buffs(&external.powers);
+    monster.position = position;
+    monster.targetable = !external.is_gone;
+    monster.intent = import_intent(&external.intent);
+    Ok(monster)
+}
+
+fn import_shop(
+    game_state: internal::game::GameState,
+    screen_state: internal::shop::ShopScreenState,
+) -> internal::shop::ShopState {
+    internal::shop::ShopState {
+        generated: false,
+        updated: false,
+        cards: Vector::new(),
+        potions: Vector::new(),
+        relics: Vector::new(),
+        can_purge: true,
+        game_state,
+        screen_state,
+    }
+}
+
+fn import_rewards(rewards: Vector<internal::core::Reward>) -> internal::core::RewardState {
+    internal::core::RewardState {
+        rewards,
+        deck_operation: None,
+        viewing_reward: None,
+    }
+}
+
+fn import_reward(
+    external: &external::RewardType,
+    fight_type: internal_core::FightType,
+) -> internal::core::Reward {
+    match external {
+        // The offers are generated when the reward is taken
+        external::RewardType::Card => {
+            internal::core::Reward::CardChoice(Vector::new(), Some(fight_type), false)
+        }
+        external::RewardType::Gold { gold } | external::RewardType::StolenGold { gold } => {
+            internal::core::Reward::Gold(*gold as u16)
+        }
+        external::RewardType::Relic { relic } => {
+            internal::core::Reward::Relic(models::relics::by_name(&relic.name))
+        }
+        external::RewardType::Potion { potion } => {
+            internal::core::Reward::Potion(models::potions::by_name(&potion.name))
+        }
+        external::RewardType::EmeraldKey => internal::core::Reward::EmeraldKey,
+        external::RewardType::SapphireKey { link } => {
+            internal::core::Reward::SapphireLinkedRelic(models::relics::by_name(&link.name))
+        }
+    }
+}
+
+fn import_fight_type(room_type: &str) -> internal_core::FightType {
+    match room_type {
+        "MonsterRoomElite" => internal_core::FightType::Elite { burning: false },
+        "MonsterRoomBoss" => internal_core::FightType::Boss,
+        _ => internal_core::FightType::Common,
+    }
+}
+
+fn import_orb_type(name: &str) -> Result<internal_core::OrbType, Box<dyn Error>> {
+    match name {
+        "Lightning" => Ok(internal_core::OrbType::Lightning),
+        "Dark" => Ok(internal_core::OrbType::Dark),
+        "Frost" => Ok(internal_core::OrbType::Frost),
+        "Plasma" => Ok(internal_core::OrbType::Plasma),
+        _ => Err(format!("Unrecognized orb type: {}", name).into()),
+    }
+}
+
+fn import_chest(
+    external: &external::ChestType,
+) -> Result<internal_core::ChestType, Box<dyn Error>> {
+    match external {
+        external::ChestType::SmallChest => Ok(internal_core::ChestType::Small),
+        external::ChestType::MediumChest => Ok(internal_core::ChestType::Medium),
+        external::ChestType::LargeChest => Ok(internal_core::ChestType::Large),
+        external::ChestType::BossChest => Ok(internal_core::ChestType::Boss),
+        external::ChestType::Unknown => Err("Unrecognized type of chest".into()),
+    }
+}
+
+fn import_class(external: &external::PlayerClass) -> Result<internal_core::Class, Box<dyn Error>> {
+    match external {
+        external::PlayerClass::Ironclad => Ok(internal_core::Class::Ironclad),
+        external::PlayerClass::Silent => Ok(internal_core::Class::Silent),
+        external::PlayerClass::Defect => Ok(internal_core::Class::Defect),
+        external::PlayerClass::Watcher => Ok(internal_core::Class::Watcher),
+        external::PlayerClass::Other => Err("Unrecognized class".into()),
+    }
+}
+
+fn import_intent(external: &external::Intent) -> NewIntent {
+    match external {
+        external::Intent::Attack => NewIntent::Attack,
+        external::Intent::AttackBuff => NewIntent::AttackBuff,
+        external::Intent::AttackDebuff => NewIntent::AttackDebuff,
+        external::Intent::AttackDefend => NewIntent::AttackDefend,
+        external::Intent::Buff => NewIntent::Buff,
+        external::Intent::Debuff => NewIntent::Debuff,
+        external::Intent::StrongDebuff => NewIntent::StrongDebuff,
+        external::Intent::Defend => NewIntent::Defend,
+        external::Intent::DefendDebuff => NewIntent::DefendDebuff,
+        external::Intent::DefendBuff => NewIntent::DefendBuff,
+        external::Intent::Escape => NewIntent::Escape,
+        external::Intent::None => NewIntent::None,
+        external::Intent::Sleep => NewIntent::Sleep,
+        external::Intent::Stun => NewIntent::Stun,
+        // None of the simulated monsters use these, so they are as good as unknown
+        external::Intent::Unknown | external::Intent::Debug | external::Intent::Magic => {
+            NewIntent::Unknown
+        }
+    }
+}
+
 pub fn convert_shop(state: &external::ShopScreen, shop: &mut internal::shop::ShopState) {
     shop.generated = true;
     shop.updated = true;
@@ -123,7 +734,7 @@ pub fn convert_map(state: &external::GameState) -> internal::map::MapState {
             unknown_normal_count: 0,
             unknown_shop_count: 0,
             unknown_treasure_count: 0,
-            event_history: HashSet::new(),
+            event_history: HashSet::default(),
             last_shop: false,
         },
     }
@@ -150,10 +761,14 @@ pub fn convert_node(node: &external::MapNode) -> internal::map::MapNode {
 
 pub fn relics_match(
     external: &[external::Relic],
-    internal: &[internal::core::Relic],
+    internal: &Vector<internal::core::Relic>,
     uuid_map: &mut HashMap<String, Uuid>,
 ) -> bool {
-    sets_match(external, internal, uuid_map, relic_matches, |relic| {
+    let internal = internal
+        .iter()
+        .map(|relic| (relic.uuid, relic.clone()))
+        .collect();
+    sets_match(external, &internal, uuid_map, relic_matches, |relic| {
         relic.id.to_string()
     })
 }
@@ -167,7 +782,7 @@ pub fn battle_state_matches(
     internal: &internal::battle::BattleState,
     uuid_map: &mut HashMap<String, Uuid>,
 ) -> bool {
-    buffs_match(&external.player.powers, &internal.player.buffs, uuid_map)
+    buffs_match(&external.player.powers, &internal.player.buffs)
         && external.player.block as u16 == internal.player.block
         && cards_match(
             &external.hand,
@@ -288,18 +903,11 @@ pub fn floor_state_matches(
     }
 }
 
-fn chest_matches(external: &external::Chest, internal: internal_core::ChestType) -> bool {
-    internal
-        == match external.chest_type {
-            external::ChestType::SmallChest => internal_core::ChestType::Small,
-            external::ChestType::MediumChest => internal_core::ChestType::Medium,
-            external::ChestType::LargeChest => internal_core::ChestType::Large,
-            external::ChestType::BossChest => internal_core::ChestType::Boss,
-            _ => panic!("Unexpected type of chest"),
-        }
+pub fn chest_matches(external: &external::Chest, internal: internal_core::ChestType) -> bool {
+    import_chest(&external.chest_type).map_or(false, |a| a == internal)
 }
 
-fn rewards_match(
+pub fn rewards_match(
     external: &external::CombatRewards,
     internal: &Vector<internal::core::Reward>,
 ) -> bool {
@@ -336,7 +944,7 @@ fn rewards_match(
     })
 }
 
-fn events_match(external: &external::Event, internal: &internal::event::EventState) -> bool {
+pub fn events_match(external: &external::Event, internal: &internal::event::EventState) -> bool {
     external.event_name == internal.base.name
         && external.options.iter().all(|option| {
             internal
@@ -346,7 +954,7 @@ fn events_match(external: &external::Event, internal: &internal::event::EventSta
         })
 }
 
-fn orbs_match(
+pub fn orbs_match(
     external_map: &[external::OrbType],
     internal_map: &Vector<internal::core::Orb>,
 ) -> bool {
@@ -356,14 +964,7 @@ fn orbs_match(
 
     for (idx, external) in external_map.iter().enumerate() {
         let internal = &internal_map[idx];
-        if !(internal.base
-            == match external.name.as_str() {
-                "Lightning" => internal_core::OrbType::Lightning,
-                "Dark" => internal_core::OrbType::Dark,
-                "Frost" => internal_core::OrbType::Frost,
-                "Plasma" => internal_core::OrbType::Plasma,
-                _ => panic!("Unrecognized orb type"),
-            }
+        if !(import_orb_type(&external.name).map_or(false, |a| a == internal.base)
             && external.evoke_amount as u16 == internal.n)
         {
             return false;
@@ -384,7 +985,7 @@ fn monsters_match(
 
     for internal in internal_map.values() {
         let external = &external_map[internal.position];
-        if !(buffs_match(&external.powers, &internal.creature.buffs, uuid_map)
+        if !(buffs_match(&external.powers, &internal.creature.buffs)
             && external.current_hp as u16 == internal.creature.hp.amount
             && external.max_hp as u16 == internal.creature.hp.max
             && external.block as u16 == internal.creature.block
@@ -398,42 +999,17 @@ fn monsters_match(
     true
 }
 
-fn intent_matches(external: &external::Intent, internal: NewIntent) -> bool {
-    internal
-        == match external {
-            external::Intent::Attack => NewIntent::Attack,
-            external::Intent::AttackBuff => NewIntent::AttackBuff,
-            external::Intent::AttackDebuff => NewIntent::AttackDebuff,
-            external::Intent::AttackDefend => NewIntent::AttackDefend,
-            external::Intent::Buff => NewIntent::Buff,
-            external::Intent::Debuff => NewIntent::Debuff,
-            external::Intent::StrongDebuff => NewIntent::StrongDebuff,
-            external::Intent::Defend => NewIntent::Defend,
-            external::Intent::DefendDebuff => NewIntent::DefendDebuff,
-            external::Intent::DefendBuff => NewIntent::DefendBuff,
-            external::Intent::Escape => NewIntent::Escape,
-            external::Intent::None => NewIntent::None,
-            external::Intent::Sleep => NewIntent::Sleep,
-            external::Intent::Stun => NewIntent::Stun,
-            external::Intent::Unknown => NewIntent::Unknown,
-            external::Intent::Debug | external::Intent::Magic => {
-                panic!("Unrecognized intent: {:?}", external)
-            }
-        }
+pub fn intent_matches(external: &external::Intent, internal: NewIntent) -> bool {
+    internal == import_intent(external)
 }
 
-fn buffs_match(
-    external: &[external::Power],
-    internal: &HashMap<Uuid, internal::core::Buff>,
-    uuid_map: &mut HashMap<String, Uuid>,
-) -> bool {
-    sets_match(
-        external,
-        internal,
-        uuid_map,
-        |a, b| a.name == b.base.name && a.amount as i16 == b.vars.n,
-        |a| a.id.to_string(),
-    )
+fn buffs_match(external: &[external::Power], internal: &[internal::core::Buff]) -> bool {
+    external.len() == internal.len()
+        && external.iter().all(|power| {
+            internal
+                .iter()
+                .any(|buff| power.name == buff.base.name && power.amount as i16 == buff.vars.x)
+        })
 }
 
 fn potions_match(
@@ -475,7 +1051,7 @@ where
     }
 
     let mut remaining = Vec::new();
-    let mut used_uuids = HashMap::new();
+    let mut used_uuids = HashMap::default();
 
     for external_item in external {
         if let Some(uuid) = uuid_map.get(&id(external_item)) {
@@ -519,15 +1095,24 @@ fn cards_match(
     })
 }
 
-fn card_matches(external: &external::Card, internal: &internal::core::Card) -> bool {
-    let name = if external.name.ends_with('+') {
+// Upgraded cards are named with a trailing "+"
+fn card_name(external: &external::Card) -> &str {
+    if external.name.ends_with('+') {
         &external.name[0..external.name.len() - 1]
     } else {
         external.name.as_str()
-    };
-    name == internal.base.name
+    }
+}
+
+// The game reports X costs as -1 and unplayable cards as -2, where cards are given a cost of 0
+pub fn import_cost(cost: i32) -> u8 {
+    cost.max(0) as u8
+}
+
+fn card_matches(external: &external::Card, internal: &internal::core::Card) -> bool {
+    card_name(external) == internal.base.name
         && external.upgrades as u8 == internal.upgrades
-        && external.cost as u8 == internal.cost
+        && import_cost(external.cost) == internal.cost
 }
 
 fn card_offer_matches(
@@ -535,23 +1120,98 @@ fn card_offer_matches(
     internal: &internal::core::CardOffer,
     price: u16,
 ) -> bool {
-    let name = if external.name.ends_with('+') {
-        &external.name[0..external.name.len() - 1]
-    } else {
-        external.name.as_str()
-    };
-    name == internal.base.name
+    card_name(external) == internal.base.name
         && (external.upgrades > 0) == internal.upgraded
         && external.price.unwrap() as u16 == price
 }
 
-fn class_matches(external: &external::PlayerClass, internal: internal_core::Class) -> bool {
-    internal
-        == match external {
-            external::PlayerClass::Ironclad => internal_core::Class::Ironclad,
-            external::PlayerClass::Silent => internal_core::Class::Silent,
-            external::PlayerClass::Defect => internal_core::Class::Defect,
-            external::PlayerClass::Watcher => internal_core::Class::Watcher,
-            external::PlayerClass::Other => panic!("Unrecognized class"),
+pub fn class_matches(external: &external::PlayerClass, internal: internal_core::Class) -> bool {
+    import_class(external).map_or(false, |a| a == internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use im::vector;
+
+    use crate::comm::request::Request;
+    use crate::comm::simulator::export_state;
+    use crate::models::core::{Class, FightType, Stance};
+    use crate::state::battle::{BattleState, CardChoiceState};
+    use crate::state::floor::FloorState;
+    use crate::state::game::GameState;
+    use crate::state::probability::Probability;
+    use crate::state::HashMap;
+
+    #[test]
+    fn imports_exported_state() {
+        let state = FloorState::Map(GameState::new(Class::Silent, 11));
+        let external = export_state(&state, &None);
+        let mut uuid_map = HashMap::default();
+
+        let imported = super::import_state(&external, &FloorState::Menu, &mut uuid_map).unwrap();
+
+        assert!(super::state_matches(&external, &imported, &mut uuid_map));
+        assert_eq!(imported.game_state().deck.len(), 12);
+        assert_eq!(imported.game_state().potions.len(), 2);
+    }
+
+    // A hand as CommunicationMod sends it, with an X cost card and a curse
+    const COMBAT_REQUEST: &str = r#"{"available_commands":["play","end","key","click","wait","state"],"ready_for_command":true,"in_game":true,"game_state":{"choice_list":[],"screen_type":"NONE","screen_state":{},"seed":-4411683436484096574,"combat_state":{"draw_pile":[],"discard_pile":[],"exhaust_pile":[],"cards_discarded_this_turn":0,"times_damaged":0,"monsters":[{"is_gone":false,"move_hits":1,"move_base_damage":11,"last_move_id":null,"half_dead":false,"move_adjusted_damage":11,"max_hp":42,"intent":"ATTACK","second_last_move_id":null,"move_id":1,"name":"Jaw Worm","current_hp":42,"block":0,"id":"JawWorm","powers":[]}],"turn":1,"limbo":[],"hand":[{"exhausts":false,"is_playable":true,"cost":-1,"name":"Whirlwind","id":"Whirlwind","type":"ATTACK","ethereal":false,"uuid":"2f1a3c62-5d1e-4a3b-9b4e-0c8d7e6f5a41","upgrades":0,"rarity":"UNCOMMON","has_target":false},{"exhausts":false,"is_playable":false,"cost":-2,"name":"Ascender's Bane","id":"AscendersBane","type":"CURSE","ethereal":true,"uuid":"7c9e2b10-3f4d-4e8a-a1b2-5d6c7e8f9a03","upgrades":0,"rarity":"SPECIAL","has_target":false},{"exhausts":false,"is_playable":true,"cost":1,"name":"Strike","id":"Strike_R","type":"ATTACK","ethereal":false,"uuid":"b4d5e6f7-0a1b-4c2d-8e3f-9a0b1c2d3e4f","upgrades":0,"rarity":"BASIC","has_target":true}],"player":{"orbs":[],"current_hp":80,"block":0,"max_hp":80,"powers":[],"energy":3}},"deck":[],"relics":[{"name":"Burning Blood","id":"Burning Blood","counter":-1}],"max_hp":80,"act_boss":"The Guardian","gold":99,"action_phase":"WAITING_ON_USER","act":1,"screen_name":"NONE","room_phase":"COMBAT","is_screen_up":false,"potions":[{"requires_target":false,"can_use":false,"can_discard":false,"name":"Potion Slot","id":"Potion Slot"},{"requires_target":false,"can_use":false,"can_discard":false,"name":"Potion Slot","id":"Potion Slot"}],"current_hp":80,"floor":1,"ascension_level":10,"class":"IRONCLAD","map":[],"room_type":"MonsterRoom"}}"#;
+
+    #[test]
+    fn imports_game_costs() {
+        let request: Request = serde_json::from_str(COMBAT_REQUEST).unwrap();
+        let mut uuid_map = HashMap::default();
+
+        let imported =
+            super::import_state(&request.game_state, &FloorState::Menu, &mut uuid_map).unwrap();
+
+        assert!(super::state_matches(
+            &request.game_state,
+            &imported,
+            &mut uuid_map
+        ));
+        match imported {
+            FloorState::Battle(battle) => {
+                let costs: Vec<u8> = battle.hand.iter().map(|a| battle.cards[a].cost).collect();
+                assert_eq!(costs, vec![0, 0, 1]);
+            }
+            other => panic!("Expected a battle, got {:?}", other),
         }
+    }
+
+    #[test]
+    fn imports_battle() {
+        let mut battle = BattleState::new(
+            GameState::new(Class::Watcher, 0),
+            &[String::from("Jaw Worm")],
+            FightType::Common,
+            &mut Probability::new(),
+        );
+        battle.stance = Stance::Wrath;
+        battle.card_choose = Some(CardChoiceState {
+            choices: battle.hand().collect(),
+            count_range: 1..2,
+            then: vector![],
+            scry: false,
+        });
+        let state = FloorState::Battle(battle.clone());
+        let external = export_state(&state, &None);
+        let mut uuid_map = HashMap::default();
+
+        let imported = super::import_state(&external, &state, &mut uuid_map).unwrap();
+
+        assert!(super::state_matches(&external, &imported, &mut uuid_map));
+        match imported {
+            FloorState::Battle(imported) => {
+                assert_eq!(imported.stance, Stance::Wrath);
+                assert_eq!(imported.monsters.len(), 1);
+                assert_eq!(imported.hand.len(), battle.hand.len());
+                let card_choose = imported.card_choose.unwrap();
+                assert_eq!(card_choose.choices.len(), battle.hand.len());
+                assert_eq!(card_choose.count_range, 1..2);
+            }
+            other => panic!("Expected a battle, got {:?}", other),
+        }
+    }
 }
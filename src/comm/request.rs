@@ -1,6 +1,6 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum PlayerClass {
     Ironclad,
@@ -10,7 +10,7 @@ pub enum PlayerClass {
     Other,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum CardType {
     Attack,
@@ -20,7 +20,7 @@ pub enum CardType {
     Curse,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum CardRarity {
     Basic,
@@ -31,7 +31,7 @@ pub enum CardRarity {
     Curse,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum RoomPhase {
     Combat,
@@ -40,7 +40,7 @@ pub enum RoomPhase {
     Incomplete,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EventOption {
     pub text: String,
     pub label: String,
@@ -48,7 +48,7 @@ pub struct EventOption {
     pub choice_index: Option<i32>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Event {
     pub event_name: String,
     pub event_id: String,
@@ -56,33 +56,33 @@ pub struct Event {
     pub options: Vec<EventOption>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Chest {
     pub chest_type: ChestType,
     pub chest_open: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Rest {
     pub has_rested: bool,
     pub rest_options: Vec<RestOption>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CardReward {
     pub cards: Vec<Card>,
     pub bowl_available: bool,
     pub skip_available: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MapChoice {
     pub current_node: Option<MapNode>,
     pub next_nodes: Option<Vec<MapNode>>,
     pub boss_available: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ShopScreen {
     pub cards: Vec<Card>,
     pub relics: Vec<Relic>,
@@ -91,7 +91,7 @@ pub struct ShopScreen {
     pub purge_cost: i32,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Grid {
     pub cards: Vec<Card>,
     pub selected_cards: Vec<Card>,
@@ -104,7 +104,7 @@ pub struct Grid {
     pub for_purge: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HandSelect {
     pub cards: Vec<Card>,
     pub selected: Vec<Card>,
@@ -112,13 +112,13 @@ pub struct HandSelect {
     pub can_pick_zero: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GameOver {
     pub score: i32,
     pub victory: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(
     tag = "screen_type",
     content = "screen_state",
@@ -141,12 +141,12 @@ pub enum ScreenState {
     Complete {},
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CombatRewards {
     pub rewards: Vec<RewardType>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde()]
 pub enum ChestType {
     SmallChest,
@@ -156,7 +156,7 @@ pub enum ChestType {
     Unknown,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "reward_type", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum RewardType {
     Card,
@@ -168,7 +168,7 @@ pub enum RewardType {
     SapphireKey { link: Relic },
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum RestOption {
     Dig,
@@ -179,7 +179,7 @@ pub enum RestOption {
     Toke,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Request {
     pub error: Option<String>,
     pub ready_for_command: bool,
@@ -190,7 +190,7 @@ pub struct Request {
     pub available_commands: Vec<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GameState {
     pub current_action: Option<String>,
     pub current_hp: i32,
@@ -212,12 +212,21 @@ pub struct GameState {
     pub room_type: String,
     pub combat_state: Option<CombatState>,
     #[serde(default)]
+    pub keys: Option<Keys>,
+    #[serde(default)]
     pub choice_list: Vec<String>,
     #[serde(flatten)]
     pub screen_state: ScreenState,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keys {
+    pub ruby: bool,
+    pub emerald: bool,
+    pub sapphire: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CombatState {
     pub player: Player,
     pub monsters: Vec<Monster>,
@@ -233,7 +242,7 @@ pub struct CombatState {
     pub cards_discarded_this_turn: i32,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Intent {
     Attack,
@@ -255,7 +264,7 @@ pub enum Intent {
     Unknown,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Monster {
     pub max_hp: i32,
     pub current_hp: i32,
@@ -277,7 +286,7 @@ pub struct Monster {
     pub move_hits: i32,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Player {
     pub max_hp: i32,
     pub current_hp: i32,
@@ -287,7 +296,7 @@ pub struct Player {
     pub orbs: Vec<OrbType>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct OrbType {
     pub name: String,
     pub orb_id: String,
@@ -295,7 +304,7 @@ pub struct OrbType {
     pub passive_amount: i32,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Power {
     pub id: String,
     pub name: String,
@@ -307,7 +316,7 @@ pub struct Power {
     pub card: Option<Card>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Relic {
     pub id: String,
     pub name: String,
@@ -315,7 +324,7 @@ pub struct Relic {
     pub price: Option<i32>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Card {
     pub id: String,
     pub name: String,
@@ -334,7 +343,7 @@ pub struct Card {
     pub exhausts: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MapNode {
     pub x: i32,
     pub y: i32,
@@ -344,7 +353,7 @@ pub struct MapNode {
     pub children: Vec<MapNode>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Potion {
     pub id: String,
     pub name: String,
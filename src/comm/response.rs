@@ -1,10 +1,10 @@
-use im::HashMap;
 use uuid::Uuid;
 
 use crate::comm::request::*;
 use crate::models::choices::Choice;
 use crate::spireai::references::{CardReference, MonsterReference};
 use crate::state::game::DeckCard;
+use crate::state::HashMap;
 use std::iter::once;
 
 pub fn serialize_response(response: &Response, state: &Option<GameState>) -> String {
@@ -92,13 +92,24 @@ pub fn decompose_choice(
         Choice::End => vec![Response::Simple(String::from("END"))],
         Choice::EnterShop => vec![Response::Simple(String::from("CHOOSE 0"))],
         Choice::Event(name) => vec![Response::Choose(name)],
+        // The shop has to be closed before the room can be left
+        Choice::Proceed if in_shop(request) => vec![
+            Response::Simple(String::from("LEAVE")),
+            Response::Simple(String::from("PROCEED")),
+        ],
         Choice::Proceed => vec![Response::Simple(String::from("PROCEED"))],
         Choice::State => vec![Response::Simple(String::from("STATE"))],
         Choice::Skip => vec![Response::Simple(String::from("SKIP"))],
         Choice::SingingBowl => vec![Response::Simple(String::from("SINGING_BOWL"))],
-        Choice::BuyCard(card) => vec![Response::ChooseIdx(card)],
-        Choice::BuyPotion(potion) => vec![Response::ChooseIdx(potion)],
-        Choice::BuyRelic(relic) => vec![Response::ChooseIdx(relic)],
+        Choice::BuyCard(card) => vec![Response::Choose(
+            get_shop_screen(request).cards[card].name.clone(),
+        )],
+        Choice::BuyPotion(potion) => vec![Response::Choose(
+            get_shop_screen(request).potions[potion].name.clone(),
+        )],
+        Choice::BuyRelic(relic) => vec![Response::Choose(
+            get_shop_screen(request).relics[relic].name.clone(),
+        )],
         Choice::BuyRemoveCard(card) => vec![
             Response::Choose(String::from("purge")),
             Response::CardInDeck(get_card_position_in_deck(card, request, uuid_map)),
@@ -168,6 +179,20 @@ fn uuid_to_id(uuid: Uuid, uuid_map: &HashMap<String, Uuid>) -> &String {
     uuid_map.iter().find(|(_, id)| **id == uuid).unwrap().0
 }
 
+// Shop items are chosen by name, since the game only lists the ones the player can afford
+fn get_shop_screen(request: &Request) -> &ShopScreen {
+    match &request.game_state.as_ref().unwrap().screen_state {
+        ScreenState::ShopScreen(shop) => shop,
+        _ => panic!("Expected a ShopScreen when buying"),
+    }
+}
+
+fn in_shop(request: &Request) -> bool {
+    request.game_state.as_ref().map_or(false, |a| {
+        matches!(a.screen_state, ScreenState::ShopScreen(_))
+    })
+}
+
 fn get_monster_position(
     monster: MonsterReference,
     request: &Request,
@@ -0,0 +1,1113 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use itertools::Itertools;
+use uuid::Uuid;
+
+use crate::comm::request as external;
+use crate::comm::response;
+use crate::models::choices::Choice;
+use crate::models::core::{
+    Amount, CardType, ChestType, Class, Condition, DeckOperation, FightType, OrbType, Rarity,
+};
+use crate::models::monsters::Intent;
+use crate::models::{cards::BaseCard, potions::BasePotion, relics, relics::BaseRelic};
+use crate::spireai::{enumerator, predictor};
+use crate::state::battle::BattleState;
+use crate::state::core::{Card, CardOffer, Creature, Monster, Orb, Relic, Reward, RewardState};
+use crate::state::event::EventScreenState;
+use crate::state::floor::{FloorState, GamePossibility, RestScreenState};
+use crate::state::game::GameState;
+use crate::state::map::{MapNode, MapNodeIcon, MapState};
+use crate::state::probability::Probability;
+use crate::state::shop::{ShopScreenState, ShopState};
+use crate::state::HashMap;
+
+// Stands in for CommunicationMod: commands are applied to a FloorState with the predictor,
+// and the result is exported in the shape the game would send.
+pub struct Simulator {
+    state: FloorState,
+    last_game: Option<GameState>, // GameOver does not carry a game state of its own
+    pending: Vec<String>,         // Commands received for a choice that needs more than one
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self {
+            state: FloorState::Menu,
+            last_game: None,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn handle(&mut self, line: &str) -> external::Request {
+        let command = normalize(line);
+        let mut words = command.split(' ');
+        match words.next().unwrap_or_default() {
+            "READY" | "STATE" => self.request(),
+            "START" => {
+                if !matches!(self.state, FloorState::Menu) {
+                    return self.error(String::from("A run is already in progress"));
+                }
+
+                match parse_start(words) {
+                    Some(choice) => self.apply(choice),
+                    None => self.error(format!("Invalid command: {}", command)),
+                }
+            }
+            _ => self.advance(command),
+        }
+    }
+
+    fn advance(&mut self, command: String) -> external::Request {
+        let game_state = export_state(&self.state, &self.last_game);
+        let commands = self.commands(&game_state);
+        self.pending.push(command);
+
+        if let Some((choice, _)) = commands
+            .iter()
+            .find(|(_, sequence)| sequence == &self.pending)
+        {
+            self.pending.clear();
+            return self.apply(choice.clone());
+        }
+
+        if commands
+            .iter()
+            .any(|(_, sequence)| sequence.starts_with(&self.pending))
+        {
+            return self.request();
+        }
+
+        let command = self.pending.pop().unwrap();
+        self.error(format!("Invalid command: {}", command))
+    }
+
+    fn apply(&mut self, choice: Choice) -> external::Request {
+        if let FloorState::GameOver(..) = self.state {
+            // Dismissing the death screen returns to the main menu
+            self.state = FloorState::Menu;
+            return self.request();
+        }
+
+        let mut possibility = GamePossibility {
+            state: self.state.clone(),
+            probability: Probability::new(),
+        };
+
+        // Parts of the predictor are unfinished, so a failed prediction is reported like a
+        // command the game rejected instead of taking the simulator down
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            predictor::predict_outcome(choice.clone(), &mut possibility)
+        }));
+
+        match outcome {
+            Ok(()) => {
+                if !matches!(self.state, FloorState::Menu) {
+                    self.last_game = Some(self.state.game_state().clone());
+                }
+                self.state = possibility.state;
+                self.request()
+            }
+            Err(cause) => {
+                let message = cause
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| cause.downcast_ref::<&str>().map(|a| a.to_string()))
+                    .unwrap_or_default();
+                self.error(format!("Unable to simulate {:?}: {}", choice, message))
+            }
+        }
+    }
+
+    // Every choice available in the current state, along with the commands that make it
+    fn commands(&self, game_state: &Option<external::GameState>) -> Vec<(Choice, Vec<String>)> {
+        match self.state {
+            FloorState::Menu => return vec![], // START is parsed on its own
+            FloorState::GameOver(..) => {
+                return vec![(Choice::Proceed, vec![String::from("PROCEED")])]
+            }
+            _ => {}
+        }
+
+        let request = external::Request {
+            error: None,
+            ready_for_command: true,
+            in_game: true,
+            game_state: game_state.clone(),
+            available_commands: vec![],
+        };
+        let uuid_map = uuid_map(&self.state);
+
+        enumerator::all_choices(&self.state)
+            .into_iter()
+            // The purge grid only opens once "purge" has been chosen
+            .filter(|choice| !matches!(choice, Choice::BuyRemoveCard(_)))
+            .map(|choice| {
+                let sequence = response::decompose_choice(choice.clone(), &request, &uuid_map)
+                    .iter()
+                    .map(|a| normalize(&response::serialize_response(a, game_state)))
+                    .collect();
+                (choice, sequence)
+            })
+            .collect()
+    }
+
+    fn request(&self) -> external::Request {
+        let game_state = export_state(&self.state, &self.last_game);
+        let commands = self.commands(&game_state);
+
+        let mut available_commands: Vec<String> = commands
+            .iter()
+            .filter(|(_, sequence)| sequence.starts_with(&self.pending))
+            .filter_map(|(_, sequence)| sequence.get(self.pending.len()))
+            .map(|command| command.split(' ').next().unwrap().to_ascii_lowercase())
+            .unique()
+            .collect();
+
+        if let FloorState::Menu = self.state {
+            available_commands.push(String::from("start"));
+        }
+        available_commands.push(String::from("state"));
+
+        external::Request {
+            error: None,
+            ready_for_command: true,
+            in_game: game_state.is_some(),
+            game_state,
+            available_commands,
+        }
+    }
+
+    fn error(&self, message: String) -> external::Request {
+        external::Request {
+            error: Some(message),
+            ready_for_command: true,
+            in_game: !matches!(self.state, FloorState::Menu),
+            game_state: None,
+            available_commands: vec![],
+        }
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn normalize(command: &str) -> String {
+    command
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_ascii_uppercase()
+}
+
+fn parse_start<'a>(mut args: impl Iterator<Item = &'a str>) -> Option<Choice> {
+    let player_class = match args.next()? {
+        "IRONCLAD" => Class::Ironclad,
+        "SILENT" | "THE_SILENT" => Class::Silent,
+        "DEFECT" => Class::Defect,
+        "WATCHER" => Class::Watcher,
+        _ => return None,
+    };
+
+    let ascension = match args.next() {
+        Some(ascension) => Some(ascension.parse().ok()?),
+        None => None,
+    };
+
+    Some(Choice::Start {
+        player_class,
+        ascension,
+    })
+}
+
+// Exported ids are the internal uuids, so every id maps back to itself
+fn uuid_map(state: &FloorState) -> HashMap<String, Uuid> {
+    let mut uuids: Vec<Uuid> = state.game_state().deck.keys().copied().collect();
+    if let FloorState::Battle(battle) = state {
+        uuids.extend(battle.cards.keys());
+        uuids.extend(battle.monsters.keys());
+    }
+
+    uuids.into_iter().map(|a| (a.to_string(), a)).collect()
+}
+
+pub fn export_state(
+    state: &FloorState,
+    last_game: &Option<GameState>,
+) -> Option<external::GameState> {
+    let game = match state {
+        FloorState::Menu => return None,
+        FloorState::GameOver(..) => last_game.as_ref()?,
+        _ => state.game_state(),
+    };
+
+    let hp = match state {
+        FloorState::Battle(battle) => battle.player.hp,
+        _ => game.hp,
+    };
+
+    let (screen_state, choice_list) = export_screen(state, game);
+
+    Some(external::GameState {
+        current_action: None,
+        current_hp: hp.amount as i32,
+        max_hp: hp.max as i32,
+        floor: game.map.floor as i32,
+        act: game.act as i32,
+        gold: game.gold as i32,
+        seed: 0,
+        class: export_class(game.class),
+        ascension_level: game.asc as i32,
+        relics: game.relics.iter().map(export_relic).collect(),
+        deck: export_deck(game),
+        map: export_map(&game.map),
+        potions: game.potions.iter().map(export_potion).collect(),
+        act_boss: Some(game.map.boss.to_string()),
+        is_screen_up: false,
+        room_phase: match state {
+            FloorState::Battle(_) => external::RoomPhase::Combat,
+            FloorState::Event(_) => external::RoomPhase::Event,
+            _ => external::RoomPhase::Complete,
+        },
+        room_type: String::from(room_type(state)),
+        combat_state: match state {
+            FloorState::Battle(battle) => Some(export_battle(battle)),
+            _ => None,
+        },
+        keys: game.keys.map(|keys| external::Keys {
+            ruby: keys.ruby,
+            emerald: keys.emerald,
+            sapphire: keys.sapphire,
+        }),
+        choice_list,
+        screen_state,
+    })
+}
+
+fn room_type(state: &FloorState) -> &'static str {
+    match state {
+        FloorState::Battle(battle) => match battle.fight_type {
+            FightType::Common => "MonsterRoom",
+            FightType::Elite { .. } => "MonsterRoomElite",
+            FightType::Boss => "MonsterRoomBoss",
+        },
+        FloorState::BattleRewards(_) | FloorState::GameOver(..) => "MonsterRoom",
+        FloorState::Chest(chest) => {
+            if chest.chest == ChestType::Boss {
+                "TreasureRoomBoss"
+            } else {
+                "TreasureRoom"
+            }
+        }
+        FloorState::Event(_) => "EventRoom",
+        FloorState::Rest(_) => "RestRoom",
+        FloorState::Shop(_) => "ShopRoom",
+        FloorState::Map(_) => "MapRoom",
+        FloorState::Menu => panic!("No room in Menu"),
+    }
+}
+
+fn export_screen(state: &FloorState, game: &GameState) -> (external::ScreenState, Vec<String>) {
+    match state {
+        FloorState::Battle(battle) => export_battle_screen(battle),
+        FloorState::BattleRewards(rewards) => export_rewards(&rewards.rewards, game),
+        FloorState::Chest(chest) => match &chest.rewards {
+            None => (
+                external::ScreenState::Chest(external::Chest {
+                    chest_type: export_chest(chest.chest),
+                    chest_open: false,
+                }),
+                vec![String::from("open")],
+            ),
+            Some(rewards) => {
+                if chest.chest == ChestType::Boss {
+                    let relics: Vec<external::Relic> = rewards
+                        .rewards
+                        .iter()
+                        .map(|reward| match reward {
+                            Reward::Relic(relic) => export_relic_offer(relic, None),
+                            _ => panic!("Unexpected non-relic reward in boss chest"),
+                        })
+                        .collect();
+                    let choice_list = names(relics.iter().map(|a| a.name.as_str()));
+                    (external::ScreenState::BossReward(relics), choice_list)
+                } else {
+                    export_rewards(rewards, game)
+                }
+            }
+        },
+        FloorState::Event(event) => match &event.screen_state {
+            Some(EventScreenState::Rewards(rewards)) => export_rewards(rewards, game),
+            Some(EventScreenState::DeckChoose(operation, count)) => {
+                export_grid(game, *operation, *count)
+            }
+            None => (
+                external::ScreenState::Event(external::Event {
+                    event_name: event.base.name.to_string(),
+                    event_id: event.base.name.to_string(),
+                    body_text: String::new(),
+                    options: event
+                        .available_choices
+                        .iter()
+                        .enumerate()
+                        .map(|(index, name)| external::EventOption {
+                            text: name.to_string(),
+                            label: name.to_string(),
+                            disabled: false,
+                            choice_index: Some(index as i32),
+                        })
+                        .collect(),
+                }),
+                names(event.available_choices.iter().map(|a| a.as_str())),
+            ),
+        },
+        FloorState::GameOver(victory, _) => (
+            external::ScreenState::GameOver(external::GameOver {
+                score: 0,
+                victory: *victory,
+            }),
+            vec![],
+        ),
+        FloorState::Map(game) => export_map_screen(state, &game.map),
+        FloorState::Rest(rest) => match &rest.screen_state {
+            RestScreenState::IShouldRest => {
+                let options: Vec<(external::RestOption, &str)> = enumerator::all_choices(state)
+                    .into_iter()
+                    .filter_map(|choice| match choice {
+                        Choice::Rest => Some((external::RestOption::Rest, "rest")),
+                        Choice::Smith => Some((external::RestOption::Smith, "smith")),
+                        Choice::Lift => Some((external::RestOption::Lift, "lift")),
+                        Choice::Dig => Some((external::RestOption::Dig, "dig")),
+                        Choice::Recall => Some((external::RestOption::Recall, "recall")),
+                        Choice::Toke => Some((external::RestOption::Toke, "toke")),
+                        _ => None,
+                    })
+                    .collect();
+
+                (
+                    external::ScreenState::Rest(external::Rest {
+                        has_rested: false,
+                        rest_options: options.iter().map(|a| a.0.clone()).collect(),
+                    }),
+                    names(options.iter().map(|a| a.1)),
+                )
+            }
+            RestScreenState::Toke => export_grid(game, DeckOperation::Remove, 1),
+            RestScreenState::Smith => export_grid(game, DeckOperation::Upgrade, 1),
+            RestScreenState::DeckSelect(operation) => export_grid(game, *operation, 1),
+            RestScreenState::DreamCatch(offers) => export_card_reward(offers, game),
+            RestScreenState::Dig(rewards) => export_rewards(rewards, game),
+            RestScreenState::Proceed => (
+                external::ScreenState::Rest(external::Rest {
+                    has_rested: true,
+                    rest_options: vec![],
+                }),
+                vec![],
+            ),
+        },
+        FloorState::Shop(shop) => match &shop.screen_state {
+            ShopScreenState::Entrance => (
+                external::ScreenState::ShopRoom {},
+                vec![String::from("shop")],
+            ),
+            ShopScreenState::DeckChoose(operation) => export_grid(game, *operation, 1),
+            ShopScreenState::Reward(rewards) => export_rewards(rewards, game),
+            ShopScreenState::InShop => export_shop(shop),
+        },
+        FloorState::Menu => panic!("No screen in Menu"),
+    }
+}
+
+fn names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    names.map(|a| a.to_ascii_lowercase()).collect()
+}
+
+fn export_battle_screen(battle: &BattleState) -> (external::ScreenState, Vec<String>) {
+    if let Some(card_choose) = &battle.card_choose {
+        let cards = export_card_choices(battle);
+        let choice_list = names(cards.iter().map(|a| a.name.as_str()));
+        (
+            external::ScreenState::Grid(external::Grid {
+                cards,
+                selected_cards: vec![],
+                num_cards: card_choose.count_range.end as i32 - 1,
+                any_number: card_choose.count_range.start == 0,
+                confirm_up: card_choose.count_range.start == 0,
+                for_upgrade: false,
+                for_transform: false,
+                for_purge: false,
+            }),
+            choice_list,
+        )
+    } else if battle.wish > 0 {
+        (
+            external::ScreenState::None {},
+            names(["plated armor", "strength", "gold"].iter().copied()),
+        )
+    } else if battle.stance_pot {
+        (
+            external::ScreenState::None {},
+            names(["calm", "wrath"].iter().copied()),
+        )
+    } else {
+        (external::ScreenState::None {}, vec![])
+    }
+}
+
+fn export_rewards(rewards: &RewardState, game: &GameState) -> (external::ScreenState, Vec<String>) {
+    if let Some(index) = rewards.viewing_reward {
+        match &rewards.rewards[index] {
+            Reward::CardChoice(offers, _, _) => export_card_reward(offers, game),
+            _ => panic!("Viewing a reward that is not a card choice!"),
+        }
+    } else {
+        let linked_relic = rewards.rewards.iter().find_map(|reward| match reward {
+            Reward::SapphireLinkedRelic(relic) => Some(*relic),
+            _ => None,
+        });
+
+        let exported = rewards
+            .rewards
+            .iter()
+            .map(|reward| match reward {
+                Reward::CardChoice(..) => external::RewardType::Card,
+                Reward::Gold(gold) => external::RewardType::Gold { gold: *gold as i32 },
+                Reward::Relic(relic) => external::RewardType::Relic {
+                    relic: export_relic_offer(relic, None),
+                },
+                Reward::Potion(potion) => external::RewardType::Potion {
+                    potion: export_potion_offer(potion, None),
+                },
+                Reward::EmeraldKey => external::RewardType::EmeraldKey,
+                Reward::SapphireKey => external::RewardType::SapphireKey {
+                    link: export_relic_offer(
+                        linked_relic.expect("Sapphire key without a linked relic"),
+                        None,
+                    ),
+                },
+                Reward::SapphireLinkedRelic(relic) => external::RewardType::SapphireKey {
+                    link: export_relic_offer(relic, None),
+                },
+            })
+            .collect();
+
+        let choice_list = names(rewards.rewards.iter().map(|reward| match reward {
+            Reward::CardChoice(..) => "card",
+            Reward::Gold(_) => "gold",
+            Reward::Relic(_) | Reward::SapphireLinkedRelic(_) => "relic",
+            Reward::Potion(_) => "potion",
+            Reward::EmeraldKey => "emerald_key",
+            Reward::SapphireKey => "sapphire_key",
+        }));
+
+        (
+            external::ScreenState::CombatReward(external::CombatRewards { rewards: exported }),
+            choice_list,
+        )
+    }
+}
+
+fn export_card_reward(
+    offers: &im::Vector<CardOffer>,
+    game: &GameState,
+) -> (external::ScreenState, Vec<String>) {
+    let cards: Vec<external::Card> = offers.iter().map(|a| export_offer(a, None)).collect();
+    let choice_list = names(offers.iter().map(|a| a.base.name.as_str()));
+    (
+        external::ScreenState::CardReward(external::CardReward {
+            cards,
+            bowl_available: game.has_relic(relics::SINGING_BOWL),
+            skip_available: true,
+        }),
+        choice_list,
+    )
+}
+
+// The whole deck is shown, since cards are picked out of the grid by id
+fn export_grid(
+    game: &GameState,
+    operation: DeckOperation,
+    count: usize,
+) -> (external::ScreenState, Vec<String>) {
+    let cards = export_deck(game);
+    let choice_list = names(cards.iter().map(|a| a.name.as_str()));
+    (
+        external::ScreenState::Grid(external::Grid {
+            cards,
+            selected_cards: vec![],
+            num_cards: count as i32,
+            any_number: false,
+            confirm_up: false,
+            for_upgrade: operation == DeckOperation::Upgrade,
+            for_transform: matches!(
+                operation,
+                DeckOperation::Transform | DeckOperation::TransformUpgrade
+            ),
+            for_purge: operation == DeckOperation::Remove,
+        }),
+        choice_list,
+    )
+}
+
+fn export_shop(shop: &ShopState) -> (external::ScreenState, Vec<String>) {
+    let screen = external::ShopScreen {
+        cards: shop
+            .cards
+            .iter()
+            .map(|(offer, price)| export_offer(offer, Some(*price)))
+            .collect(),
+        relics: shop
+            .relics
+            .iter()
+            .map(|(relic, price)| export_relic_offer(relic, Some(*price)))
+            .collect(),
+        potions: shop
+            .potions
+            .iter()
+            .map(|(potion, price)| export_potion_offer(potion, Some(*price)))
+            .collect(),
+        purge_available: shop.can_purge,
+        purge_cost: shop.purge_cost() as i32,
+    };
+
+    // Like the game, purge comes first and only what the player can afford is listed
+    let gold = shop.game_state.gold as i32;
+    let affordable = |price: Option<i32>| price.unwrap_or(0) <= gold;
+    let mut choice_list = Vec::new();
+    if screen.purge_available && screen.purge_cost <= gold {
+        choice_list.push(String::from("purge"));
+    }
+    choice_list.extend(names(
+        screen
+            .cards
+            .iter()
+            .filter(|a| affordable(a.price))
+            .map(|a| a.name.as_str())
+            .chain(
+                screen
+                    .relics
+                    .iter()
+                    .filter(|a| affordable(a.price))
+                    .map(|a| a.name.as_str()),
+            )
+            .chain(
+                screen
+                    .potions
+                    .iter()
+                    .filter(|a| affordable(a.price))
+                    .map(|a| a.name.as_str()),
+            ),
+    ));
+
+    (external::ScreenState::ShopScreen(screen), choice_list)
+}
+
+fn export_map_screen(state: &FloorState, map: &MapState) -> (external::ScreenState, Vec<String>) {
+    let current_node = map.index.and_then(|index| map.nodes[index]);
+    let next_row = current_node.map_or(0, |node| node.y as usize + 1);
+    let next_xs: Vec<u8> = enumerator::all_choices(state)
+        .into_iter()
+        .filter_map(|choice| match choice {
+            Choice::NavigateToNode(x) => Some(x),
+            _ => None,
+        })
+        .collect();
+
+    (
+        external::ScreenState::Map(external::MapChoice {
+            current_node: current_node.map(|node| export_node(map, &node, false)),
+            next_nodes: Some(
+                next_xs
+                    .iter()
+                    .filter_map(|x| map.nodes.get(next_row * 7 + *x as usize).copied().flatten())
+                    .map(|node| export_node(map, &node, false))
+                    .collect(),
+            ),
+            boss_available: current_node.map_or(false, |node| node.is_top()),
+        }),
+        next_xs.iter().map(|x| format!("x={}", x)).collect(),
+    )
+}
+
+fn export_map(map: &MapState) -> Vec<external::MapNode> {
+    map.nodes
+        .iter()
+        .flatten()
+        .map(|node| export_node(map, node, true))
+        .collect()
+}
+
+fn export_node(map: &MapState, node: &MapNode, with_children: bool) -> external::MapNode {
+    let children = if with_children {
+        [(node.left, -1), (node.up, 0), (node.right, 1)]
+            .iter()
+            .filter(|(exists, _)| *exists)
+            .filter_map(|(_, offset)| {
+                let x = node.x as i32 + offset;
+                map.nodes[(node.y as i32 + 1) as usize * 7 + x as usize]
+            })
+            .map(|child| export_node(map, &child, false))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    external::MapNode {
+        x: node.x as i32,
+        y: node.y as i32,
+        symbol: match node.icon {
+            MapNodeIcon::Monster => 'M',
+            MapNodeIcon::Question => '?',
+            MapNodeIcon::Shop => '$',
+            MapNodeIcon::Campfire => 'R',
+            MapNodeIcon::Chest => 'T',
+            MapNodeIcon::Elite | MapNodeIcon::BurningElite => 'E',
+        },
+        children,
+    }
+}
+
+fn export_battle(battle: &BattleState) -> external::CombatState {
+    external::CombatState {
+        player: external::Player {
+            max_hp: battle.player.hp.max as i32,
+            current_hp: battle.player.hp.amount as i32,
+            block: battle.player.block as i32,
+            powers: export_powers(&battle.player),
+            energy: battle.energy as i32,
+            orbs: battle.orbs.iter().map(export_orb).collect(),
+        },
+        monsters: battle
+            .monsters
+            .values()
+            .sorted_by_key(|a| a.position)
+            .map(export_monster)
+            .collect(),
+        draw_pile: export_pile(battle, battle.draw.iter().sorted(), false),
+        discard_pile: export_pile(battle, battle.discard.iter(), false),
+        exhaust_pile: export_pile(battle, battle.exhaust.iter().sorted(), false),
+        hand: export_pile(battle, battle.hand.iter().sorted(), true),
+        limbo: export_card_choices(battle),
+        card_in_play: None,
+        turn: 0,
+        cards_discarded_this_turn: battle.discard_count as i32,
+    }
+}
+
+fn export_pile<'a>(
+    battle: &BattleState,
+    uuids: impl Iterator<Item = &'a Uuid>,
+    in_hand: bool,
+) -> Vec<external::Card> {
+    uuids
+        .map(|uuid| {
+            let card = &battle.cards[uuid];
+            let playable = in_hand
+                && battle
+                    .card_playable(card.reference(crate::models::core::CardLocation::PlayerHand));
+            export_card(card, playable)
+        })
+        .collect()
+}
+
+// Cards being chosen from are put in limbo, which is where selections are looked up
+fn export_card_choices(battle: &BattleState) -> Vec<external::Card> {
+    match &battle.card_choose {
+        Some(card_choose) => card_choose
+            .choices
+            .iter()
+            .map(|card| export_card(&battle.cards[&card.uuid], false))
+            .collect(),
+        None => vec![],
+    }
+}
+
+fn export_monster(monster: &Monster) -> external::Monster {
+    external::Monster {
+        max_hp: monster.creature.hp.max as i32,
+        current_hp: monster.creature.hp.amount as i32,
+        block: monster.creature.block as i32,
+        powers: export_powers(&monster.creature),
+        name: monster.base.name.to_string(),
+        id: monster.uuid.to_string(),
+        intent: export_intent(monster.intent),
+        half_dead: false,
+        is_gone: !monster.targetable,
+        move_id: None,
+        last_move_id: None,
+        second_last_move_id: None,
+        move_base_damage: 0,
+        move_adjusted_damage: 0,
+        move_hits: 0,
+    }
+}
+
+fn export_powers(creature: &Creature) -> Vec<external::Power> {
+    creature
+        .buffs
+        .iter()
+        .map(|buff| external::Power {
+            id: buff.uuid.to_string(),
+            name: buff.base.name.to_string(),
+            amount: buff.vars.x as i32,
+            damage: None,
+            misc: None,
+            just_applied: false,
+            card: None,
+        })
+        .collect()
+}
+
+fn export_orb(orb: &Orb) -> external::OrbType {
+    let name = match orb.base {
+        OrbType::Lightning => "Lightning",
+        OrbType::Dark => "Dark",
+        OrbType::Frost => "Frost",
+        OrbType::Plasma => "Plasma",
+        OrbType::Any => panic!("Unexpected orb type of Any"),
+    };
+
+    external::OrbType {
+        name: name.to_string(),
+        orb_id: name.to_string(),
+        evoke_amount: orb.n as i32,
+        passive_amount: 0,
+    }
+}
+
+fn export_deck(game: &GameState) -> Vec<external::Card> {
+    game.deck
+        .values()
+        .sorted_by_key(|a| (a.base.name.as_str(), a.uuid))
+        .map(|a| export_card(a, false))
+        .collect()
+}
+
+fn export_card(card: &Card, is_playable: bool) -> external::Card {
+    external::Card {
+        id: card.uuid.to_string(),
+        name: if card.upgrades > 0 {
+            format!("{}+", card.base.name)
+        } else {
+            card.base.name.to_string()
+        },
+        card_type: export_card_type(card.base._type),
+        rarity: export_rarity(card.base),
+        upgrades: card.upgrades as i32,
+        has_target: card.targeted(),
+        cost: export_cost(card),
+        uuid: card.uuid.to_string(),
+        misc: None,
+        price: None,
+        is_playable,
+        exhausts: false,
+    }
+}
+
+// The costs interop::import_cost reads back as 0
+fn export_cost(card: &Card) -> i32 {
+    if card.base.cost == Amount::X {
+        -1
+    } else if card.base.playable_if == Condition::Never {
+        -2
+    } else {
+        card.cost as i32
+    }
+}
+
+fn export_offer(offer: &CardOffer, price: Option<u16>) -> external::Card {
+    let mut card = Card::new(offer.base);
+    if offer.upgraded {
+        card.upgrade();
+    }
+
+    external::Card {
+        id: offer.base.name.to_string(),
+        uuid: String::new(),
+        price: price.map(|a| a as i32),
+        ..export_card(&card, false)
+    }
+}
+
+fn export_card_type(card_type: CardType) -> external::CardType {
+    match card_type {
+        CardType::Attack => external::CardType::Attack,
+        CardType::Skill => external::CardType::Skill,
+        CardType::Power => external::CardType::Power,
+        CardType::Status => external::CardType::Status,
+        CardType::Curse => external::CardType::Curse,
+        CardType::All => panic!("Unexpected card type of All"),
+    }
+}
+
+fn export_rarity(card: &BaseCard) -> external::CardRarity {
+    if card._type == CardType::Curse {
+        return external::CardRarity::Curse;
+    }
+
+    match card.rarity {
+        Rarity::Starter => external::CardRarity::Basic,
+        Rarity::Common => external::CardRarity::Common,
+        Rarity::Uncommon => external::CardRarity::Uncommon,
+        Rarity::Rare => external::CardRarity::Rare,
+        _ => external::CardRarity::Special,
+    }
+}
+
+fn export_relic(relic: &Relic) -> external::Relic {
+    external::Relic {
+        id: relic.uuid.to_string(),
+        name: relic.base.name.to_string(),
+        counter: relic.vars.x as i32,
+        price: None,
+    }
+}
+
+fn export_relic_offer(relic: &BaseRelic, price: Option<u16>) -> external::Relic {
+    external::Relic {
+        id: relic.name.to_string(),
+        name: relic.name.to_string(),
+        counter: 0,
+        price: price.map(|a| a as i32),
+    }
+}
+
+fn export_potion(potion: &Option<&'static BasePotion>) -> external::Potion {
+    match potion {
+        Some(potion) => external::Potion {
+            can_use: true,
+            can_discard: true,
+            ..export_potion_offer(potion, None)
+        },
+        None => external::Potion {
+            id: String::from("Potion Slot"),
+            name: String::from("Potion Slot"),
+            can_use: false,
+            can_discard: false,
+            requires_target: false,
+            price: None,
+        },
+    }
+}
+
+fn export_potion_offer(potion: &BasePotion, price: Option<u16>) -> external::Potion {
+    external::Potion {
+        id: potion.name.to_string(),
+        name: potion.name.to_string(),
+        can_use: false,
+        can_discard: false,
+        requires_target: potion.targeted,
+        price: price.map(|a| a as i32),
+    }
+}
+
+fn export_chest(chest: ChestType) -> external::ChestType {
+    match chest {
+        ChestType::Small => external::ChestType::SmallChest,
+        ChestType::Medium => external::ChestType::MediumChest,
+        ChestType::Large => external::ChestType::LargeChest,
+        ChestType::Boss => external::ChestType::BossChest,
+    }
+}
+
+fn export_class(class: Class) -> external::PlayerClass {
+    match class {
+        Class::Ironclad => external::PlayerClass::Ironclad,
+        Class::Silent => external::PlayerClass::Silent,
+        Class::Defect => external::PlayerClass::Defect,
+        Class::Watcher => external::PlayerClass::Watcher,
+        _ => external::PlayerClass::Other,
+    }
+}
+
+fn export_intent(intent: Intent) -> external::Intent {
+    match intent {
+        Intent::Attack => external::Intent::Attack,
+        Intent::AttackBuff => external::Intent::AttackBuff,
+        Intent::AttackDebuff => external::Intent::AttackDebuff,
+        Intent::AttackDefend => external::Intent::AttackDefend,
+        Intent::Buff => external::Intent::Buff,
+        Intent::Debuff => external::Intent::Debuff,
+        Intent::StrongDebuff => external::Intent::StrongDebuff,
+        Intent::Defend => external::Intent::Defend,
+        Intent::DefendDebuff => external::Intent::DefendDebuff,
+        Intent::DefendBuff => external::Intent::DefendBuff,
+        Intent::Escape => external::Intent::Escape,
+        Intent::None => external::Intent::None,
+        Intent::Sleep => external::Intent::Sleep,
+        Intent::Stun => external::Intent::Stun,
+        Intent::Unknown => external::Intent::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::vector;
+
+    use crate::comm::request::ScreenState;
+    use crate::models::core::{Class, FightType};
+    use crate::models::{cards, relics};
+    use crate::state::battle::{BattleState, CardChoiceState};
+    use crate::state::core::{CardOffer, Reward, RewardState};
+    use crate::state::floor::{BattleRewardsState, FloorState};
+    use crate::state::game::GameState;
+    use crate::state::probability::Probability;
+    use crate::state::shop::{ShopScreenState, ShopState};
+
+    use super::Simulator;
+
+    fn offer(name: &str) -> CardOffer {
+        CardOffer {
+            base: cards::by_name(name),
+            upgraded: false,
+        }
+    }
+
+    #[test]
+    fn can_start_run() {
+        let mut simulator = Simulator::new();
+
+        let menu = simulator.handle("ready");
+        assert!(menu.game_state.is_none());
+        assert!(menu.available_commands.contains(&String::from("start")));
+
+        let neow = simulator.handle("START Ironclad ");
+        assert!(neow.error.is_none());
+        assert!(neow.in_game);
+        assert_eq!(neow.game_state.unwrap().room_type, "EventRoom");
+
+        let invalid = simulator.handle("PLAY 1");
+        assert!(invalid.error.is_some());
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        let mut simulator = Simulator::new();
+        assert!(simulator.handle("PROCEED").error.is_some());
+
+        simulator.handle("START Ironclad");
+        let restart = simulator.handle("START Silent");
+        assert_eq!(
+            restart.error,
+            Some(String::from("A run is already in progress"))
+        );
+        assert!(simulator.handle("CHOOSE 99").error.is_some());
+    }
+
+    #[test]
+    fn lists_affordable_shop_items() {
+        let mut game_state = GameState::new(Class::Ironclad, 0);
+        game_state.gold = 100;
+        let mut simulator = Simulator::new();
+        simulator.state = FloorState::Shop(ShopState {
+            generated: true,
+            updated: false,
+            cards: vector![(offer("Pommel Strike"), 150), (offer("Shrug It Off"), 60)],
+            potions: vector![],
+            relics: vector![(relics::MEMBERSHIP_CARD, 90)],
+            can_purge: true,
+            game_state,
+            screen_state: ShopScreenState::InShop,
+        });
+
+        let request = simulator.handle("STATE");
+        let game = request.game_state.unwrap();
+        assert!(matches!(game.screen_state, ScreenState::ShopScreen(_)));
+        assert_eq!(
+            game.choice_list,
+            vec!["purge", "shrug it off", "membership card"]
+        );
+
+        // The second card is the first item listed after purge
+        let bought = simulator.handle("CHOOSE 1");
+        assert!(bought.error.is_none());
+        assert_eq!(bought.game_state.unwrap().gold, 40);
+    }
+
+    #[test]
+    fn leaves_the_shop_before_proceeding() {
+        let mut simulator = Simulator::new();
+        simulator.state = FloorState::Shop(ShopState {
+            generated: true,
+            updated: false,
+            cards: vector![],
+            potions: vector![],
+            relics: vector![],
+            can_purge: false,
+            game_state: GameState::new(Class::Ironclad, 0),
+            screen_state: ShopScreenState::InShop,
+        });
+
+        let left = simulator.handle("LEAVE");
+        assert!(left.error.is_none());
+        assert_eq!(left.available_commands, vec!["proceed", "state"]);
+
+        let proceeded = simulator.handle("PROCEED");
+        assert!(proceeded.error.is_none());
+        let game = proceeded.game_state.unwrap();
+        assert!(!matches!(game.screen_state, ScreenState::ShopScreen(_)));
+    }
+
+    #[test]
+    fn takes_rewards() {
+        let mut simulator = Simulator::new();
+        simulator.state = FloorState::BattleRewards(BattleRewardsState {
+            boss: false,
+            game_state: GameState::new(Class::Ironclad, 0),
+            rewards: RewardState {
+                rewards: vector![
+                    Reward::Gold(25),
+                    Reward::CardChoice(vector![offer("Pommel Strike")], None, false)
+                ],
+                deck_operation: None,
+                viewing_reward: None,
+            },
+        });
+
+        let request = simulator.handle("STATE");
+        let gold = request.game_state.as_ref().unwrap().gold;
+        assert_eq!(
+            request.game_state.unwrap().choice_list,
+            vec!["gold", "card"]
+        );
+
+        let taken = simulator.handle("CHOOSE 0");
+        assert!(taken.error.is_none());
+        let game = taken.game_state.unwrap();
+        assert_eq!(game.gold, gold + 25);
+        assert_eq!(game.choice_list, vec!["card"]);
+    }
+
+    #[test]
+    fn selects_cards_from_a_grid() {
+        let mut battle = BattleState::new(
+            GameState::new(Class::Ironclad, 0),
+            &[String::from("Jaw Worm")],
+            FightType::Common,
+            &mut Probability::new(),
+        );
+        battle.card_choose = Some(CardChoiceState {
+            choices: battle.hand().collect(),
+            count_range: 1..2,
+            then: vector![],
+            scry: false,
+        });
+        let mut simulator = Simulator::new();
+        simulator.state = FloorState::Battle(battle);
+
+        let request = simulator.handle("STATE");
+        let game = request.game_state.unwrap();
+        assert!(matches!(game.screen_state, ScreenState::Grid(_)));
+        assert!(!game.choice_list.is_empty());
+
+        // The selection is only sent once it is confirmed
+        let selected = simulator.handle("CHOOSE 0");
+        assert!(selected.error.is_none());
+        assert!(selected
+            .available_commands
+            .contains(&String::from("confirm")));
+    }
+}
@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// Every line of a session file is prefixed with the direction it travelled in
+const REQUEST_PREFIX: &str = "< ";
+const RESPONSE_PREFIX: &str = "> ";
+const CONFIG_PREFIX: &str = "# ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Request(String),  // Received from the game
+    Response(String), // Sent to the game
+    Config(String),   // The settings the session was played with, as RON
+}
+
+impl Entry {
+    fn to_line(&self) -> String {
+        match self {
+            Entry::Request(line) => format!("{}{}", REQUEST_PREFIX, line),
+            Entry::Response(line) => format!("{}{}", RESPONSE_PREFIX, line),
+            Entry::Config(line) => format!("{}{}", CONFIG_PREFIX, line),
+        }
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        if let Some(request) = line.strip_prefix(REQUEST_PREFIX) {
+            Some(Entry::Request(request.to_string()))
+        } else if let Some(response) = line.strip_prefix(RESPONSE_PREFIX) {
+            Some(Entry::Response(response.to_string()))
+        } else {
+            line.strip_prefix(CONFIG_PREFIX)
+                .map(|config| Entry::Config(config.to_string()))
+        }
+    }
+}
+
+pub struct Transcript {
+    writer: Option<Box<dyn Write>>,
+}
+
+impl Transcript {
+    pub fn disabled() -> Self {
+        Self { writer: None }
+    }
+
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+
+    pub fn new<W: Write + 'static>(writer: W) -> Self {
+        Self {
+            writer: Some(Box::new(writer)),
+        }
+    }
+
+    pub fn record_request(&mut self, line: &str) {
+        self.record(Entry::Request(line.trim_end().to_string()))
+    }
+
+    pub fn record_response(&mut self, line: &str) {
+        self.record(Entry::Response(line.trim_end().to_string()))
+    }
+
+    pub fn record_config(&mut self, config: &str) {
+        self.record(Entry::Config(config.to_string()))
+    }
+
+    fn record(&mut self, entry: Entry) {
+        if let Some(writer) = &mut self.writer {
+            // Flushed on every line so that the session survives a crash of the AI
+            writeln!(writer, "{}", entry.to_line())
+                .and_then(|_| writer.flush())
+                .expect("Failed to write to transcript!");
+        }
+    }
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let file = File::open(path)?;
+    parse(BufReader::new(file))
+}
+
+pub fn parse<R: BufRead>(reader: R) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        match Entry::from_line(&line) {
+            Some(entry) => entries.push(entry),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unrecognized transcript line: {}", line),
+                ))
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+pub fn requests(entries: &[Entry]) -> impl Iterator<Item = &str> {
+    entries.iter().filter_map(|entry| match entry {
+        Entry::Request(line) => Some(line.as_str()),
+        _ => None,
+    })
+}
+
+pub fn responses(entries: &[Entry]) -> impl Iterator<Item = &str> {
+    entries.iter().filter_map(|entry| match entry {
+        Entry::Response(line) => Some(line.as_str()),
+        _ => None,
+    })
+}
+
+pub fn config(entries: &[Entry]) -> Option<&str> {
+    entries.iter().find_map(|entry| match entry {
+        Entry::Config(line) => Some(line.as_str()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Entry;
+
+    #[test]
+    fn can_round_trip() {
+        let entries = vec![
+            Entry::Config(String::from("(seed:Some(1))")),
+            Entry::Response(String::from("ready")),
+            Entry::Request(String::from("{\"ready_for_command\":true}")),
+            Entry::Response(String::from("START Ironclad ")),
+        ];
+
+        let text = entries
+            .iter()
+            .map(|entry| entry.to_line() + "\n")
+            .collect::<String>();
+
+        let parsed = super::parse(text.as_bytes()).unwrap();
+        assert_eq!(parsed, entries);
+    }
+}
@@ -0,0 +1,175 @@
+use ron::de::from_reader;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::{stderr, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::models::core::Class;
+use crate::spireai::evaluator::{Evaluator, FloorEvaluator, LinearEvaluator};
+use crate::spireai::network::NetworkEvaluator;
+use crate::spireai::rollout::RolloutConfig;
+use crate::spireai::SearchConfig;
+
+pub mod args;
+
+// Settings that differ between the machines the bot runs on, so that they don't have to be
+// compiled in. Anything left out of the file keeps its default.
+
+pub const DEFAULT_PATH: &str = "spireai.ron";
+// The iteration budget of a seeded run that doesn't set one
+pub const SEEDED_ITERATIONS: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum EvaluatorConfig {
+    Floor,
+    Heuristic,
+    Weights(PathBuf),
+    Network(PathBuf),
+}
+
+// Stdout is how the bot talks to the game, so logs go to a file or stderr
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum LogDestination {
+    Stderr,
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    // The search limits replace the default together, as soon as either of them is set
+    pub time_limit: Option<u64>, // Milliseconds
+    pub iterations: Option<usize>,
+    pub threads: Option<usize>, // Every core when not set
+    pub max_nodes: Option<usize>,
+    pub exploration: Option<f64>,
+    pub rollout: Option<RolloutConfig>,
+    pub evaluator: EvaluatorConfig,
+    // Every class is considered when starting a run, unless one is set. Ascension only applies
+    // along with a class.
+    pub class: Option<Class>,
+    pub ascension: Option<u8>,
+    pub decision_log: Option<LogDestination>,
+    pub decision_top: usize,
+    pub transcript: Option<PathBuf>,
+    pub data_dir: PathBuf,
+    // Makes the bot play the same way every time. The search then runs on one thread with an
+    // iteration budget instead of a time limit, since both of those depend on the machine.
+    pub seed: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            time_limit: None,
+            iterations: None,
+            threads: None,
+            max_nodes: None,
+            exploration: None,
+            rollout: None,
+            evaluator: EvaluatorConfig::Floor,
+            class: None,
+            ascension: None,
+            decision_log: None,
+            decision_top: 5,
+            transcript: None,
+            data_dir: PathBuf::from("data"),
+            seed: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let config = from_reader(file)?;
+        Ok(config)
+    }
+
+    pub fn search_config(&self) -> SearchConfig {
+        let mut config = SearchConfig::default();
+        if self.time_limit.is_some() || self.iterations.is_some() {
+            config.time_limit = self.time_limit.map(Duration::from_millis);
+            config.iterations = self.iterations;
+        }
+        if let Some(threads) = self.threads {
+            config.threads = threads.max(1);
+        }
+        if let Some(max_nodes) = self.max_nodes {
+            config.max_nodes = max_nodes;
+        }
+        if let Some(exploration) = self.exploration {
+            config.exploration = exploration;
+        }
+        config.rollout = self.rollout;
+        config.start = self.class.map(|class| (class, self.ascension));
+        if self.seed.is_some() {
+            config.threads = 1;
+            config.time_limit = None;
+            config.iterations = Some(self.iterations.unwrap_or(SEEDED_ITERATIONS));
+        }
+        config
+    }
+
+    pub fn evaluator(&self) -> Result<Arc<dyn Evaluator>, Box<dyn Error>> {
+        let evaluator: Arc<dyn Evaluator> = match &self.evaluator {
+            EvaluatorConfig::Floor => Arc::new(FloorEvaluator),
+            EvaluatorConfig::Heuristic => Arc::new(LinearEvaluator::heuristic()),
+            EvaluatorConfig::Weights(path) => Arc::new(LinearEvaluator::load(path)?),
+            EvaluatorConfig::Network(path) => Arc::new(NetworkEvaluator::load(path)?),
+        };
+        Ok(evaluator)
+    }
+
+    pub fn decision_log(&self) -> Result<Option<Box<dyn Write + Send>>, Box<dyn Error>> {
+        let writer: Box<dyn Write + Send> = match &self.decision_log {
+            None => return Ok(None),
+            Some(LogDestination::Stderr) => Box::new(stderr()),
+            Some(LogDestination::File(path)) => Box::new(File::create(path)?),
+        };
+        Ok(Some(writer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use crate::models::core::Class;
+    use crate::spireai::rollout::Horizon;
+
+    use super::{Config, EvaluatorConfig, LogDestination};
+
+    #[test]
+    fn test_load_example() {
+        let config = Config::load(Path::new("spireai.example.ron")).unwrap();
+        assert_eq!(config.evaluator, EvaluatorConfig::Heuristic);
+        assert_eq!(config.decision_log, Some(LogDestination::Stderr));
+        assert_eq!(config.data_dir, PathBuf::from("data"));
+
+        let search = config.search_config();
+        assert_eq!(search.time_limit, Some(Duration::from_millis(2000)));
+        assert_eq!(search.iterations, None);
+        assert_eq!(search.exploration, 100.0);
+        assert_eq!(search.rollout.unwrap().horizon, Horizon::Battle);
+        assert_eq!(search.start, Some((Class::Ironclad, Some(0))));
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config: Config = ron::de::from_str("(threads: Some(2))").unwrap();
+        assert_eq!(config.decision_top, 5);
+        assert_eq!(config.search_config().threads, 2);
+        assert_eq!(config.search_config().start, None);
+
+        let seeded: Config = ron::de::from_str("(threads: Some(2), seed: Some(7))").unwrap();
+        let search = seeded.search_config();
+        assert_eq!(search.threads, 1);
+        assert_eq!(search.time_limit, None);
+        assert_eq!(search.iterations, Some(super::SEEDED_ITERATIONS));
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use crate::spireai::rollout::{Horizon, RolloutConfig};
+
+use super::{Config, EvaluatorConfig, LogDestination, DEFAULT_PATH};
+
+// Shared by the binaries. Options are removed from the arguments as they are read, so that
+// whatever is left over can be checked for anything unexpected.
+
+// Settings are read from the file given with --config, or spireai.ron in the working directory if
+// there is one. Options on the command line override the file.
+pub fn load_config(args: &mut Vec<String>) -> Config {
+    let mut config = match config_path(args) {
+        Some(path) => Config::load(&path)
+            .unwrap_or_else(|err| panic!("Unable to load config {}: {}", path.display(), err)),
+        None => Config::default(),
+    };
+
+    search_options(args, &mut config);
+    evaluator_option(args, &mut config);
+    if let Some(log) = take_option(args, "--decision-log") {
+        config.decision_log = Some(match log.as_str() {
+            "stderr" => LogDestination::Stderr,
+            path => LogDestination::File(PathBuf::from(path)),
+        });
+    }
+    if let Some(top) = take_number(args, "--decision-top") {
+        config.decision_top = top as usize;
+    }
+    if let Some(path) = take_option(args, "--record") {
+        config.transcript = Some(PathBuf::from(path));
+    }
+    config
+}
+
+pub fn config_path(args: &mut Vec<String>) -> Option<PathBuf> {
+    take_option(args, "--config")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(DEFAULT_PATH)).filter(|a| a.exists()))
+}
+
+// The search limits can be given on their own or together, and replace both limits of the file
+fn search_options(args: &mut Vec<String>, config: &mut Config) {
+    let time_limit = take_number(args, "--time");
+    let iterations = take_number(args, "--iterations").map(|a| a as usize);
+    if time_limit.is_some() || iterations.is_some() {
+        config.time_limit = time_limit;
+        config.iterations = iterations;
+    }
+    if let Some(threads) = take_number(args, "--threads") {
+        config.threads = Some(threads as usize);
+    }
+    if let Some(max_nodes) = take_number(args, "--max-nodes") {
+        config.max_nodes = Some(max_nodes as usize);
+    }
+    if let Some(seed) = take_number(args, "--seed") {
+        config.seed = Some(seed);
+    }
+    if let Some(horizon) = take_option(args, "--rollout") {
+        let horizon = match horizon.as_str() {
+            "battle" => Horizon::Battle,
+            "floor" => Horizon::Floor,
+            "run" => Horizon::Run,
+            _ => panic!(
+                "Expected battle, floor or run after --rollout, got {}",
+                horizon
+            ),
+        };
+        config.rollout = Some(RolloutConfig {
+            horizon,
+            ..RolloutConfig::default()
+        });
+    }
+}
+
+// Anything other than the built in evaluators is read as a weights file
+fn evaluator_option(args: &mut Vec<String>, config: &mut Config) {
+    let evaluator = take_option(args, "--evaluator");
+    if let Some(path) = take_option(args, "--network") {
+        if evaluator.is_some() {
+            panic!("--network and --evaluator can't be used together");
+        }
+        config.evaluator = EvaluatorConfig::Network(PathBuf::from(path));
+        return;
+    }
+
+    match evaluator.as_deref() {
+        None => {}
+        Some("floor") => config.evaluator = EvaluatorConfig::Floor,
+        Some("heuristic") => config.evaluator = EvaluatorConfig::Heuristic,
+        Some(path) => config.evaluator = EvaluatorConfig::Weights(PathBuf::from(path)),
+    }
+}
+
+pub fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|a| a == name)?;
+    args.remove(index);
+    if index == args.len() {
+        panic!("Expected a value after {}", name);
+    }
+
+    Some(args.remove(index))
+}
+
+pub fn take_number(args: &mut Vec<String>, name: &str) -> Option<u64> {
+    take_option(args, name).map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Expected a number after {}, got {}", name, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::EvaluatorConfig;
+
+    use super::{load_config, take_flag};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_options_override_file() {
+        let mut args = args(&[
+            "--config",
+            "spireai.example.ron",
+            "--runs",
+            "3",
+            "--iterations",
+            "50",
+            "--evaluator",
+            "floor",
+        ]);
+        let config = load_config(&mut args);
+
+        assert_eq!(config.iterations, Some(50));
+        assert_eq!(config.time_limit, None);
+        assert_eq!(config.evaluator, EvaluatorConfig::Floor);
+        // Options the config doesn't know about are left for the binary
+        assert_eq!(args, vec!["--runs", "3"]);
+        assert!(!take_flag(&mut args, "--random"));
+    }
+}
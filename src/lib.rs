@@ -0,0 +1,10 @@
+#![allow(dead_code)]
+
+pub mod comm;
+pub mod config;
+pub mod models;
+pub mod spireai;
+pub mod state;
+
+#[macro_use]
+extern crate lazy_static;
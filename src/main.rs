@@ -1,34 +1,222 @@
-#![allow(dead_code)]
-
-use crate::comm::request::GameState;
-use crate::models::choices::Choice;
-use comm::request::Request;
-use comm::response::Response;
+use spireai::comm;
+use spireai::comm::request::{GameState, Request};
+use spireai::comm::response::Response;
+use spireai::comm::transcript::{self, Transcript};
+use spireai::config::args::{config_path, load_config, take_flag, take_number};
+use spireai::config::Config;
+use spireai::models;
+use spireai::models::choices::Choice;
+use spireai::models::validate;
+use spireai::spireai::evaluator::Evaluator;
+use spireai::spireai::selfplay::{self, BatchStats, Policy, SelfPlayConfig};
+use spireai::spireai::SpireAi;
+use spireai::state::floor::FloorState;
+use spireai::state::probability;
 use std::error::Error;
 use std::io::{stdin, stdout, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-mod comm;
-mod models;
-mod spireai;
-mod state;
+// The subcommand can be left out, and defaults to play so that CommunicationMod can start the
+// binary with nothing but options.
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match args.first() {
+        Some(first) if !first.starts_with("--") => args.remove(0),
+        _ => String::from("play"),
+    };
+    // The config is one of the files that validate-data checks, so it can't be loaded up front
+    if command == "validate-data" {
+        validate_data(args);
+        return;
+    }
 
-#[macro_use]
-extern crate lazy_static;
+    let config = load_config(&mut args);
+    models::set_data_dir(&config.data_dir);
+    if let Some(seed) = config.seed {
+        probability::seed_thread(seed);
+    }
 
-fn main() {
-    run(stdin().lock(), stdout());
+    match command.as_str() {
+        "play" => play(args, &config),
+        "simulate" => simulate(args, &config),
+        "replay" => replay_transcript(args, &config),
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: spireai [play] [options] [--record <transcript>]
+       spireai simulate [options] [--runs <count>] [--random] [--max-decisions <count>]
+       spireai replay <transcript> [options]
+       spireai validate-data [--config <spireai.ron>]
+Options: [--config <spireai.ron>] [--time <ms>] [--iterations <count>] [--threads <count>] [--max-nodes <count>] [--seed <number>] [--rollout <battle | floor | run>] [--evaluator <floor | heuristic | weights.ron> | --network <network.ron>] [--decision-log <path | stderr>] [--decision-top <count>]"
+    );
+    std::process::exit(2);
+}
+
+// Talks to the game through CommunicationMod on stdin and stdout
+fn play(args: Vec<String>, config: &Config) {
+    if !args.is_empty() {
+        usage();
+    }
+
+    let mut transcript = match &config.transcript {
+        None => Transcript::disabled(),
+        Some(path) => Transcript::create(path).unwrap_or_else(|err| {
+            panic!("Unable to create transcript {}: {}", path.display(), err)
+        }),
+    };
+    if config.transcript.is_some() {
+        if config.seed.is_none() {
+            eprintln!("Recording without a seed, so the transcript can't be replayed");
+        }
+        let header = ron::ser::to_string(config).expect("Unable to serialize config");
+        transcript.record_config(&header);
+    }
+    run(stdin().lock(), stdout(), &mut transcript, create_ai(config))
+}
+
+// Plays whole runs against the simulator, and prints how each of them went along with the totals
+fn simulate(mut args: Vec<String>, config: &Config) {
+    let runs = take_number(&mut args, "--runs").unwrap_or(10) as usize;
+    let random = take_flag(&mut args, "--random");
+    let mut selfplay_config = SelfPlayConfig {
+        policy: if random {
+            Policy::Random
+        } else {
+            Policy::Search(config.search_config())
+        },
+        ..SelfPlayConfig::default()
+    };
+    if let Some(max_decisions) = take_number(&mut args, "--max-decisions") {
+        selfplay_config.max_decisions = max_decisions as usize;
+    }
+    if !args.is_empty() {
+        usage();
+    }
+
+    let evaluator = evaluator(config);
+    let mut stats = BatchStats::default();
+    for run in 0..runs {
+        let records = selfplay::play_run(run, &selfplay_config, evaluator.clone());
+        let outcome = records.first().map(|a| a.outcome).unwrap_or_default();
+        stats.add(&outcome, records.len());
+        println!(
+            "Run {}: {} decisions, {} on floor {}",
+            run,
+            records.len(),
+            if !outcome.finished {
+                "unfinished"
+            } else if outcome.won {
+                "won"
+            } else {
+                "died"
+            },
+            outcome.floor
+        );
+    }
+    println!("{}", stats);
+}
+
+fn replay_transcript(args: Vec<String>, config: &Config) {
+    let path = match args.as_slice() {
+        [path] => path,
+        _ => usage(),
+    };
+    let entries = transcript::read(Path::new(path))
+        .unwrap_or_else(|err| panic!("Unable to read transcript {}: {}", path, err));
+    let config = replay_config(&entries, config).unwrap_or_else(|err| {
+        eprintln!("Unable to replay {}: {}", path, err);
+        std::process::exit(1);
+    });
+    if let Some(seed) = config.seed {
+        probability::seed_thread(seed);
+    }
+    if !replay(&entries, create_ai(&config)) {
+        std::process::exit(1);
+    }
 }
 
-fn run<R, W>(mut reader: R, mut writer: W)
+// The search only plays the same way again with the settings it was recorded with, and only when
+// those settings made it deterministic. Where the logs go and where the data is are still up to
+// the machine doing the replay.
+fn replay_config(entries: &[transcript::Entry], config: &Config) -> Result<Config, String> {
+    let recorded = transcript::config(entries)
+        .ok_or_else(|| String::from("it was recorded without its config"))?;
+    let recorded: Config =
+        ron::de::from_str(recorded).map_err(|err| format!("its config can't be read: {}", err))?;
+    if recorded.seed.is_none() {
+        return Err(String::from(
+            "it was recorded without a seed, so its search depended on timing and threads",
+        ));
+    }
+
+    Ok(Config {
+        decision_log: config.decision_log.clone(),
+        decision_top: config.decision_top,
+        transcript: None,
+        data_dir: config.data_dir.clone(),
+        ..recorded
+    })
+}
+
+fn validate_data(mut args: Vec<String>) {
+    let config = config_path(&mut args);
+    if !args.is_empty() {
+        usage();
+    }
+
+    let problems = validate::validate(config.as_deref());
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        eprintln!("Found {} problems in the data", problems.len());
+        std::process::exit(1);
+    }
+    println!("All data files are valid");
+}
+
+fn evaluator(config: &Config) -> Arc<dyn Evaluator> {
+    config
+        .evaluator()
+        .unwrap_or_else(|err| panic!("Unable to load evaluator {:?}: {}", config.evaluator, err))
+}
+
+fn create_ai(config: &Config) -> SpireAi {
+    let mut ai =
+        SpireAi::with_evaluator(FloorState::Menu, config.search_config(), evaluator(config));
+    let decision_log = config
+        .decision_log()
+        .unwrap_or_else(|err| panic!("Unable to create decision log: {}", err));
+    if let Some(writer) = decision_log {
+        ai.log_decisions(writer, config.decision_top);
+    }
+    ai
+}
+
+fn run<R, W>(mut reader: R, mut writer: W, transcript: &mut Transcript, mut ai: SpireAi)
 where
     R: BufRead,
     W: Write,
 {
-    let mut ai = spireai::SpireAi::new(crate::state::floor::FloorState::Menu);
     let mut game_state: Option<GameState> = None;
     let mut queue: Vec<Response> = initial_queue();
-    loop {
-        let request = process_queue(&mut queue, &game_state, &mut reader, &mut writer);
+    while let Some((request, recovered)) = process_queue(
+        &mut queue,
+        &game_state,
+        &mut reader,
+        &mut writer,
+        transcript,
+    ) {
+        if recovered {
+            ai.reject_choice();
+        }
+
         let choice = handle_request(&request, &mut ai);
 
         queue = comm::response::decompose_choice(choice, &request, &ai.uuid_map);
@@ -36,85 +224,353 @@ where
     }
 }
 
+// Feeds the requests of a recorded session back through the AI with no game attached.
+// Returns false if the AI responded differently than it did during the recording.
+fn replay(entries: &[transcript::Entry], ai: SpireAi) -> bool {
+    let input = transcript::requests(entries)
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+    let mut output = Vec::new();
+
+    run(
+        input.as_bytes(),
+        &mut output,
+        &mut Transcript::disabled(),
+        ai,
+    );
+
+    let replayed = String::from_utf8(output).expect("Responses are not valid UTF-8");
+    let recorded: Vec<&str> = transcript::responses(entries).collect();
+    let replayed: Vec<&str> = replayed.lines().collect();
+    compare_responses(&recorded, &replayed)
+}
+
+fn compare_responses(recorded: &[&str], replayed: &[&str]) -> bool {
+    let mut matches = true;
+    for (index, (recorded, replayed)) in recorded.iter().zip(replayed).enumerate() {
+        if recorded != replayed {
+            eprintln!(
+                "Response {} differs. Recorded: \"{}\", Replayed: \"{}\"",
+                index, recorded, replayed
+            );
+            matches = false;
+        }
+    }
+    if recorded.len() != replayed.len() {
+        eprintln!(
+            "{} responses were recorded, but {} were replayed",
+            recorded.len(),
+            replayed.len()
+        );
+        matches = false;
+    }
+
+    matches
+}
+
 fn initial_queue() -> Vec<Response> {
     vec![Response::Simple(String::from("ready"))]
 }
 
-fn handle_request(request: &Request, ai: &mut spireai::SpireAi) -> Choice {
+fn handle_request(request: &Request, ai: &mut SpireAi) -> Choice {
     ai.choose(&request.game_state)
 }
 
+const RECOVERY_DELAY_MS: u64 = 100;
+const MAX_RECOVERY_DELAY_MS: u64 = 10_000;
+
+// Returns None once the game closes the connection.
+// If the game rejects a response, the rest of the queue is dropped and the fresh state is returned
+// along with true, so that the caller can make a new choice from it.
 fn process_queue<R, W>(
     queue: &mut Vec<Response>,
     game_state: &Option<GameState>,
     reader: &mut R,
     writer: &mut W,
-) -> Request
+    transcript: &mut Transcript,
+) -> Option<(Request, bool)>
 where
     R: BufRead,
     W: Write,
 {
     loop {
-        send_message(&queue[0], game_state, writer);
-        let request = read_request(reader);
-        match &request.error {
-            Some(err) => {
-                panic!("Game error: {}", err)
-            }
-            None => {}
+        send_message(&queue[0], game_state, writer, transcript);
+        let request = read_request(reader, transcript)?;
+        if let Some(err) = &request.error {
+            eprintln!("Game error: {}", err);
+            return recover(game_state, reader, writer, transcript).map(|a| (a, true));
         }
 
         if queue.len() > 1 {
             queue.remove(0);
         } else {
-            return request;
+            return Some((request, false));
         }
     }
 }
 
-fn send_message<W>(response: &Response, game: &Option<GameState>, writer: &mut W)
+fn recover<R, W>(
+    game_state: &Option<GameState>,
+    reader: &mut R,
+    writer: &mut W,
+    transcript: &mut Transcript,
+) -> Option<Request>
 where
+    R: BufRead,
+    W: Write,
+{
+    // The game can stay busy for a while, so the state is asked for until it answers
+    let mut attempt = 0;
+    loop {
+        send_message(
+            &Response::Simple(String::from("STATE")),
+            game_state,
+            writer,
+            transcript,
+        );
+        let request = read_request(reader, transcript)?;
+        match &request.error {
+            Some(err) => eprintln!("Game error while recovering: {}", err),
+            None => return Some(request),
+        }
+
+        thread::sleep(recovery_delay(attempt));
+        attempt += 1;
+    }
+}
+
+// Doubles with every failed attempt, up to a limit
+fn recovery_delay(attempt: u32) -> Duration {
+    let delay = RECOVERY_DELAY_MS.saturating_mul(1 << attempt.min(16));
+    Duration::from_millis(delay.min(MAX_RECOVERY_DELAY_MS))
+}
+
+fn send_message<W>(
+    response: &Response,
+    game: &Option<GameState>,
+    writer: &mut W,
+    transcript: &mut Transcript,
+) where
     W: Write,
 {
     let serialized = comm::response::serialize_response(response, game);
+    transcript.record_response(&serialized);
     writeln!(writer, "{}", serialized).expect("Failed to write!");
 }
 
-fn read_request<R>(reader: &mut R) -> Request
+fn read_request<R>(reader: &mut R, transcript: &mut Transcript) -> Option<Request>
 where
     R: BufRead,
 {
     let input = &mut String::new();
     let request = match reader.read_line(input) {
+        Ok(0) => return None,
         Ok(_) => input.to_string(),
         Err(err) => panic!("Communication failed! Error: {}", err),
     };
 
+    transcript.record_request(&request);
+
+    // A line that can't be read is treated like an error reported by the game
     let model = match deserialize(&request) {
         Ok(model) => model,
-        Err(err) => panic!("Failed to deserialize game state: Error: {}", err),
+        Err(err) => Request {
+            error: Some(format!("Failed to deserialize game state: {}", err)),
+            ready_for_command: true,
+            in_game: false,
+            game_state: None,
+            available_commands: vec![],
+        },
     };
 
-    model
+    Some(model)
 }
 
-fn deserialize(state: &str) -> Result<comm::request::Request, Box<dyn Error>> {
+fn deserialize(state: &str) -> Result<Request, Box<dyn Error>> {
     let deserialized = serde_json::from_str(state)?;
     Ok(deserialized)
 }
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
+
+    use spireai::comm::response::Response;
+    use spireai::comm::transcript::{self, Entry, Transcript};
+    use spireai::config::Config;
+    use spireai::spireai::{SearchConfig, SpireAi};
+    use spireai::state::floor::FloorState;
+
+    const MENU_REQUEST: &str = "{\"available_commands\":[\"start\",\"state\"],\"ready_for_command\":true,\"in_game\":false}";
+
     #[test]
     fn test_io() {
-        let mut input = b"{\"available_commands\":[\"start\",\"state\"],\"ready_for_command\":true,\"in_game\":false}" as &[u8];
+        let mut input = MENU_REQUEST.as_bytes();
         let mut output = Vec::new();
         let mut queue = crate::initial_queue();
 
-        let response = crate::process_queue(&mut queue, &None, &mut input, &mut output);
+        let response = crate::process_queue(
+            &mut queue,
+            &None,
+            &mut input,
+            &mut output,
+            &mut Transcript::disabled(),
+        )
+        .unwrap()
+        .0;
 
         assert!(response.error.is_none());
         assert!(response.game_state.is_none());
         assert_eq!(std::str::from_utf8(&output).unwrap(), "ready\n")
     }
+
+    #[test]
+    fn test_recover() {
+        let input = format!(
+            "{}\n{}\n",
+            "{\"error\":\"Invalid command\",\"ready_for_command\":true}", MENU_REQUEST
+        );
+        let mut output = Vec::new();
+        let mut queue = vec![
+            Response::Simple(String::from("END")),
+            Response::Simple(String::from("PROCEED")),
+        ];
+
+        let (response, recovered) = crate::process_queue(
+            &mut queue,
+            &None,
+            &mut input.as_bytes(),
+            &mut output,
+            &mut Transcript::disabled(),
+        )
+        .unwrap();
+
+        assert!(recovered);
+        assert!(response.error.is_none());
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "END\nSTATE\n")
+    }
+
+    #[test]
+    fn test_recover_keeps_trying() {
+        let error = "{\"error\":\"Invalid command\",\"ready_for_command\":true}";
+        let input = format!("{}\n{}\n{}\n{}\n", error, error, error, MENU_REQUEST);
+        let mut output = Vec::new();
+        let mut queue = vec![Response::Simple(String::from("END"))];
+
+        let (response, recovered) = crate::process_queue(
+            &mut queue,
+            &None,
+            &mut input.as_bytes(),
+            &mut output,
+            &mut Transcript::disabled(),
+        )
+        .unwrap();
+
+        assert!(recovered);
+        assert!(response.error.is_none());
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            "END\nSTATE\nSTATE\nSTATE\n"
+        );
+        assert_eq!(crate::recovery_delay(1), Duration::from_millis(200));
+        assert_eq!(crate::recovery_delay(40), Duration::from_millis(10_000));
+    }
+
+    #[test]
+    fn test_rejected_choice_not_repeated() {
+        let error = "{\"error\":\"Invalid command\",\"ready_for_command\":true}";
+        let input = format!("{}\n{}\n{}\n", MENU_REQUEST, error, MENU_REQUEST);
+        let mut output = Vec::new();
+        let config = SearchConfig {
+            time_limit: None,
+            iterations: Some(20),
+            ..SearchConfig::default()
+        };
+
+        crate::run(
+            input.as_bytes(),
+            &mut output,
+            &mut Transcript::disabled(),
+            SpireAi::with_config(FloorState::Menu, config),
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        let responses: Vec<&str> = output.lines().collect();
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[2], "STATE");
+        assert!(responses[1].starts_with("START"));
+        assert!(responses[3].starts_with("START"));
+        assert_ne!(responses[1], responses[3]);
+    }
+
+    #[test]
+    fn test_record() {
+        let path =
+            std::env::temp_dir().join(format!("spireai-{}.transcript", uuid::Uuid::new_v4()));
+        let mut input = MENU_REQUEST.as_bytes();
+        let mut queue = crate::initial_queue();
+
+        {
+            let mut transcript = Transcript::create(&path).unwrap();
+            crate::process_queue(
+                &mut queue,
+                &None,
+                &mut input,
+                &mut Vec::new(),
+                &mut transcript,
+            )
+            .unwrap();
+        }
+
+        let entries = transcript::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                Entry::Response(String::from("ready")),
+                Entry::Request(String::from(MENU_REQUEST))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replay_config() {
+        let header = |config: &Config| Entry::Config(ron::ser::to_string(config).unwrap());
+        let config = Config {
+            decision_top: 2,
+            ..Config::default()
+        };
+        assert!(crate::replay_config(&[], &config).is_err());
+
+        let unseeded = Config {
+            threads: Some(4),
+            ..Config::default()
+        };
+        assert!(crate::replay_config(&[header(&unseeded)], &config).is_err());
+
+        let seeded = Config {
+            seed: Some(3),
+            iterations: Some(50),
+            ..Config::default()
+        };
+        let replayed = crate::replay_config(&[header(&seeded)], &config).unwrap();
+        assert_eq!(replayed.seed, Some(3));
+        assert_eq!(replayed.search_config().iterations, Some(50));
+        assert_eq!(replayed.decision_top, 2);
+    }
+
+    #[test]
+    fn test_compare_responses() {
+        assert!(crate::compare_responses(
+            &["ready", "PLAY 1"],
+            &["ready", "PLAY 1"]
+        ));
+        assert!(!crate::compare_responses(
+            &["ready", "PLAY 1"],
+            &["ready", "END"]
+        ));
+        assert!(!crate::compare_responses(&["ready", "PLAY 1"], &["ready"]));
+        assert!(!crate::compare_responses(&["ready"], &["ready", "END"]));
+    }
 }
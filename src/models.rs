@@ -1,3 +1,6 @@
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
 pub mod acts;
 pub mod buffs;
 pub mod cards;
@@ -7,3 +10,17 @@ pub mod events;
 pub mod monsters;
 pub mod potions;
 pub mod relics;
+pub mod validate;
+
+lazy_static! {
+    static ref DATA_DIR: RwLock<PathBuf> = RwLock::new(PathBuf::from("data"));
+}
+
+// The game data is loaded on first use, so the directory has to be set before anything reads it
+pub fn set_data_dir(path: &Path) {
+    *DATA_DIR.write().unwrap() = path.to_path_buf();
+}
+
+pub fn data_path(file: &str) -> PathBuf {
+    DATA_DIR.read().unwrap().join(file)
+}
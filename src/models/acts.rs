@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::{error::Error, fs::File, io::BufReader, path::Path};
+use std::{error::Error, fs::File, io::BufReader};
 
 #[derive(Eq, PartialEq, Clone, Deserialize, Serialize)]
 pub struct Act {
@@ -39,7 +39,7 @@ impl std::fmt::Debug for Act {
 }
 
 pub fn all_acts() -> Result<Vec<Act>, Box<dyn Error>> {
-    let filepath = Path::new("data").join("acts.ron");
+    let filepath = super::data_path("acts.ron");
     let file = File::open(filepath)?;
     let reader = BufReader::new(file);
     let u = ron::de::from_reader(reader)?;
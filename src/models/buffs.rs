@@ -1,7 +1,7 @@
 use ron::de::from_reader;
 use serde::{Deserialize, Serialize};
 
-use std::{collections::HashMap, error::Error, fs::File, hash::Hash, path::Path, ptr};
+use std::{collections::HashMap, error::Error, fs::File, hash::Hash, ptr};
 
 use super::core::{is_default, Effect, When, WhenEffect};
 
@@ -130,8 +130,8 @@ pub static VIGOR: &'static BaseBuff = BUFFS.get("Vigor").unwrap_or(&BAD_BUFF);
 pub static VULNERABLE: &'static BaseBuff = BUFFS.get("Vulnerable").unwrap_or(&BAD_BUFF);
 pub static WEAK: &'static BaseBuff = BUFFS.get("Weak").unwrap_or(&BAD_BUFF);
 
-fn all_buffs() -> Result<Vec<BaseBuff>, Box<dyn Error>> {
-    let filepath = Path::new("data").join("buffs.ron");
+pub fn all_buffs() -> Result<Vec<BaseBuff>, Box<dyn Error>> {
+    let filepath = super::data_path("buffs.ron");
     let file = File::open(filepath)?;
     let u = from_reader(file)?;
     Ok(u)
@@ -2,7 +2,7 @@ use ron::de::from_reader;
 use serde::{Deserialize, Serialize};
 
 use ::std::hash::{Hash, Hasher};
-use std::{collections::HashMap, error::Error, fs::File, path::Path};
+use std::{collections::HashMap, error::Error, fs::File};
 
 use super::core::{is_default, Amount, CardType, Class, Condition, Effect, Rarity};
 
@@ -141,8 +141,8 @@ lazy_static! {
         .collect();
 }
 
-fn all_cards() -> Result<Vec<BaseCard>, Box<dyn Error>> {
-    let filepath = Path::new("data").join("cards.ron");
+pub fn all_cards() -> Result<Vec<BaseCard>, Box<dyn Error>> {
+    let filepath = super::data_path("cards.ron");
     let file = File::open(filepath)?;
     let u = from_reader(file)?;
     Ok(u)
@@ -1,6 +1,6 @@
 use ::std::hash::{Hash, Hasher};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, fs::File, path::Path};
+use std::{collections::HashMap, error::Error, fs::File};
 
 use ron::de::from_reader;
 
@@ -71,8 +71,8 @@ lazy_static! {
     };
 }
 
-fn all_events() -> Result<Vec<BaseEvent>, Box<dyn Error>> {
-    let filepath = Path::new("data").join("events.ron");
+pub fn all_events() -> Result<Vec<BaseEvent>, Box<dyn Error>> {
+    let filepath = super::data_path("events.ron");
     let file = File::open(filepath)?;
     let u = from_reader(file)?;
     Ok(u)
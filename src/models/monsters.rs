@@ -1,6 +1,6 @@
 use ron::de::from_reader;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, fs::File, path::Path};
+use std::{collections::HashMap, error::Error, fs::File};
 
 use ::std::hash::{Hash, Hasher};
 
@@ -157,8 +157,8 @@ lazy_static! {
     };
 }
 
-fn all_monsters() -> Result<Vec<BaseMonster>, Box<dyn Error>> {
-    let filepath = Path::new("data").join("monsters.ron");
+pub fn all_monsters() -> Result<Vec<BaseMonster>, Box<dyn Error>> {
+    let filepath = super::data_path("monsters.ron");
     let file = File::open(filepath)?;
     let u = from_reader(file)?;
     Ok(u)
@@ -1,7 +1,7 @@
 use ::std::hash::{Hash, Hasher};
 use ron::de::from_reader;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, fs::File, path::Path};
+use std::{collections::HashMap, error::Error, fs::File};
 
 use super::core::{is_default, Class, Effect, Rarity};
 
@@ -54,8 +54,8 @@ lazy_static! {
     };
 }
 
-fn all_potions() -> Result<Vec<BasePotion>, Box<dyn Error>> {
-    let filepath = Path::new("data").join("potions.ron");
+pub fn all_potions() -> Result<Vec<BasePotion>, Box<dyn Error>> {
+    let filepath = super::data_path("potions.ron");
     let file = File::open(filepath)?;
     let u = from_reader(file)?;
     Ok(u)
@@ -1,7 +1,7 @@
 use ::std::hash::{Hash, Hasher};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, fs::File, path::Path, ptr};
+use std::{collections::HashMap, error::Error, fs::File, ptr};
 
 use ron::de::from_reader;
 
@@ -223,8 +223,8 @@ pub static TOY_ORNITHOPTER: &'static BaseRelic =
 pub static TUNGSTEN_ROD: &'static BaseRelic = RELICS.get("Tungsten Rod").unwrap_or(&BAD_RELIC);
 pub static VIOLET_LOTUS: &'static BaseRelic = RELICS.get("Violet Lotus").unwrap_or(&BAD_RELIC);
 
-fn all_relics() -> Result<Vec<BaseRelic>, Box<dyn Error>> {
-    let filepath = Path::new("data").join("relics.ron");
+pub fn all_relics() -> Result<Vec<BaseRelic>, Box<dyn Error>> {
+    let filepath = super::data_path("relics.ron");
     let file = File::open(filepath)?;
     let u = from_reader(file)?;
     Ok(u)
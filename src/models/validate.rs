@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::panic;
+use std::path::Path;
+
+use super::acts::{self, MonsterSet};
+use super::core::{Effect, RewardType};
+use super::monsters::{BaseMonster, Move};
+use super::{buffs, cards, data_path, events, monsters, potions, relics};
+use crate::config::Config;
+use crate::spireai::evaluator::LinearEvaluator;
+
+// Loads every data file and checks that the names one entry uses to refer to another exist.
+// Buffs and relics are looked up while loading, but cards, monsters, events and phases are only
+// looked up once the simulator reaches them. The config is checked first when there is one, since
+// it says where the data files are. Returns a description of every problem found.
+pub fn validate(config: Option<&Path>) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(path) = config {
+        check_config(path, &mut problems);
+    }
+    check_weights(&data_path("weights.ron"), &mut problems);
+    let buffs = load("buffs.ron", buffs::all_buffs, &mut problems);
+    let relics = load("relics.ron", relics::all_relics, &mut problems);
+    let cards = load("cards.ron", cards::all_cards, &mut problems);
+    let potions = load("potions.ron", potions::all_potions, &mut problems);
+    let monsters = load("monsters.ron", monsters::all_monsters, &mut problems);
+    let events = load("events.ron", events::all_events, &mut problems);
+    let acts = load("acts.ron", acts::all_acts, &mut problems);
+
+    duplicates("buff", buffs.iter().map(|a| &a.name), &mut problems);
+    duplicates("relic", relics.iter().map(|a| &a.name), &mut problems);
+    duplicates("card", cards.iter().map(|a| &a.name), &mut problems);
+    duplicates("potion", potions.iter().map(|a| &a.name), &mut problems);
+    duplicates("monster", monsters.iter().map(|a| &a.name), &mut problems);
+    duplicates("event", events.iter().map(|a| &a.name), &mut problems);
+
+    let mut checker = Checker {
+        cards: cards.iter().map(|a| a.name.as_str()).collect(),
+        monsters: monsters.iter().map(|a| a.name.as_str()).collect(),
+        relics: relics.iter().map(|a| a.name.as_str()).collect(),
+        events: events.iter().map(|a| a.name.as_str()).collect(),
+        event_choices: HashSet::new(),
+        problems,
+    };
+
+    for card in &cards {
+        for effects in &[
+            &card.on_start,
+            &card.on_play,
+            &card.on_discard,
+            &card.on_draw,
+            &card.on_exhaust,
+            &card.on_retain,
+            &card.on_turn_end,
+        ] {
+            checker.effects(&card.name, effects);
+        }
+    }
+    for relic in &relics {
+        checker.effects(&relic.name, &relic.effect);
+    }
+    for potion in &potions {
+        checker.effects(&potion.name, &potion.on_drink);
+    }
+    for buff in &buffs {
+        checker.effects(&buff.name, &buff.on_add);
+        for when in &buff.effects {
+            checker.effects(&buff.name, &when.effect);
+        }
+    }
+    for monster in &monsters {
+        checker.monster(monster);
+    }
+    for event in &events {
+        checker.event_choices = event.choices.iter().map(|a| a.name.as_str()).collect();
+        for choice in &event.choices {
+            checker.effects(&event.name, &choice.effects);
+        }
+    }
+    checker.event_choices.clear();
+    for act in &acts {
+        let source = format!("Act {}", act.num);
+        let fights = act.easy_fights.iter().chain(&act.normal_fights);
+        for set in fights
+            .map(|a| &a.set)
+            .chain(&act.elites)
+            .chain(act.bosses.iter().map(|a| &a.monsters))
+        {
+            checker.monster_set(&source, set);
+        }
+        for event in &act.events {
+            checker.name(&source, "event", event);
+        }
+    }
+
+    checker.problems
+}
+
+// The evaluator the config names is loaded as well, since its file is only read when a run starts
+fn check_config(path: &Path, problems: &mut Vec<String>) {
+    let config = match Config::load(path) {
+        Ok(config) => config,
+        Err(err) => {
+            problems.push(format!("Unable to load {}: {}", path.display(), err));
+            return;
+        }
+    };
+
+    super::set_data_dir(&config.data_dir);
+    if let Err(err) = config.evaluator() {
+        problems.push(format!(
+            "Unable to load the evaluator {:?} of {}: {}",
+            config.evaluator,
+            path.display(),
+            err
+        ));
+    }
+}
+
+fn check_weights(path: &Path, problems: &mut Vec<String>) {
+    if let Err(err) = LinearEvaluator::load(path) {
+        problems.push(format!("Unable to load {}: {}", path.display(), err));
+    }
+}
+
+// Relics and buffs that other files refer to are looked up while loading, and panic when missing
+fn load<T>(
+    file: &str,
+    loader: fn() -> Result<Vec<T>, Box<dyn Error>>,
+    problems: &mut Vec<String>,
+) -> Vec<T> {
+    match panic::catch_unwind(loader) {
+        Ok(Ok(loaded)) => loaded,
+        Ok(Err(err)) => {
+            problems.push(format!("Unable to load {}: {}", file, err));
+            vec![]
+        }
+        Err(panic) => {
+            let message = match panic.downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => panic
+                    .downcast_ref::<&str>()
+                    .map_or(String::from("unknown error"), |a| a.to_string()),
+            };
+            problems.push(format!("Unable to load {}: {}", file, message));
+            vec![]
+        }
+    }
+}
+
+// Later entries replace earlier ones with the same name when the data is loaded
+fn duplicates<'a, I>(kind: &str, names: I, problems: &mut Vec<String>)
+where
+    I: Iterator<Item = &'a String>,
+{
+    let mut seen = HashSet::new();
+    for name in names {
+        if !seen.insert(name) {
+            problems.push(format!("The {} {} is defined more than once", kind, name));
+        }
+    }
+}
+
+struct Checker<'a> {
+    cards: HashSet<&'a str>,
+    monsters: HashSet<&'a str>,
+    relics: HashSet<&'a str>,
+    events: HashSet<&'a str>,
+    event_choices: HashSet<&'a str>, // The choices of the event being checked
+    problems: Vec<String>,
+}
+
+impl<'a> Checker<'a> {
+    fn name(&mut self, source: &str, kind: &str, name: &str) {
+        let known = match kind {
+            "card" => &self.cards,
+            "monster" => &self.monsters,
+            "relic" => &self.relics,
+            "event" => &self.events,
+            "event choice" => &self.event_choices,
+            _ => panic!("Unexpected kind of name: {}", kind),
+        };
+        if !known.contains(name) {
+            self.problems.push(format!(
+                "{} refers to an unknown {}: {}",
+                source, kind, name
+            ));
+        }
+    }
+
+    fn effects(&mut self, source: &str, effects: &[Effect]) {
+        for effect in effects {
+            match effect {
+                Effect::AttackDamage { if_fatal, .. } => self.effects(source, if_fatal),
+                Effect::CreateCard { name, .. } | Effect::DeckAdd(name) => {
+                    self.name(source, "card", name)
+                }
+                Effect::Split(first, second) => {
+                    self.name(source, "monster", first);
+                    self.name(source, "monster", second);
+                }
+                Effect::Spawn { choices, .. } => {
+                    for name in choices {
+                        self.name(source, "monster", name);
+                    }
+                }
+                Effect::Fight { monsters, .. } => {
+                    for name in monsters {
+                        self.name(source, "monster", name);
+                    }
+                }
+                Effect::If { then, _else, .. } => {
+                    self.effects(source, then);
+                    self.effects(source, _else);
+                }
+                Effect::RandomChance(chances) => {
+                    for chance in chances {
+                        self.effects(source, &chance.effect);
+                    }
+                }
+                Effect::Repeat { effect, .. } => self.effects(source, effect),
+                Effect::ShowReward(rewards) => {
+                    for reward in rewards {
+                        if let RewardType::RelicName(name) = reward {
+                            self.name(source, "relic", name);
+                        }
+                    }
+                }
+                Effect::ShowChoices(choices) => {
+                    for name in choices {
+                        self.name(source, "event choice", name);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Moves have to be in the moveset, and phases can only lead to phases of the same monster
+    fn monster(&mut self, monster: &BaseMonster) {
+        let source = &monster.name;
+        self.effects(source, &monster.on_create);
+
+        let moves: HashSet<&str> = monster.moveset.iter().map(|a| a.name.as_str()).collect();
+        let phases: HashSet<&str> = monster.phases.iter().map(|a| a.name.as_str()).collect();
+        let mut used_moves = Vec::new();
+        let mut used_phases = Vec::new();
+        for phase in &monster.phases {
+            used_phases.push(&phase.next);
+            for next in &phase.moves {
+                match next {
+                    Move::If {
+                        then_phase,
+                        else_phase,
+                        ..
+                    } => {
+                        used_phases.push(then_phase);
+                        used_phases.push(else_phase);
+                    }
+                    Move::Fixed(name) => used_moves.push(name),
+                    Move::Probability(choices) => {
+                        used_moves.extend(choices.iter().map(|a| &a.name))
+                    }
+                }
+            }
+        }
+
+        for name in used_moves {
+            if !moves.contains(name.as_str()) {
+                self.problems
+                    .push(format!("{} refers to an unknown move: {}", source, name));
+            }
+        }
+        // An empty phase name means the monster stays in its current phase
+        for name in used_phases {
+            if !name.is_empty() && !phases.contains(name.as_str()) {
+                self.problems
+                    .push(format!("{} refers to an unknown phase: {}", source, name));
+            }
+        }
+        for monster_move in &monster.moveset {
+            self.effects(source, &monster_move.effects);
+        }
+    }
+
+    fn monster_set(&mut self, source: &str, set: &MonsterSet) {
+        let names: Vec<&String> = match set {
+            MonsterSet::Fixed(names) => names.iter().collect(),
+            MonsterSet::ChooseN { choices, .. } => choices.iter().collect(),
+            MonsterSet::RandomSet(sets) => sets.iter().flatten().collect(),
+        };
+        for name in names {
+            self.name(source, "monster", name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{check_config, check_weights, Checker};
+    use crate::models::core::Effect;
+
+    #[test]
+    fn test_malformed_files() {
+        let path = std::env::temp_dir().join(format!("spireai-{}.ron", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "(weights: [1.0, ").unwrap();
+        let mut problems = vec![];
+        check_weights(&path, &mut problems);
+        check_config(&path, &mut problems);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with(&format!("Unable to load {}", path.display())));
+
+        let mut problems = vec![];
+        check_weights(
+            &std::path::Path::new("data").join("weights.ron"),
+            &mut problems,
+        );
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_card() {
+        let mut checker = Checker {
+            cards: vec!["Strike"].into_iter().collect(),
+            monsters: HashSet::new(),
+            relics: HashSet::new(),
+            events: HashSet::new(),
+            event_choices: HashSet::new(),
+            problems: vec![],
+        };
+        let effects = vec![
+            Effect::DeckAdd(String::from("Strike")),
+            Effect::DeckAdd(String::from("Strike Twice")),
+        ];
+        checker.effects("Test Event", &effects);
+        assert_eq!(
+            checker.problems,
+            vec![String::from(
+                "Test Event refers to an unknown card: Strike Twice"
+            )]
+        );
+    }
+}
@@ -1,62 +1,297 @@
-use crate::comm::interop;
 use crate::comm::request::GameState as CommState;
+use crate::comm::{diagnostics, interop};
+use crate::models;
 use crate::state::floor::{FloorState, GamePossibility};
-use crate::{models, state::probability::Probability};
-use im::{HashMap, HashSet};
-use itertools::Itertools;
+use crate::state::probability::{self, Probability};
+use crate::state::shop::ShopScreenState;
+use crate::state::{HashMap, HashSet};
+use evaluator::{Evaluator, FloorEvaluator};
 use models::choices::Choice;
-use num::complex::ComplexFloat;
-use rand::rngs::ThreadRng;
+use models::core::Class;
 use rand::seq::SliceRandom;
 use rand::Rng;
+use rollout::RolloutConfig;
 use rustc_hash::FxHasher;
+use serde::Serialize;
 use std::cmp::Ordering;
-use std::hash::{BuildHasher, Hash};
-use std::rc::Rc;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 pub mod appraiser;
+pub mod encoder;
 pub mod enumerator;
+pub mod environment;
+pub mod evaluator;
+pub mod network;
+pub mod planner;
 pub mod predictor;
 pub mod references;
+pub mod rollout;
+pub mod route;
+pub mod selfplay;
+pub mod shopper;
+pub mod trainer;
+
+// Limits on how long the tree is searched before each decision. The search stops at whichever
+// limit is reached first, so at least one of them needs to be set.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchConfig {
+    pub time_limit: Option<Duration>,
+    pub iterations: Option<usize>, // Shared between all of the threads
+    // Weight of the exploration term, on the same scale as the evaluation (100 per floor)
+    pub exploration: f64,
+    pub threads: usize,
+    pub max_nodes: usize, // Shared between all of the threads
+    // New leaves are played out with the rollout policy when set, instead of being evaluated as is
+    pub rollout: Option<RolloutConfig>,
+    // Runs are started with this class and ascension, instead of searching over every class
+    pub start: Option<(Class, Option<u8>)>,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            time_limit: Some(Duration::from_secs(1)),
+            iterations: None,
+            exploration: 100.0,
+            threads: thread::available_parallelism().map_or(1, |a| a.get()),
+            max_nodes: 200_000,
+            rollout: None,
+            start: None,
+        }
+    }
+}
+
+impl SearchConfig {
+    fn finished(&self, iterations: usize, elapsed: Duration) -> bool {
+        self.iterations.map_or(false, |max| iterations >= max)
+            || self.time_limit.map_or(false, |max| elapsed >= max)
+    }
+
+    // The share of the iterations that one of the threads runs
+    fn for_thread(&self, index: usize) -> SearchConfig {
+        let threads = self.threads.max(1);
+        SearchConfig {
+            iterations: self
+                .iterations
+                .map(|a| a / threads + (index < a % threads) as usize),
+            threads: 1,
+            max_nodes: self.max_nodes / threads,
+            ..*self
+        }
+    }
+}
 
 pub struct SpireAi {
     last_choice: Option<Choice>,
     tree: MonteCarloTree,
+    config: SearchConfig,
+    decision_log: Option<DecisionLog>,
+    decisions: usize,
     pub uuid_map: HashMap<String, Uuid>,
 }
 
+// Where the reasoning behind each decision is written, as one JSON object per line. Stdout is the
+// game's protocol channel, so this has to go somewhere else.
+struct DecisionLog {
+    writer: Box<dyn Write + Send>,
+    top: usize,
+}
+
 impl SpireAi {
     pub fn new(state: FloorState) -> SpireAi {
+        SpireAi::with_config(state, SearchConfig::default())
+    }
+
+    pub fn with_config(state: FloorState, config: SearchConfig) -> SpireAi {
+        SpireAi::with_evaluator(state, config, Arc::new(FloorEvaluator))
+    }
+
+    pub fn with_evaluator(
+        state: FloorState,
+        config: SearchConfig,
+        evaluator: Arc<dyn Evaluator>,
+    ) -> SpireAi {
+        assert!(
+            config.time_limit.is_some() || config.iterations.is_some(),
+            "The search needs a time or iteration limit"
+        );
+
         SpireAi {
             last_choice: None,
-            tree: MonteCarloTree::new(Rc::new(Box::new(state))),
-            uuid_map: HashMap::new(),
+            tree: MonteCarloTree::new(Arc::new(Box::new(state)), evaluator),
+            config,
+            decision_log: None,
+            decisions: 0,
+            uuid_map: HashMap::default(),
         }
     }
 
+    // Logs the statistics of the most visited choices after every decision
+    pub fn log_decisions(&mut self, writer: Box<dyn Write + Send>, top: usize) {
+        self.decision_log = Some(DecisionLog { writer, top });
+    }
+
     pub fn update_state(&mut self, comm_state: &Option<CommState>) {
-        if let Some(choice) = self.last_choice {
-            if let Some(mut matching_state) = self.find_match(&choice, comm_state) {
-                interop::update_state(comm_state, matching_state.as_mut());
-                self.tree.new_root(matching_state);
-            } else {
-                panic!("No matching state found!")
+        if let Some(choice) = self.last_choice.take() {
+            if let Some(matching_state) = self.find_match(&choice, comm_state) {
+                let mut state = matching_state.as_ref().as_ref().clone();
+                interop::update_state(comm_state, &mut state);
+                self.tree.new_root(Arc::new(Box::new(state)));
+                return;
             }
+
+            eprintln!(
+                "No matching state found, resyncing with the game\n{}",
+                self.mismatch_report(&choice, comm_state)
+            );
+            let predicted = self.likely_outcome(&choice);
+            if !self.attach(comm_state, &predicted) {
+                self.tree.new_root(predicted);
+            }
+        } else if !interop::state_matches(comm_state, &self.tree.root.game, &mut self.uuid_map) {
+            // Started in the middle of a run, or the game refused the last choice
+            let root = self.tree.root.game.clone();
+            self.attach(comm_state, &root);
         }
     }
 
-    pub fn choose(&mut self) -> Option<Choice> {
-        let choice = self.tree.make_choice();
-        self.last_choice = choice;
+    pub fn choose(&mut self, comm_state: &Option<CommState>) -> Choice {
+        self.update_state(comm_state);
+        self.tree.search(&self.config);
+        let choice = self.tree.make_choice().expect("No choices available!");
+        self.decisions += 1;
+        self.log_decision(&choice);
+        self.last_choice = Some(choice.clone());
         choice
     }
 
+    // Why the search made its last decision. Choices are sorted by visits, and only the top ones
+    // are kept.
+    pub fn explain(&self, chosen: &Choice, top: usize) -> Decision {
+        let root = &self.tree.root;
+        let log_visits = root.visits.max(1.0).ln();
+        let mut choices: Vec<ChoiceStats> = root
+            .children
+            .iter()
+            .map(|child| ChoiceStats {
+                choice: format!("{:?}", child.choice),
+                visits: child.visits,
+                value: if child.visits > 0.0 {
+                    Some(child.value())
+                } else {
+                    None
+                },
+                exploration: if child.visits > 0.0 {
+                    Some(child.exploration_bonus(log_visits, self.config.exploration))
+                } else {
+                    None
+                },
+                outcomes: child.outcomes.len(),
+                fully_evaluated: child.fully_evaluated,
+            })
+            .collect();
+        choices.sort_by(|a, b| b.visits.partial_cmp(&a.visits).unwrap_or(Ordering::Equal));
+        choices.truncate(top);
+
+        Decision {
+            decision: self.decisions,
+            floor: match root.game.as_ref().as_ref() {
+                FloorState::Menu | FloorState::GameOver(..) => None,
+                state => Some(state.game_state().map.floor),
+            },
+            chosen: format!("{:?}", chosen),
+            visits: root.visits,
+            eval: root.eval,
+            choices,
+        }
+    }
+
+    fn log_decision(&mut self, choice: &Choice) {
+        let top = match &self.decision_log {
+            Some(log) => log.top,
+            None => return,
+        };
+        let line = serde_json::to_string(&self.explain(choice, top))
+            .expect("Unable to serialize the decision");
+        // Flushed every time, so that the log is complete if the bot crashes
+        let log = self.decision_log.as_mut().unwrap();
+        if let Err(err) = writeln!(log.writer, "{}", line).and_then(|_| log.writer.flush()) {
+            eprintln!("Unable to write to the decision log: {}", err);
+        }
+    }
+
+    // Throws away the tree and starts over from the state reported by the game. Returns false
+    // when the state can't be imported, in which case the tree is left alone.
+    fn attach(&mut self, comm_state: &Option<CommState>, predicted: &GameState) -> bool {
+        match interop::import_state(comm_state, predicted, &mut self.uuid_map) {
+            Ok(state) => {
+                self.tree =
+                    MonteCarloTree::new(Arc::new(Box::new(state)), self.tree.evaluator.clone());
+                true
+            }
+            Err(err) => {
+                eprintln!(
+                    "Unable to import the game's state, keeping the prediction: {}",
+                    err
+                );
+                false
+            }
+        }
+    }
+
+    // The most probable state after a choice, or the current one if it was never explored
+    fn likely_outcome(&self, choice: &Choice) -> GameState {
+        self.tree
+            .root
+            .get_outcomes(choice)
+            .and_then(|a| {
+                a.outcomes
+                    .iter()
+                    .max_by(|a, b| {
+                        a.1.probability
+                            .partial_cmp(&b.1.probability)
+                            .unwrap_or(Ordering::Equal)
+                    })
+                    .map(|a| a.0.clone())
+            })
+            .unwrap_or_else(|| self.tree.root.game.clone())
+    }
+
+    fn mismatch_report(&self, choice: &Choice, comm_state: &Option<CommState>) -> String {
+        let outcomes: Vec<&FloorState> = self
+            .tree
+            .root
+            .get_outcomes(choice)
+            .map(|a| a.outcomes.keys().map(|a| a.as_ref().as_ref()).collect())
+            .unwrap_or_default();
+
+        diagnostics::report(comm_state, &outcomes, &self.uuid_map)
+    }
+
+    pub fn table_stats(&self) -> TableStats {
+        self.tree.stats()
+    }
+
+    // The game refused the last choice, so the next state is not one of its outcomes. The choice
+    // is dropped from the root, so that the same command isn't sent again if the game reports the
+    // same state.
+    pub fn reject_choice(&mut self) {
+        if let Some(choice) = self.last_choice.take() {
+            self.tree.root.remove_choice(&choice);
+        }
+    }
+
     fn find_match(&mut self, choice: &Choice, comm_state: &Option<CommState>) -> Option<GameState> {
         if let Some(outcomes) = self.tree.root.get_outcomes(choice) {
             for outcome in outcomes.outcomes.keys() {
                 if interop::state_matches(comm_state, outcome, &mut self.uuid_map) {
-                    return Some(*outcome);
+                    return Some(outcome.clone());
                 }
             }
         }
@@ -64,21 +299,93 @@ impl SpireAi {
     }
 }
 
-type GameState = Rc<Box<FloorState>>;
+type GameState = Arc<Box<FloorState>>;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Decision {
+    pub decision: usize,
+    pub floor: Option<i8>,
+    pub chosen: String,
+    pub visits: f64,
+    pub eval: f64, // The static evaluation of the state the decision was made in
+    pub choices: Vec<ChoiceStats>,
+}
+
+// Value and exploration are left out for choices the search never tried
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChoiceStats {
+    pub choice: String,
+    pub visits: f64,
+    pub value: Option<f64>,
+    pub exploration: Option<f64>,
+    pub outcomes: usize,
+    pub fully_evaluated: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TableStats {
+    pub size: usize,
+    pub lookups: u64,
+    pub hits: u64,
+    pub evictions: u64,
+}
+
+impl TableStats {
+    pub fn hit_rate(&self) -> f64 {
+        if self.lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.lookups as f64
+        }
+    }
+
+    fn add(&mut self, other: &TableStats) {
+        self.lookups += other.lookups;
+        self.hits += other.hits;
+        self.evictions += other.evictions;
+    }
+}
+
+impl fmt::Display for TableStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {:.1}% hit rate over {} lookups, {} evicted",
+            self.size,
+            self.hit_rate() * 100.0,
+            self.lookups,
+            self.evictions
+        )
+    }
+}
+
+// Once the table is over its budget, this fraction of it is evicted at once so that the cost of
+// sorting the nodes is spread over many iterations
+const EVICTION_FRACTION: usize = 10;
 
-#[derive(PartialEq, Clone)]
 struct MonteCarloTree {
     root: MonteCarloNode,
-    nodes: HashMap<GameState, MonteCarloNode, FxBuildHasher>,
+    nodes: im::HashMap<GameState, MonteCarloNode, FxBuildHasher>,
+    stats: TableStats,
+    evaluator: Arc<dyn Evaluator>,
 }
 
 impl MonteCarloTree {
-    pub fn new(state: GameState) -> Self {
-        let root = MonteCarloNode::new(state, 0);
-        let mut nodes = HashMap::with_hasher(FxBuildHasher::default());
+    pub fn new(state: GameState, evaluator: Arc<dyn Evaluator>) -> Self {
+        let root = MonteCarloNode::new(state, 0, evaluator.as_ref());
+        let nodes = im::HashMap::with_hasher(FxBuildHasher::default());
         Self {
             root,
             nodes,
+            stats: TableStats::default(),
+            evaluator,
+        }
+    }
+
+    pub fn stats(&self) -> TableStats {
+        TableStats {
+            size: self.nodes.len(),
+            ..self.stats
         }
     }
 
@@ -88,33 +395,166 @@ impl MonteCarloTree {
             self.root = node;
             self.nodes.retain(|_, v| v.depth > old_depth)
         } else {
-            *self = MonteCarloTree::new(root);
+            *self = MonteCarloTree::new(root, self.evaluator.clone());
         }
     }
 
+    // The most visited choice is the one the search is most confident in
     pub fn make_choice(&self) -> Option<Choice> {
-        self.root.choose().map(|a| a.choice)
-    }
-
-    pub fn explore(&mut self) -> Vec<(Choice, GameState)> {
-        let mut current_node = &self.root;
-        let mut path: Vec<(Choice, GameState)> = vec![];
-        loop {
-            if let Some(choice) = current_node.choose() {
-                
-                let outcome = choice.predict_outcome(current_node.game);
-                let mut inserted = false;
-                current_node = self.nodes.entry(outcome).or_insert_with(|| {
-                    inserted = true;
-                    MonteCarloNode::new(outcome, current_node.depth + 1, )
-                });
-                path.push((choice.choice, current_node.game));
-                
-                if inserted
-                {
-                    return path;
-                }
+        self.root
+            .children
+            .iter()
+            .max_by(|a, b| a.visits.partial_cmp(&b.visits).unwrap_or(Ordering::Equal))
+            .map(|a| a.choice.clone())
+    }
+
+    // Root parallel search: every thread grows its own tree from the root, and the statistics of
+    // the root's choices are added together at the end
+    pub fn search(&mut self, config: &SearchConfig) {
+        self.root.restrict_start(config.start);
+        if self.root.children.is_empty() {
+            return;
+        }
+
+        if config.threads <= 1 {
+            self.search_thread(config);
+            return;
+        }
+
+        let root = self.root.game.clone();
+        let choices: Vec<Choice> = self
+            .root
+            .children
+            .iter()
+            .map(|a| a.choice.clone())
+            .collect();
+        let workers: Vec<MonteCarloTree> = thread::scope(|scope| {
+            let handles: Vec<_> = (1..config.threads)
+                .map(|index| {
+                    let root = root.clone();
+                    let evaluator = self.evaluator.clone();
+                    let config = config.for_thread(index);
+                    let choices = &choices;
+                    scope.spawn(move || {
+                        let mut tree = MonteCarloTree::new(root, evaluator);
+                        // Only the choices left at the main root, which has the start restricted
+                        // and rejected choices removed
+                        tree.root.children.retain(|a| choices.contains(&a.choice));
+                        tree.search_thread(&config);
+                        tree
+                    })
+                })
+                .collect();
+
+            self.search_thread(&config.for_thread(0));
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Search thread panicked!"))
+                .collect()
+        });
+
+        for worker in workers {
+            self.root.merge(&worker.root);
+            self.stats.add(&worker.stats);
+        }
+    }
+
+    fn search_thread(&mut self, config: &SearchConfig) {
+        let start = Instant::now();
+        let mut iterations = 0;
+        while !config.finished(iterations, start.elapsed()) {
+            self.explore(config);
+            self.evict(config.max_nodes);
+            iterations += 1;
+        }
+    }
+
+    // Drops the least visited nodes, deepest first, once the table is over budget. The evicted
+    // states are dropped from the outcomes of their parents as well, along with their statistics,
+    // so that nothing holds on to them. An evicted state is rebuilt from scratch if the search
+    // reaches it again.
+    fn evict(&mut self, max_nodes: usize) {
+        if self.nodes.len() <= max_nodes {
+            return;
+        }
+
+        let target = max_nodes - max_nodes / EVICTION_FRACTION;
+        let count = self.nodes.len() - target;
+        let mut candidates: Vec<(f64, usize, GameState)> = self
+            .nodes
+            .iter()
+            .map(|(state, node)| (node.visits, node.depth, state.clone()))
+            .collect();
+        candidates.select_nth_unstable_by(count - 1, |a, b| {
+            a.0.partial_cmp(&b.0)
+                .unwrap_or(Ordering::Equal)
+                .then(b.1.cmp(&a.1))
+        });
+
+        let evicted: HashSet<GameState> = candidates
+            .into_iter()
+            .take(count)
+            .map(|(_, _, state)| state)
+            .collect();
+        self.stats.evictions += evicted.len() as u64;
+        for state in &evicted {
+            self.nodes.remove(state);
+        }
+
+        self.root.forget(&evicted);
+        for (_, node) in self.nodes.iter_mut() {
+            node.forget(&evicted);
+        }
+    }
+
+    // Walks down the tree until it reaches a state that hasn't been evaluated yet, adds it to the
+    // tree, and then feeds its evaluation back through every choice made along the way
+    fn explore(&mut self, config: &SearchConfig) {
+        let mut path: Vec<(GameState, usize, GameState)> = vec![];
+        let mut current = self.root.game.clone();
+        let value = loop {
+            let node = self.node_mut(&current);
+            let child = match node.select(config.exploration) {
+                Some(child) => child,
+                None => break node.eval,
+            };
+            let depth = node.depth;
+            let outcome = node.children[child].predict_outcome(&current);
+            path.push((current, child, outcome.clone()));
+
+            if path.iter().any(|(state, _, _)| *state == outcome) {
+                break self.node_mut(&outcome).eval;
             }
+
+            self.stats.lookups += 1;
+            if self.nodes.contains_key(&outcome) {
+                self.stats.hits += 1;
+            } else {
+                let node = MonteCarloNode::new(outcome.clone(), depth + 1, self.evaluator.as_ref());
+                let eval = match &config.rollout {
+                    Some(rollout) => rollout::rollout(&outcome, rollout, self.evaluator.as_ref()),
+                    None => node.eval,
+                };
+                self.nodes.insert(outcome, node);
+                break eval;
+            }
+
+            current = outcome;
+        };
+
+        for (state, child, outcome) in path {
+            self.node_mut(&state).record(child, &outcome, value);
+        }
+    }
+
+    fn node_mut(&mut self, state: &GameState) -> &mut MonteCarloNode {
+        if *state == self.root.game {
+            &mut self.root
+        } else {
+            self.nodes
+                .get_mut(state)
+                .expect("State is not in the tree!")
         }
     }
 }
@@ -129,15 +569,15 @@ struct MonteCarloNode {
 }
 
 impl MonteCarloNode {
-    pub fn new(state: GameState, depth: usize) -> Self {
-        let eval = evaluate(&state);
-        let mut children: Vec<_> = enumerator::all_choices(&state)
+    pub fn new(state: GameState, depth: usize, evaluator: &dyn Evaluator) -> Self {
+        let eval = evaluator.evaluate(&state);
+        let mut children: Vec<_> = worth_exploring(&state, enumerator::all_choices(&state))
             .into_iter()
             .map(ChoiceOutcomes::new)
             .collect();
 
         // Shuffle children to make exploration of choices balanced
-        children.shuffle(&mut rand::thread_rng());
+        probability::with_rng(|rng| children.shuffle(rng));
 
         Self {
             game: state,
@@ -152,25 +592,129 @@ impl MonteCarloNode {
         self.children.iter().find(|f| &f.choice == choice)
     }
 
-    pub fn choose(&self) -> Option<&ChoiceOutcomes> {
-        let total_visits_factor = self.visits.ln() * 2.0;
-        self.children.iter()
-            .map(|child| (child, child.eval(total_visits_factor)))
-            .reduce(|child1, child2| {
-                if child1.1 < child2.1 {
-                    child2
-                } else {
-                    child1
-                }
-            })
+    pub fn record(&mut self, child: usize, outcome: &GameState, value: f64) {
+        self.visits += 1.0;
+        self.children[child].record(outcome, value);
+    }
+
+    // Adds in the statistics that another tree gathered from the same state
+    pub fn merge(&mut self, other: &MonteCarloNode) {
+        self.visits += other.visits;
+        for other_child in &other.children {
+            let child = self
+                .children
+                .iter_mut()
+                .find(|a| a.choice == other_child.choice)
+                .expect("Merged trees have different choices!");
+            child.merge(other_child);
+        }
+    }
+
+    // Replaces the choice between every class on the menu with the configured start, keeping
+    // whatever the search already knows about it
+    fn restrict_start(&mut self, start: Option<(Class, Option<u8>)>) {
+        let (player_class, ascension) = match start {
+            Some(start) => start,
+            None => return,
+        };
+        let choice = Choice::Start {
+            player_class,
+            ascension,
+        };
+        let on_menu = self
+            .children
+            .iter()
+            .any(|a| matches!(a.choice, Choice::Start { .. }));
+        if on_menu {
+            let child = self
+                .get_outcomes(&choice)
+                .cloned()
+                .unwrap_or_else(|| ChoiceOutcomes::new(choice));
+            self.visits = child.visits;
+            self.children = vec![child];
+        }
+    }
+
+    // Leaves the node as it is if the choice is the only one, since there is nothing else to try
+    fn remove_choice(&mut self, choice: &Choice) {
+        if let Some(index) = self.children.iter().position(|a| &a.choice == choice) {
+            if self.children.len() > 1 {
+                self.visits -= self.children.remove(index).visits;
+            }
+        }
+    }
+
+    fn forget(&mut self, evicted: &HashSet<GameState>) {
+        for child in &mut self.children {
+            let visits = child.forget(evicted);
+            self.visits -= visits;
+        }
+    }
+
+    // Picks the child to explore next using UCT
+    pub fn select(&self, exploration: f64) -> Option<usize> {
+        let log_visits = self.visits.max(1.0).ln();
+        self.children
+            .iter()
+            .enumerate()
+            .map(|(index, child)| (index, child.score(log_visits, exploration)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
             .map(|a| a.0)
     }
 }
 
+// Leaves out choices that the planners rate as clearly worse than the alternatives, so that the
+// search doesn't spend its budget on them
+fn worth_exploring(state: &FloorState, mut choices: Vec<Choice>) -> Vec<Choice> {
+    match state {
+        FloorState::Map(game_state) => {
+            let routes = route::worth_exploring(game_state);
+            if choices.iter().any(|a| routes.contains(a)) {
+                choices.retain(|a| !matches!(a, Choice::NavigateToNode(_)) || routes.contains(a));
+            }
+        }
+        FloorState::Shop(shop) if shop.screen_state == ShopScreenState::InShop => {
+            let basket = shopper::worth_buying(shop);
+            choices.retain(|a| {
+                !matches!(
+                    a,
+                    Choice::BuyCard(_)
+                        | Choice::BuyPotion(_)
+                        | Choice::BuyRelic(_)
+                        | Choice::BuyRemoveCard(_)
+                ) || basket.contains(a)
+            });
+        }
+        _ => {}
+    }
+    choices
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+struct OutcomeStats {
+    probability: f64,
+    visits: f64,
+    total_value: f64,
+}
+
+impl OutcomeStats {
+    fn new(probability: f64) -> Self {
+        Self {
+            probability,
+            visits: 0.0,
+            total_value: 0.0,
+        }
+    }
+
+    fn value(&self) -> f64 {
+        self.total_value / self.visits
+    }
+}
+
 #[derive(PartialEq, Clone)]
 struct ChoiceOutcomes {
     choice: Choice,
-    outcomes: HashMap<GameState, f64>,
+    outcomes: HashMap<GameState, OutcomeStats>,
     visits: f64,
     fully_evaluated: bool,
 }
@@ -179,14 +723,14 @@ impl ChoiceOutcomes {
     fn new(choice: Choice) -> Self {
         Self {
             choice,
-            outcomes: HashMap::new(),
+            outcomes: HashMap::default(),
             visits: 0.0,
             fully_evaluated: false,
         }
     }
 
     fn total_probability(&self) -> f64 {
-        let total_probability: f64 = self.outcomes.values().sum();
+        let total_probability: f64 = self.outcomes.values().map(|a| a.probability).sum();
         assert!(
             total_probability < 1.00001,
             "Total probability greater than 1!"
@@ -194,44 +738,101 @@ impl ChoiceOutcomes {
         total_probability
     }
 
-    fn eval(&self, total_visits_factor: f64) -> f64 {
-        if self.outcomes.is_empty() {
+    fn score(&self, log_visits: f64, exploration: f64) -> f64 {
+        if self.visits == 0.0 {
             return f64::MAX;
         }
 
-        let mut total_probability: f64 = 0.0;
-        let mut total_eval: f64 = 0.0;
-        for (outcome, probability) in self.outcomes {
-            total_probability += probability;
-            total_eval += outcome.eval * outcome.probability;
+        self.value() + self.exploration_bonus(log_visits, exploration)
+    }
+
+    fn exploration_bonus(&self, log_visits: f64, exploration: f64) -> f64 {
+        exploration * (2.0 * log_visits / self.visits).sqrt()
+    }
+
+    // Each outcome's average is weighted by its probability, so that outcomes which happened to
+    // be sampled more often than their odds don't skew the value of the choice
+    fn value(&self) -> f64 {
+        let (total_value, total_probability) = self
+            .outcomes
+            .values()
+            .filter(|a| a.visits > 0.0)
+            .fold((0.0, 0.0), |(value, probability), a| {
+                (
+                    value + a.value() * a.probability,
+                    probability + a.probability,
+                )
+            });
+
+        total_value / total_probability
+    }
+
+    fn record(&mut self, outcome: &GameState, value: f64) {
+        self.visits += 1.0;
+        let stats = self
+            .outcomes
+            .get_mut(outcome)
+            .expect("Recorded an outcome that was never predicted!");
+        stats.visits += 1.0;
+        stats.total_value += value;
+    }
+
+    fn merge(&mut self, other: &ChoiceOutcomes) {
+        self.visits += other.visits;
+        for (state, other_stats) in &other.outcomes {
+            let stats = self
+                .outcomes
+                .entry(state.clone())
+                .or_insert_with(|| OutcomeStats::new(other_stats.probability));
+            stats.visits += other_stats.visits;
+            stats.total_value += other_stats.total_value;
         }
 
-        total_eval / total_probability + (total_visits_factor / self.visits).sqrt()
+        self.fully_evaluated = self.total_probability() > 0.99999;
+    }
+
+    // Returns the visits of the outcomes that were dropped
+    fn forget(&mut self, evicted: &HashSet<GameState>) -> f64 {
+        let mut visits = 0.0;
+        self.outcomes.retain(|state, stats| {
+            let keep = !evicted.contains(state);
+            if !keep {
+                visits += stats.visits;
+            }
+            keep
+        });
+        self.visits -= visits;
+        self.fully_evaluated = self.total_probability() > 0.99999;
+        visits
     }
 
-    fn predict_outcome(&mut self, state: GameState) -> GameState {
+    fn predict_outcome(&mut self, state: &GameState) -> GameState {
         if self.fully_evaluated {
-            let mut remaining = rand::thread_rng().gen_range(0.0..1.0);
-            for (state, val) in self.outcomes {
-                remaining -= val;
+            let mut remaining = probability::with_rng(|rng| rng.gen_range(0.0..1.0));
+            for (state, stats) in &self.outcomes {
+                remaining -= stats.probability;
                 if remaining < 0.00001 {
-                    return state;
+                    return state.clone();
                 }
             }
 
             panic!("Expected total probability to be greater than the random range!")
         }
         let mut possibility = GamePossibility {
-            state: state.clone(),
+            state: state.as_ref().as_ref().clone(),
             probability: Probability::new(),
         };
 
-        predictor::predict_outcome(self.choice, &mut possibility);
+        predictor::predict_outcome(self.choice.clone(), &mut possibility);
 
         let probability = possibility.probability.probability;
-        let state = Rc::new(Box::new(possibility.state));
+        let state = Arc::new(Box::new(possibility.state));
 
-        let existing_probability = *self.outcomes.entry(state).or_insert(probability);
+        let existing_probability = self
+            .outcomes
+            .entry(state.clone())
+            .or_insert_with(|| OutcomeStats::new(probability))
+            .probability;
 
         assert!(
             (probability - existing_probability).abs() < 0.0001,
@@ -246,12 +847,6 @@ impl ChoiceOutcomes {
     }
 }
 
-fn evaluate(state: &FloorState) -> f64 {
-    let game_state = state.game_state();
-    game_state.map.floor as f64 * 100.0 + game_state.hp.amount as f64
-    // Neural net
-}
-
 #[derive(Default)]
 struct FxBuildHasher;
 
@@ -265,9 +860,30 @@ impl BuildHasher for FxBuildHasher {
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
+    use crate::state::HashMap;
+
+    use crate::models::core::Class;
     use crate::{models::choices::Choice, state::floor::FloorState};
 
-    use super::SpireAi;
+    use super::{ChoiceOutcomes, GameState, MonteCarloNode, OutcomeStats, SearchConfig, SpireAi};
+
+    fn state(won: bool) -> GameState {
+        Arc::new(Box::new(FloorState::GameOver(won, false)))
+    }
+
+    fn choice_outcomes(choice: Choice, outcomes: &[(GameState, f64)]) -> ChoiceOutcomes {
+        ChoiceOutcomes {
+            choice,
+            outcomes: outcomes
+                .iter()
+                .map(|(state, probability)| (state.clone(), OutcomeStats::new(*probability)))
+                .collect(),
+            visits: 0.0,
+            fully_evaluated: true,
+        }
+    }
 
     #[test]
     fn test_start() {
@@ -275,4 +891,162 @@ mod test {
         let choice = ai.choose(&None);
         assert!(matches!(choice, Choice::Start { .. }))
     }
+
+    #[test]
+    fn test_configured_start() {
+        let config = SearchConfig {
+            time_limit: None,
+            iterations: Some(20),
+            start: Some((Class::Silent, None)),
+            ..SearchConfig::default()
+        };
+        let mut ai = SpireAi::with_config(FloorState::Menu, config);
+        let start = Choice::Start {
+            player_class: Class::Silent,
+            ascension: None,
+        };
+        assert_eq!(ai.choose(&None), start);
+
+        // Statistics gathered for the configured start before it was restricted are kept
+        let mut ironclad = ChoiceOutcomes::new(Choice::Start {
+            player_class: Class::Ironclad,
+            ascension: None,
+        });
+        ironclad.visits = 5.0;
+        let mut node = MonteCarloNode {
+            game: Arc::new(Box::new(FloorState::Menu)),
+            depth: 0,
+            visits: 8.0,
+            eval: 0.0,
+            children: vec![ironclad.clone(), ChoiceOutcomes::new(start)],
+        };
+        node.restrict_start(Some((Class::Ironclad, None)));
+        assert!(node.children == vec![ironclad]);
+        assert_eq!(node.visits, 5.0);
+    }
+
+    #[test]
+    fn test_iteration_budget() {
+        let config = SearchConfig {
+            time_limit: None,
+            iterations: Some(20),
+            ..SearchConfig::default()
+        };
+        let mut ai = SpireAi::with_config(FloorState::Menu, config);
+        ai.choose(&None);
+        assert_eq!(ai.tree.root.visits, 20.0);
+    }
+
+    #[test]
+    fn test_parallel_search() {
+        let config = SearchConfig {
+            time_limit: None,
+            iterations: Some(30),
+            threads: 4,
+            ..SearchConfig::default()
+        };
+        let mut ai = SpireAi::with_config(FloorState::Menu, config);
+        let choice = ai.choose(&None);
+        assert!(matches!(choice, Choice::Start { .. }));
+        assert_eq!(ai.tree.root.visits, 30.0);
+    }
+
+    #[test]
+    fn test_node_budget() {
+        let config = SearchConfig {
+            time_limit: None,
+            iterations: Some(200),
+            threads: 1,
+            max_nodes: 10,
+            ..SearchConfig::default()
+        };
+        let mut ai = SpireAi::with_config(FloorState::Menu, config);
+        ai.choose(&None);
+
+        let stats = ai.table_stats();
+        assert!(stats.size <= 10);
+        assert!(stats.evictions > 0);
+
+        // Evicted states aren't kept alive by the outcomes of their parents
+        let tree = &ai.tree;
+        for node in tree.nodes.values().chain(std::iter::once(&tree.root)) {
+            for child in &node.children {
+                for state in child.outcomes.keys() {
+                    assert!(tree.nodes.contains_key(state) || *state == tree.root.game);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        let config = SearchConfig {
+            time_limit: None,
+            iterations: Some(20),
+            threads: 1,
+            ..SearchConfig::default()
+        };
+        let mut ai = SpireAi::with_config(FloorState::Menu, config);
+        let choice = ai.choose(&None);
+
+        let decision = ai.explain(&choice, 2);
+        assert_eq!(decision.decision, 1);
+        assert_eq!(decision.floor, None);
+        assert_eq!(decision.visits, 20.0);
+        assert_eq!(decision.choices.len(), 2);
+        assert_eq!(decision.chosen, format!("{:?}", choice));
+        assert!(decision.choices[0].visits >= decision.choices[1].visits);
+        assert!(decision.choices[0].value.is_some());
+        serde_json::to_string(&decision).unwrap();
+    }
+
+    #[test]
+    fn test_outcomes_weighted_by_probability() {
+        let mut choice = choice_outcomes(
+            Choice::Proceed,
+            &[(state(true), 0.25), (state(false), 0.75)],
+        );
+
+        // The unlikely outcome is sampled far more often than its odds
+        for _ in 0..9 {
+            choice.record(&state(true), 100.0);
+        }
+        choice.record(&state(false), 0.0);
+
+        assert_eq!(choice.visits, 10.0);
+        assert!((choice.value() - 25.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_uct_converges() {
+        // Proceed is worth 15 on average, Skip is always worth 20
+        let mut node = MonteCarloNode {
+            game: Arc::new(Box::new(FloorState::Menu)),
+            depth: 0,
+            visits: 0.0,
+            eval: 0.0,
+            children: vec![
+                choice_outcomes(Choice::Proceed, &[(state(true), 0.5), (state(false), 0.5)]),
+                choice_outcomes(Choice::Skip, &[(state(true), 1.0)]),
+            ],
+        };
+        let values: HashMap<(usize, bool), f64> =
+            vec![((0, true), 30.0), ((0, false), 0.0), ((1, true), 20.0)]
+                .into_iter()
+                .collect();
+
+        for _ in 0..2000 {
+            let child = node.select(5.0).unwrap();
+            // Alternate the outcomes of the chance choice instead of sampling them
+            let won = child == 1 || node.children[0].visits % 2.0 == 0.0;
+            node.record(child, &state(won), values[&(child, won)]);
+        }
+
+        let proceed = &node.children[0];
+        let skip = &node.children[1];
+        assert_eq!(node.visits, 2000.0);
+        assert!((proceed.value() - 15.0).abs() < 1e-9);
+        assert!((skip.value() - 20.0).abs() < 1e-9);
+        assert!(skip.visits > proceed.visits * 4.0);
+    }
 }
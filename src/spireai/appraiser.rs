@@ -1,9 +1,589 @@
-/*
-pub fn rate_possibility_set(set: &GamePossibility) -> f64 {
-    panic!("Not implemented")
+use crate::models::buffs;
+use crate::models::cards::{self, BaseCard};
+use crate::models::choices::Choice;
+use crate::models::core::{Amount, CardEffect, CardType, Class, Effect, Rarity, Target, When};
+use crate::models::potions::BasePotion;
+use crate::models::relics::{self, Activation, BaseRelic};
+use crate::state::core::{Reward, RewardState};
+use crate::state::event::EventScreenState;
+use crate::state::floor::FloorState;
+use crate::state::game::{DeckCard, GameState};
+use std::cmp::Ordering;
+
+// Rates cards, relics and potions against the run they would be added to. Cards are valued by
+// what their effects do for the energy they cost, on a scale where a point is about a point of
+// damage. A card offer is rated against the average card in the deck, so that anything below zero
+// makes the deck worse and should be skipped.
+
+const DAMAGE_VALUE: f64 = 1.0;
+const BLOCK_VALUE: f64 = 0.8;
+const DRAW_VALUE: f64 = 4.0;
+const ENERGY_VALUE: f64 = 6.0;
+const ENERGY_COST: f64 = 5.0; // Taken off for every energy a card costs
+const BUFF_VALUE: f64 = 3.0; // For every stack of a buff on the player
+const DEBUFF_VALUE: f64 = 1.5; // For every stack of a debuff on a monster
+const ORB_VALUE: f64 = 5.0;
+const OTHER_VALUE: f64 = 2.0; // Effects that aren't modelled
+const AREA_MULTIPLIER: f64 = 1.5; // Hitting every monster
+const X_COST: f64 = 3.0; // The energy usually spent on X cost cards
+const POWER_TURNS: f64 = 3.0; // Powers keep paying out for the rest of the battle
+const CURSE_VALUE: f64 = -15.0;
+const DUPLICATE_FALLOFF: f64 = 0.85; // For every copy already in the deck
+const SINGING_BOWL_VALUE: f64 = 3.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct CardParts {
+    damage: f64,
+    block: f64,
+    utility: f64,
+    cost: f64,
+}
+
+fn card_parts(card: &BaseCard, upgraded: bool) -> CardParts {
+    let mut parts = CardParts {
+        cost: match card.cost {
+            Amount::X => X_COST,
+            ref cost => amount(cost, upgraded).max(0.0),
+        },
+        ..CardParts::default()
+    };
+    for effect in &card.on_play {
+        add_effect(&mut parts, effect, upgraded);
+    }
+
+    if card._type == CardType::Power {
+        parts.utility *= POWER_TURNS;
+    }
+    if card.cost == Amount::X {
+        parts.damage *= X_COST;
+        parts.block *= X_COST;
+    }
+    parts
+}
+
+fn add_effect(parts: &mut CardParts, effect: &Effect, upgraded: bool) {
+    match effect {
+        Effect::AttackDamage {
+            amount: damage,
+            times,
+            target,
+            ..
+        } => {
+            let times = match times {
+                Amount::Fixed(0) => 1.0,
+                times => amount(times, upgraded).max(1.0),
+            };
+            parts.damage += amount(damage, upgraded) * times * area(*target) * DAMAGE_VALUE
+        }
+        Effect::Damage {
+            amount: damage,
+            target,
+        } => parts.damage += amount(damage, upgraded) * area(*target) * DAMAGE_VALUE,
+        Effect::Block { amount: block, .. } => parts.block += amount(block, upgraded) * BLOCK_VALUE,
+        Effect::Draw(draw) => parts.utility += amount(draw, upgraded) * DRAW_VALUE,
+        Effect::AddEnergy(energy) => parts.utility += amount(energy, upgraded) * ENERGY_VALUE,
+        Effect::AddBuff {
+            buff,
+            amount: stacks,
+            target,
+        } => {
+            let stacks = amount(stacks, upgraded).abs().max(1.0);
+            parts.utility += match (target, buff.debuff) {
+                (Target::_Self | Target::Player, false) => stacks * BUFF_VALUE,
+                (Target::_Self | Target::Player, true) => -stacks * DEBUFF_VALUE,
+                (_, true) => stacks * DEBUFF_VALUE * area(*target),
+                (_, false) => -stacks * BUFF_VALUE,
+            }
+        }
+        Effect::ChannelOrb(_) => parts.utility += ORB_VALUE,
+        Effect::If { then, .. } => {
+            // Counted at half, since the condition won't always hold
+            let mut inner = CardParts::default();
+            for effect in then {
+                add_effect(&mut inner, effect, upgraded);
+            }
+            parts.damage += inner.damage / 2.0;
+            parts.block += inner.block / 2.0;
+            parts.utility += inner.utility / 2.0;
+        }
+        Effect::LoseHp { target, .. } if matches!(target, Target::_Self | Target::Player) => {
+            parts.utility -= OTHER_VALUE
+        }
+        _ => parts.utility += OTHER_VALUE,
+    }
+}
+
+fn area(target: Target) -> f64 {
+    if target == Target::AllMonsters {
+        AREA_MULTIPLIER
+    } else {
+        1.0
+    }
+}
+
+// The value of an amount outside of a battle, where anything that depends on the battle is
+// treated as a single point
+fn amount(amount: &Amount, upgraded: bool) -> f64 {
+    match amount {
+        Amount::Fixed(a) => *a as f64,
+        Amount::ByAsc { amount, .. } => *amount as f64,
+        Amount::Upgradable {
+            amount,
+            upgraded: a,
+        } => {
+            if upgraded {
+                *a as f64
+            } else {
+                *amount as f64
+            }
+        }
+        Amount::Sum(amounts) => amounts.iter().map(|a| self::amount(a, upgraded)).sum(),
+        Amount::Mult(amounts) => amounts.iter().map(|a| self::amount(a, upgraded)).product(),
+        Amount::NegX => -1.0,
+        _ => 1.0,
+    }
+}
+
+// What a card is worth to this run before looking at the rest of the deck. Attacks matter most
+// early on, when fights are short, and powers matter more as the fights get longer.
+pub fn card_value(card: &BaseCard, upgraded: bool, game_state: &GameState) -> f64 {
+    match card._type {
+        CardType::Curse | CardType::Status => return CURSE_VALUE,
+        _ => {}
+    }
+    if !matches!(card._class, Class::All | Class::None) && card._class != game_state.class {
+        return 0.0;
+    }
+
+    let parts = card_parts(card, upgraded);
+    let act = game_state.act.max(1) as f64;
+    let act_weight = match card._type {
+        CardType::Attack => 1.2 - 0.1 * act,
+        CardType::Power => 0.7 + 0.15 * act,
+        _ => 1.0,
+    };
+
+    (parts.damage + parts.block + parts.utility) * act_weight + rarity_bonus(card.rarity)
+        - effective_cost(parts.cost, game_state) * ENERGY_COST
+}
+
+// Energy relics make every card cheaper to fit into a turn, and Snecko Eye averages costs out
+fn effective_cost(cost: f64, game_state: &GameState) -> f64 {
+    let cost = if game_state.has_relic(relics::SNECKO_EYE) {
+        1.5
+    } else {
+        cost
+    };
+    let energy_relics = game_state
+        .relics
+        .iter()
+        .filter(|a| a.base.energy_relic)
+        .count() as f64;
+    cost * 0.9f64.powf(energy_relics)
+}
+
+// Most of what makes rarer cards good is in custom effects, which aren't modelled
+fn rarity_bonus(rarity: Rarity) -> f64 {
+    match rarity {
+        Rarity::Rare => 4.0,
+        Rarity::Uncommon => 2.0,
+        _ => 0.0,
+    }
+}
+
+// The value of the average card drawn from the deck
+pub fn deck_quality(game_state: &GameState) -> f64 {
+    if game_state.deck.is_empty() {
+        return 0.0;
+    }
+
+    let total: f64 = game_state
+        .deck
+        .values()
+        .map(|card| card_value(card.base, card.upgrades > 0, game_state))
+        .sum();
+    total / game_state.deck.len() as f64
+}
+
+// How much better the deck gets by adding the card. Damage and block are worth more to a deck
+// that is short on them, copies of a card are worth less the more of them the deck has, and cards
+// that fit the deck's archetypes are worth more.
+pub fn rate_card(card: &BaseCard, upgraded: bool, game_state: &GameState) -> f64 {
+    let mut value = card_value(card, upgraded, game_state);
+    if value > 0.0 {
+        let (damage, block) = game_state
+            .deck
+            .values()
+            .map(|card| card_parts(card.base, card.upgrades > 0))
+            .fold((0.0, 0.0), |(damage, block), parts| {
+                (damage + parts.damage, block + parts.block)
+            });
+        let parts = card_parts(card, upgraded);
+        let need = ((block + 1.0) / (damage + 1.0)).sqrt().max(0.7).min(1.4);
+        value += parts.damage * (need - 1.0) + parts.block * (1.0 / need - 1.0);
+
+        let copies = game_state.deck.values().filter(|a| a.base == card).count();
+        value *= DUPLICATE_FALLOFF.powi(copies as i32);
+    }
+
+    value + plan_bonus(&card_archetypes(card), game_state) - deck_quality(game_state)
+}
+
+pub fn rate_relic(relic: &BaseRelic, game_state: &GameState) -> f64 {
+    if !matches!(relic.class, Class::All | Class::None) && relic.class != game_state.class {
+        return 0.0;
+    }
+
+    let value = match relic.rarity {
+        Rarity::Starter => 5.0,
+        Rarity::Common => 10.0,
+        Rarity::Uncommon | Rarity::Shop => 15.0,
+        Rarity::Rare => 20.0,
+        Rarity::Boss => 25.0,
+        _ => 10.0,
+    };
+    let energy = if relic.energy_relic {
+        ENERGY_VALUE * 2.0
+    } else {
+        0.0
+    };
+    value + energy + plan_bonus(&relic_archetypes(relic), game_state)
+}
+
+// How much better the deck gets without the card
+pub fn rate_removal(card: DeckCard, game_state: &GameState) -> f64 {
+    let upgraded = game_state
+        .deck
+        .get(&card.uuid)
+        .map_or(false, |a| a.upgrades > 0);
+    deck_quality(game_state) - card_value(card.base, upgraded, game_state)
+}
+
+// Potions are only worth something if there's room for them
+pub fn rate_potion(potion: &BasePotion, game_state: &GameState) -> f64 {
+    if !game_state.potions.iter().any(|a| a.is_none())
+        || (!matches!(potion.class, Class::All | Class::None) && potion.class != game_state.class)
+    {
+        return 0.0;
+    }
+
+    match potion.rarity {
+        Rarity::Rare => 10.0,
+        Rarity::Uncommon => 7.0,
+        _ => 5.0,
+    }
 }
 
-fn rate_state(state: GameState) -> f64 {
-    panic!("Not implemented")
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Archetype {
+    Strength,
+    Exhaust,
+    Poison,
+    Shivs,
+    Discard,
+    Focus,
+    Stances,
+    Block,
+}
+
+pub const ARCHETYPES: [Archetype; 8] = [
+    Archetype::Strength,
+    Archetype::Exhaust,
+    Archetype::Poison,
+    Archetype::Shivs,
+    Archetype::Discard,
+    Archetype::Focus,
+    Archetype::Stances,
+    Archetype::Block,
+];
+
+const ARCHETYPE_SUPPORT: f64 = 4.0; // Cards and relics a deck needs to be fully committed to a plan
+const ARCHETYPE_RELIC: f64 = 2.0; // A relic counts as this many cards
+const ARCHETYPE_BONUS: f64 = 6.0; // For a card that supports a plan the deck is fully committed to
+const BLOCK_BUFFS: [&str; 6] = [
+    "Barricade",
+    "Blur",
+    "Dexterity",
+    "Juggernaut",
+    "Metallicize",
+    "Plated Armor",
+];
+
+// How committed the deck is to each archetype, from 0 to 1. Archetypes nothing supports are left
+// out, and the rest are sorted with the strongest first.
+pub fn archetypes(game_state: &GameState) -> Vec<(Archetype, f64)> {
+    let mut support = [0.0; ARCHETYPES.len()];
+    for card in game_state.deck.values() {
+        for archetype in card_archetypes(card.base) {
+            support[archetype as usize] += 1.0;
+        }
+    }
+    for relic in &game_state.relics {
+        for archetype in relic_archetypes(relic.base) {
+            support[archetype as usize] += ARCHETYPE_RELIC;
+        }
+    }
+
+    let mut found: Vec<(Archetype, f64)> = ARCHETYPES
+        .iter()
+        .zip(&support)
+        .filter(|(_, support)| **support > 0.0)
+        .map(|(archetype, support)| (*archetype, (support / ARCHETYPE_SUPPORT).min(1.0)))
+        .collect();
+    found.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    found
+}
+
+fn plan_bonus(supports: &[Archetype], game_state: &GameState) -> f64 {
+    if supports.is_empty() {
+        return 0.0;
+    }
+
+    archetypes(game_state)
+        .iter()
+        .filter(|(archetype, _)| supports.contains(archetype))
+        .map(|(_, commitment)| commitment * ARCHETYPE_BONUS)
+        .sum()
+}
+
+pub fn card_archetypes(card: &BaseCard) -> Vec<Archetype> {
+    let mut found = Vec::new();
+    for effect in &card.on_play {
+        effect_archetypes(effect, &mut found, 0);
+    }
+    if !card.on_exhaust.is_empty() {
+        found.push(Archetype::Exhaust);
+    }
+    if !card.on_discard.is_empty() {
+        found.push(Archetype::Discard);
+    }
+
+    found.sort_by_key(|a| *a as usize);
+    found.dedup();
+    found
+}
+
+pub fn relic_archetypes(relic: &BaseRelic) -> Vec<Archetype> {
+    let mut found = Vec::new();
+    for effect in &relic.effect {
+        effect_archetypes(effect, &mut found, 0);
+    }
+    match &relic.activation {
+        Activation::When(when)
+        | Activation::Counter {
+            increment: when, ..
+        } => when_archetypes(when, &mut found),
+        _ => {}
+    }
+
+    found.sort_by_key(|a| *a as usize);
+    found.dedup();
+    found
+}
+
+// Buffs the player gains are followed into their own effects, so that powers like Demon Form count
+// towards strength
+fn effect_archetypes(effect: &Effect, found: &mut Vec<Archetype>, depth: u8) {
+    match effect {
+        Effect::AddBuff { buff, target, .. } => {
+            let on_player = matches!(target, Target::_Self | Target::Player);
+            if *buff == buffs::POISON && !on_player {
+                found.push(Archetype::Poison);
+            }
+            if on_player {
+                if *buff == buffs::STRENGTH {
+                    found.push(Archetype::Strength);
+                } else if *buff == buffs::FOCUS {
+                    found.push(Archetype::Focus);
+                } else if BLOCK_BUFFS.contains(&buff.name.as_str()) {
+                    found.push(Archetype::Block);
+                }
+
+                if depth < 2 && !buff.debuff {
+                    for when_effect in &buff.effects {
+                        when_archetypes(&when_effect.when, found);
+                        for effect in &when_effect.effect {
+                            effect_archetypes(effect, found, depth + 1);
+                        }
+                    }
+                }
+            }
+        }
+        Effect::AttackDamage { amount, .. } | Effect::Damage { amount, .. }
+            if *amount == Amount::PlayerBlock =>
+        {
+            found.push(Archetype::Block)
+        }
+        Effect::Catalyst => found.push(Archetype::Poison),
+        Effect::CreateCard { name, then, .. } => {
+            if name == "Shiv" {
+                found.push(Archetype::Shivs);
+            }
+            card_effect_archetypes(then, found);
+        }
+        Effect::SetStance(_) => found.push(Archetype::Stances),
+        Effect::ChannelOrb(_) | Effect::AddOrbSlot(_) | Effect::EvokeOrb(_) => {
+            found.push(Archetype::Focus)
+        }
+        Effect::ChooseCards { then, .. } => card_effect_archetypes(then, found),
+        Effect::DoCardEffect { effect, .. } => {
+            card_effect_archetypes(std::slice::from_ref(effect), found)
+        }
+        Effect::If { then, _else, .. } => {
+            for effect in then.iter().chain(_else) {
+                effect_archetypes(effect, found, depth);
+            }
+        }
+        Effect::Repeat { effect, .. } => {
+            for effect in effect {
+                effect_archetypes(effect, found, depth);
+            }
+        }
+        Effect::RandomChance(chances) => {
+            for effect in chances.iter().flat_map(|a| &a.effect) {
+                effect_archetypes(effect, found, depth);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Exhausting or discarding other cards. A card that exhausts itself doesn't feed an exhaust deck.
+fn card_effect_archetypes(effects: &[CardEffect], found: &mut Vec<Archetype>) {
+    for effect in effects {
+        match effect {
+            CardEffect::Exhaust => found.push(Archetype::Exhaust),
+            CardEffect::Discard => found.push(Archetype::Discard),
+            CardEffect::CopyTo { then, .. } => card_effect_archetypes(then, found),
+            CardEffect::If { then, .. } => card_effect_archetypes(then, found),
+            _ => {}
+        }
+    }
+}
+
+fn when_archetypes(when: &When, found: &mut Vec<Archetype>) {
+    match when {
+        When::Exhaust => found.push(Archetype::Exhaust),
+        When::Discard => found.push(Archetype::Discard),
+        _ => {}
+    }
+}
+
+// Rates the choices that add to the run, relative to skipping them. Choices that aren't about
+// picking up a card, relic or potion aren't rated.
+pub fn rate_choice(choice: &Choice, state: &FloorState) -> Option<f64> {
+    let game_state = match state {
+        FloorState::Menu | FloorState::GameOver(..) => return None,
+        _ => state.game_state(),
+    };
+
+    match choice {
+        // The predictor adds the base card, even if an upgraded one was offered
+        Choice::AddCardToDeck(name) => Some(rate_card(cards::by_name(name), false, game_state)),
+        Choice::Skip => Some(0.0),
+        Choice::SingingBowl => Some(SINGING_BOWL_VALUE),
+        Choice::TakeReward(index) => {
+            rewards(state)
+                .and_then(|a| a.rewards.get(*index))
+                .map(|reward| match reward {
+                    Reward::Relic(relic) => rate_relic(relic, game_state),
+                    Reward::Potion(potion) => rate_potion(potion, game_state),
+                    _ => 0.0,
+                })
+        }
+        Choice::BuyCard(index) => match state {
+            FloorState::Shop(shop) => shop
+                .cards
+                .get(*index)
+                .map(|(offer, _)| rate_card(offer.base, offer.upgraded, game_state)),
+            _ => None,
+        },
+        Choice::BuyRelic(index) => match state {
+            FloorState::Shop(shop) => shop
+                .relics
+                .get(*index)
+                .map(|(relic, _)| rate_relic(relic, game_state)),
+            _ => None,
+        },
+        Choice::BuyPotion(index) => match state {
+            FloorState::Shop(shop) => shop
+                .potions
+                .get(*index)
+                .map(|(potion, _)| rate_potion(potion, game_state)),
+            _ => None,
+        },
+        Choice::BuyRemoveCard(card) => Some(rate_removal(*card, game_state)),
+        _ => None,
+    }
+}
+
+fn rewards(state: &FloorState) -> Option<&RewardState> {
+    match state {
+        FloorState::BattleRewards(state) => Some(&state.rewards),
+        FloorState::Chest(state) => state.rewards.as_ref(),
+        FloorState::Event(state) => match &state.screen_state {
+            Some(EventScreenState::Rewards(rewards)) => Some(rewards),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::cards;
+    use crate::models::core::Class;
+    use crate::state::core::Card;
+    use crate::state::game::GameState;
+
+    use super::{archetypes, card_archetypes, card_value, deck_quality, rate_card, Archetype};
+
+    #[test]
+    fn test_card_ratings() {
+        let game_state = GameState::new(Class::Ironclad, 0);
+        let strike = cards::by_name("Strike");
+        let pommel = cards::by_name("Pommel Strike");
+
+        assert!(card_value(pommel, false, &game_state) > card_value(strike, false, &game_state));
+        assert!(card_value(strike, true, &game_state) > card_value(strike, false, &game_state));
+        assert!(rate_card(pommel, false, &game_state) > 0.0);
+        assert!(rate_card(cards::by_name("Injury"), false, &game_state) < 0.0);
+        assert_eq!(
+            card_value(cards::by_name("Neutralize"), false, &game_state),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_deck_quality() {
+        let mut game_state = GameState::new(Class::Ironclad, 0);
+        let before = deck_quality(&game_state);
+        let card = Card::by_name("Injury");
+        game_state.deck.insert(card.uuid, card);
+        assert!(deck_quality(&game_state) < before);
+    }
+
+    #[test]
+    fn test_archetypes() {
+        assert_eq!(
+            card_archetypes(cards::by_name("Demon Form")),
+            vec![Archetype::Strength]
+        );
+        assert_eq!(
+            card_archetypes(cards::by_name("Blade Dance")),
+            vec![Archetype::Shivs]
+        );
+
+        let mut game_state = GameState::new(Class::Silent, 0);
+        let poison = cards::by_name("Deadly Poison");
+        let before = rate_card(poison, false, &game_state);
+        for _ in 0..4 {
+            let card = Card::by_name("Deadly Poison");
+            game_state.deck.insert(card.uuid, card);
+        }
+
+        assert_eq!(archetypes(&game_state)[0], (Archetype::Poison, 1.0));
+        // The copies make each one worth less, but the plan more than makes up for it
+        assert!(rate_card(poison, false, &game_state) > before);
+    }
 }
-*/
@@ -0,0 +1,544 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Debug};
+use uuid::Uuid;
+
+use crate::models::{
+    buffs::BUFFS,
+    cards::ALL_CARDS,
+    core::{Class, OrbType, Stance},
+    monsters::Intent,
+    potions::POTIONS,
+    relics::RELICS,
+};
+use crate::state::{
+    battle::BattleState,
+    core::Buff,
+    floor::FloorState,
+    game::GameState,
+    map::{MapNode, MapNodeIcon},
+};
+
+// Encodes a FloorState as a fixed length vector, for use by learned evaluators and policies.
+//
+// The vector is made of these sections, in order:
+//
+// screen       one-hot of the floor state variant
+// class        one-hot of the four playable classes
+// act          one-hot of acts 1 to 4
+// run          ascension, floor, hp, max hp, gold, ruby/emerald/sapphire keys
+// potions      count of each potion, by name
+// relics       1 for each relic held, by name
+// deck         count of each card, by name
+// upgrades     count of upgraded copies of each card, by name
+// map.<n>      count of each room type reachable n floors ahead, for the next LOOKAHEAD floors
+// battle       hp, max hp, block, energy, base energy, orb slots
+// stance       one-hot of the player's stance
+// orbs         count of each type of channeled orb
+// hand, draw, discard, exhaust
+//              count of each card in the pile, by name
+// player.buffs amount of each buff on the player, by name
+// monster.<n>  present, hp, max hp, block, intent one-hot, then the amount of each buff by name.
+//              Monsters are sorted by position, and there are MAX_MONSTERS slots.
+//
+// The battle sections are zero outside of battle. GameOver and Menu encode to all zeros.
+// Names are sorted, so the layout only changes when the data files do. The layout can be saved
+// next to a trained model and compared against the current one before the model is used.
+
+pub const ENCODING_VERSION: u32 = 1;
+pub const LOOKAHEAD: usize = 3;
+pub const MAX_MONSTERS: usize = 5;
+
+// Rough scales to keep the inputs close to 0..1
+const HP_SCALE: f64 = 100.0;
+const GOLD_SCALE: f64 = 100.0;
+const FLOOR_SCALE: f64 = 55.0;
+const ASC_SCALE: f64 = 20.0;
+const BUFF_SCALE: f64 = 10.0;
+
+const SCREENS: [&str; 7] = [
+    "Event",
+    "Rest",
+    "Chest",
+    "Battle",
+    "BattleRewards",
+    "Shop",
+    "Map",
+];
+const CLASSES: [Class; 4] = [
+    Class::Ironclad,
+    Class::Silent,
+    Class::Defect,
+    Class::Watcher,
+];
+const STANCES: [Stance; 4] = [Stance::None, Stance::Calm, Stance::Wrath, Stance::Divinity];
+const ORBS: [OrbType; 4] = [
+    OrbType::Lightning,
+    OrbType::Dark,
+    OrbType::Frost,
+    OrbType::Plasma,
+];
+const ICONS: [MapNodeIcon; 7] = [
+    MapNodeIcon::Monster,
+    MapNodeIcon::Elite,
+    MapNodeIcon::BurningElite,
+    MapNodeIcon::Question,
+    MapNodeIcon::Campfire,
+    MapNodeIcon::Shop,
+    MapNodeIcon::Chest,
+];
+const INTENTS: [Intent; 15] = [
+    Intent::Attack,
+    Intent::AttackBuff,
+    Intent::AttackDebuff,
+    Intent::AttackDefend,
+    Intent::Buff,
+    Intent::Debuff,
+    Intent::StrongDebuff,
+    Intent::Defend,
+    Intent::DefendDebuff,
+    Intent::DefendBuff,
+    Intent::Escape,
+    Intent::None,
+    Intent::Sleep,
+    Intent::Stun,
+    Intent::Unknown,
+];
+const PILES: [&str; 4] = ["hand", "draw", "discard", "exhaust"];
+
+lazy_static! {
+    static ref CARD_INDEX: NameIndex = NameIndex::new(ALL_CARDS.keys());
+    static ref RELIC_INDEX: NameIndex = NameIndex::new(RELICS.keys());
+    static ref POTION_INDEX: NameIndex = NameIndex::new(POTIONS.keys());
+    static ref BUFF_INDEX: NameIndex = NameIndex::new(BUFFS.keys());
+    pub static ref LAYOUT: Layout = Layout::new();
+}
+
+// A stable, sorted numbering of the names in one of the data tables
+struct NameIndex {
+    names: Vec<String>,
+    indexes: HashMap<String, usize>,
+}
+
+impl NameIndex {
+    fn new<'a, I: Iterator<Item = &'a String>>(names: I) -> Self {
+        let mut names: Vec<String> = names.cloned().collect();
+        names.sort();
+        let indexes = names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.clone(), index))
+            .collect();
+        NameIndex { names, indexes }
+    }
+
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn get(&self, name: &str) -> usize {
+        *self
+            .indexes
+            .get(name)
+            .unwrap_or_else(|| panic!("{} is missing from the encoder tables", name))
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Section {
+    pub name: String,
+    pub offset: usize,
+    pub labels: Vec<String>,
+}
+
+impl Section {
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Layout {
+    pub version: u32,
+    pub sections: Vec<Section>,
+}
+
+impl Layout {
+    fn new() -> Self {
+        let mut layout = Layout {
+            version: ENCODING_VERSION,
+            sections: Vec::new(),
+        };
+
+        layout.add("screen", labels(&SCREENS));
+        layout.add("class", debug_labels(&CLASSES));
+        layout.add("act", labels(&["1", "2", "3", "4"]));
+        layout.add(
+            "run",
+            labels(&[
+                "asc", "floor", "hp", "max_hp", "gold", "ruby", "emerald", "sapphire",
+            ]),
+        );
+        layout.add("potions", POTION_INDEX.names.clone());
+        layout.add("relics", RELIC_INDEX.names.clone());
+        layout.add("deck", CARD_INDEX.names.clone());
+        layout.add("upgrades", CARD_INDEX.names.clone());
+        for step in 0..LOOKAHEAD {
+            layout.add(&format!("map.{}", step), debug_labels(&ICONS));
+        }
+        layout.add(
+            "battle",
+            labels(&[
+                "hp",
+                "max_hp",
+                "block",
+                "energy",
+                "base_energy",
+                "orb_slots",
+            ]),
+        );
+        layout.add("stance", debug_labels(&STANCES));
+        layout.add("orbs", debug_labels(&ORBS));
+        for pile in PILES.iter() {
+            layout.add(pile, CARD_INDEX.names.clone());
+        }
+        layout.add("player.buffs", BUFF_INDEX.names.clone());
+        for slot in 0..MAX_MONSTERS {
+            let mut monster: Vec<String> = labels(&["present", "hp", "max_hp", "block"]);
+            monster.extend(INTENTS.iter().map(|a| format!("intent.{:?}", a)));
+            monster.extend(BUFF_INDEX.names.iter().map(|a| format!("buffs.{}", a)));
+            layout.add(&format!("monster.{}", slot), monster);
+        }
+
+        layout
+    }
+
+    fn add(&mut self, name: &str, labels: Vec<String>) {
+        let offset = self.len();
+        self.sections.push(Section {
+            name: name.to_string(),
+            offset,
+            labels,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.sections
+            .last()
+            .map(|a| a.offset + a.len())
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|a| a.name == name)
+    }
+
+    // Names are written as section:label, e.g. deck:Strike
+    pub fn feature_name(&self, index: usize) -> Option<String> {
+        self.sections
+            .iter()
+            .find(|a| index >= a.offset && index < a.offset + a.len())
+            .map(|a| format!("{}:{}", a.name, a.labels[index - a.offset]))
+    }
+
+    pub fn index_of(&self, feature: &str) -> Option<usize> {
+        let (name, label) = feature.split_once(':')?;
+        let section = self.section(name)?;
+        section
+            .labels
+            .iter()
+            .position(|a| a == label)
+            .map(|a| a + section.offset)
+    }
+}
+
+fn labels(names: &[&str]) -> Vec<String> {
+    names.iter().map(|a| a.to_string()).collect()
+}
+
+fn debug_labels<T: Debug>(values: &[T]) -> Vec<String> {
+    values.iter().map(|a| format!("{:?}", a)).collect()
+}
+
+pub fn encode(state: &FloorState) -> Vec<f64> {
+    let mut writer = Writer {
+        values: vec![0.0; LAYOUT.len()],
+        offset: 0,
+    };
+
+    let screen = match state {
+        FloorState::Event(_) => 0,
+        FloorState::Rest(_) => 1,
+        FloorState::Chest(_) => 2,
+        FloorState::Battle(_) => 3,
+        FloorState::BattleRewards(_) => 4,
+        FloorState::Shop(_) => 5,
+        FloorState::Map(_) => 6,
+        FloorState::GameOver(..) | FloorState::Menu => return writer.values,
+    };
+
+    writer.section("screen");
+    writer.one_hot(Some(screen), SCREENS.len());
+    encode_game(state.game_state(), &mut writer);
+
+    writer.section("battle");
+    match state {
+        FloorState::Battle(battle) => encode_battle(battle, &mut writer),
+        _ => writer.offset = LAYOUT.len(),
+    }
+
+    debug_assert_eq!(writer.offset, LAYOUT.len());
+    writer.values
+}
+
+fn encode_game(game_state: &GameState, writer: &mut Writer) {
+    writer.section("class");
+    writer.one_hot(
+        CLASSES.iter().position(|a| *a == game_state.class),
+        CLASSES.len(),
+    );
+    writer.section("act");
+    writer.one_hot((game_state.act as usize).checked_sub(1), 4);
+
+    writer.section("run");
+    let keys = game_state.keys;
+    writer.scalar(game_state.asc as f64 / ASC_SCALE);
+    writer.scalar(game_state.map.floor.max(0) as f64 / FLOOR_SCALE);
+    writer.scalar(game_state.hp.amount as f64 / HP_SCALE);
+    writer.scalar(game_state.hp.max as f64 / HP_SCALE);
+    writer.scalar(game_state.gold as f64 / GOLD_SCALE);
+    writer.flag(keys.map(|a| a.ruby).unwrap_or(false));
+    writer.flag(keys.map(|a| a.emerald).unwrap_or(false));
+    writer.flag(keys.map(|a| a.sapphire).unwrap_or(false));
+
+    writer.section("potions");
+    writer.counts(
+        game_state
+            .potions
+            .iter()
+            .flatten()
+            .map(|a| POTION_INDEX.get(&a.name)),
+        POTION_INDEX.len(),
+    );
+    writer.section("relics");
+    writer.counts(
+        game_state
+            .relics
+            .iter()
+            .map(|a| RELIC_INDEX.get(&a.base.name)),
+        RELIC_INDEX.len(),
+    );
+    writer.section("deck");
+    writer.counts(
+        game_state
+            .deck
+            .values()
+            .map(|a| CARD_INDEX.get(&a.base.name)),
+        CARD_INDEX.len(),
+    );
+    writer.section("upgrades");
+    writer.counts(
+        game_state
+            .deck
+            .values()
+            .filter(|a| a.upgrades > 0)
+            .map(|a| CARD_INDEX.get(&a.base.name)),
+        CARD_INDEX.len(),
+    );
+
+    let map = &game_state.map;
+    let mut reachable: Vec<MapNode> = match map.current_node() {
+        Some(node) => next_nodes(map.nodes.as_ref(), node),
+        None => map
+            .nodes
+            .iter()
+            .flatten()
+            .filter(|a| a.y == 0)
+            .copied()
+            .collect(),
+    };
+    for step in 0..LOOKAHEAD {
+        writer.section(&format!("map.{}", step));
+        writer.counts(
+            reachable
+                .iter()
+                .filter_map(|node| ICONS.iter().position(|a| *a == node.icon)),
+            ICONS.len(),
+        );
+        reachable = reachable
+            .iter()
+            .flat_map(|node| next_nodes(map.nodes.as_ref(), *node))
+            .collect();
+        reachable.sort_by_key(|a| a.index());
+        reachable.dedup();
+    }
+}
+
+fn next_nodes(nodes: &[Option<MapNode>], node: MapNode) -> Vec<MapNode> {
+    let index = node.index();
+    let edges = [
+        (node.left, index + 6),
+        (node.up, index + 7),
+        (node.right, index + 8),
+    ];
+    edges
+        .iter()
+        .filter(|(edge, _)| *edge)
+        .filter_map(|(_, next)| nodes.get(*next).copied().flatten())
+        .collect()
+}
+
+fn encode_battle(battle: &BattleState, writer: &mut Writer) {
+    let player = &battle.player;
+    writer.scalar(player.hp.amount as f64 / HP_SCALE);
+    writer.scalar(player.hp.max as f64 / HP_SCALE);
+    writer.scalar(player.block as f64 / HP_SCALE);
+    writer.scalar(battle.energy as f64);
+    writer.scalar(battle.base_energy as f64);
+    writer.scalar(battle.orb_slots as f64);
+
+    writer.section("stance");
+    writer.one_hot(
+        STANCES.iter().position(|a| *a == battle.stance),
+        STANCES.len(),
+    );
+    writer.section("orbs");
+    writer.counts(
+        battle
+            .orbs
+            .iter()
+            .filter_map(|orb| ORBS.iter().position(|a| *a == orb.base)),
+        ORBS.len(),
+    );
+
+    let card_index = |uuid: &Uuid| CARD_INDEX.get(&battle.cards[uuid].base.name);
+    writer.section("hand");
+    writer.counts(battle.hand.iter().map(card_index), CARD_INDEX.len());
+    writer.section("draw");
+    writer.counts(battle.draw.iter().map(card_index), CARD_INDEX.len());
+    writer.section("discard");
+    writer.counts(battle.discard.iter().map(card_index), CARD_INDEX.len());
+    writer.section("exhaust");
+    writer.counts(battle.exhaust.iter().map(card_index), CARD_INDEX.len());
+
+    writer.section("player.buffs");
+    writer.buffs(&player.buffs);
+
+    let mut monsters: Vec<_> = battle.monsters.values().collect();
+    monsters.sort_by_key(|a| a.position);
+    for slot in 0..MAX_MONSTERS {
+        writer.section(&format!("monster.{}", slot));
+        match monsters.get(slot) {
+            Some(monster) => {
+                let creature = &monster.creature;
+                writer.flag(true);
+                writer.scalar(creature.hp.amount as f64 / HP_SCALE);
+                writer.scalar(creature.hp.max as f64 / HP_SCALE);
+                writer.scalar(creature.block as f64 / HP_SCALE);
+                writer.one_hot(
+                    INTENTS.iter().position(|a| *a == monster.intent),
+                    INTENTS.len(),
+                );
+                writer.buffs(&creature.buffs);
+            }
+            None => writer.offset += 4 + INTENTS.len() + BUFF_INDEX.len(),
+        }
+    }
+}
+
+struct Writer {
+    values: Vec<f64>,
+    offset: usize,
+}
+
+impl Writer {
+    // Catches the encoder and the layout drifting apart
+    fn section(&self, name: &str) {
+        debug_assert_eq!(
+            Some(self.offset),
+            LAYOUT.section(name).map(|a| a.offset),
+            "Encoder is out of step with the layout at {}",
+            name
+        );
+    }
+
+    fn scalar(&mut self, value: f64) {
+        self.values[self.offset] = value;
+        self.offset += 1;
+    }
+
+    fn flag(&mut self, value: bool) {
+        self.scalar(if value { 1.0 } else { 0.0 })
+    }
+
+    fn one_hot(&mut self, index: Option<usize>, len: usize) {
+        if let Some(index) = index {
+            self.values[self.offset + index] = 1.0;
+        }
+        self.offset += len;
+    }
+
+    fn counts<I: Iterator<Item = usize>>(&mut self, indexes: I, len: usize) {
+        for index in indexes {
+            self.values[self.offset + index] += 1.0;
+        }
+        self.offset += len;
+    }
+
+    fn buffs(&mut self, buffs: &[Buff]) {
+        for buff in buffs {
+            self.values[self.offset + BUFF_INDEX.get(&buff.base.name)] +=
+                buff.vars.x as f64 / BUFF_SCALE;
+        }
+        self.offset += BUFF_INDEX.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::core::Class;
+    use crate::state::floor::FloorState;
+    use crate::state::game::GameState;
+
+    use super::{encode, Layout, LAYOUT};
+
+    fn map_state() -> FloorState {
+        FloorState::Map(GameState::new(Class::Silent, 3))
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let encoded = encode(&map_state());
+        assert_eq!(encoded.len(), LAYOUT.len());
+        assert_eq!(encoded, encode(&map_state()));
+
+        let index = |name: &str| LAYOUT.index_of(name).unwrap();
+        assert_eq!(encoded[index("screen:Map")], 1.0);
+        assert_eq!(encoded[index("class:Silent")], 1.0);
+        assert_eq!(encoded[index("deck:Strike")], 5.0);
+        assert_eq!(encoded[index("deck:Neutralize")], 1.0);
+        assert_eq!(encoded[index("battle:hp")], 0.0);
+
+        assert!(encode(&FloorState::Menu).iter().all(|a| *a == 0.0));
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        for index in 0..LAYOUT.len() {
+            let name = LAYOUT.feature_name(index).unwrap();
+            assert_eq!(LAYOUT.index_of(&name), Some(index), "{}", name);
+        }
+        assert_eq!(LAYOUT.feature_name(LAYOUT.len()), None);
+
+        let serialized = ron::ser::to_string(&*LAYOUT).unwrap();
+        let layout: Layout = ron::de::from_str(&serialized).unwrap();
+        assert_eq!(layout, *LAYOUT);
+    }
+}
@@ -253,6 +253,8 @@ pub fn all_choices(state: &FloorState) -> Vec<Choice> {
                 choices.extend(get_reward_choices(reward, &shop.game_state))
             }
             ShopScreenState::InShop => {
+                choices.push(Choice::Proceed);
+
                 for (index, (_, cost)) in shop.cards.iter().enumerate() {
                     if *cost <= shop.game_state.gold {
                         choices.push(Choice::BuyCard(index))
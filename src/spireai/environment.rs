@@ -0,0 +1,605 @@
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::encoder::{self, MAX_MONSTERS};
+use super::evaluator::{Evaluator, FloorEvaluator};
+use super::references::{CardReference, MonsterReference};
+use super::{enumerator, predictor};
+use crate::models::acts::{MonsterSet, ACTS};
+use crate::models::choices::Choice;
+use crate::models::core::{Class, FightType};
+use crate::models::monsters::MONSTERS;
+use crate::state::battle::BattleState;
+use crate::state::core::{Card, Relic};
+use crate::state::floor::{FloorState, GamePossibility};
+use crate::state::game::GameState;
+use crate::state::probability::{self, Probability};
+
+// A reinforcement learning environment over the same rules the bot searches with.
+//
+// Every choice maps to an index in a fixed action space. Each kind of choice has its own block
+// of actions, and the position inside the block always means the same thing for the same state.
+// A choice that doesn't fit in its block can't be taken, and is left out of the mask.
+//
+// The environment either plays whole runs, rewarded by the change in the evaluation, or single
+// fights, rewarded by the change in the player's hp. The rewards of a fight add up to minus the hp
+// it cost, and it ends as soon as the battle is over.
+
+pub const MAX_POTIONS: usize = 5;
+pub const MAX_HAND: usize = 10;
+const TARGETS: usize = 1 + MAX_MONSTERS;
+
+// The position inside each block comes from:
+// - the slot, index or map x of choices that have one (potions, rewards, shop items, nodes)
+// - the hand slot of a card, with the hand sorted by name, upgrades and uuid
+// - the target, where 0 is no target and monsters are numbered by position, like the encoder
+// - for Event, the position of the option in the event's definition
+// - for SelectCards and Scry, a bit for the position of each chosen card among the cards offered
+// - for BuyRemoveCard, the deck slot of the card, with the deck sorted like the hand
+// - for DeckSelect, the rank of the chosen deck slots among the sets of the same size, so that a
+//   single card is its deck slot and a pair (a, b) with a < b is b * (b - 1) / 2 + a
+// - otherwise, the order the choice was enumerated in among choices of the same kind, which only
+//   matters for AddCardToDeck, where it follows the order of the offers
+const ACTION_KINDS: [(&str, usize); 31] = [
+    ("DrinkPotion", MAX_POTIONS * TARGETS),
+    ("DiscardPotion", MAX_POTIONS),
+    ("PlayCard", MAX_HAND * TARGETS),
+    ("Event", 10),
+    ("NavigateToNode", 7),
+    ("TakeReward", 10),
+    ("AddCardToDeck", 5),
+    ("SelectCards", 64),
+    ("Scry", 64),
+    ("BuyCard", 10),
+    ("BuyRelic", 5),
+    ("BuyPotion", 5),
+    ("BuyRemoveCard", 100),
+    ("DeckSelect", 100),
+    ("OpenChest", 1),
+    ("Rest", 1),
+    ("Smith", 1),
+    ("Lift", 1),
+    ("Dig", 1),
+    ("Recall", 1),
+    ("Toke", 1),
+    ("EnterShop", 1),
+    ("End", 1),
+    ("Proceed", 1),
+    ("Skip", 1),
+    ("SingingBowl", 1),
+    ("WishPlated", 1),
+    ("WishStrength", 1),
+    ("WishGold", 1),
+    ("StanceCalm", 1),
+    ("StanceWrath", 1),
+];
+
+pub fn action_count() -> usize {
+    ACTION_KINDS.iter().map(|(_, len)| len).sum()
+}
+
+// e.g. PlayCard:13
+pub fn action_name(action: usize) -> Option<String> {
+    let mut offset = 0;
+    for (name, len) in ACTION_KINDS.iter() {
+        if action < offset + len {
+            return Some(format!("{}:{}", name, action - offset));
+        }
+        offset += len;
+    }
+    None
+}
+
+fn kind(choice: &Choice) -> Option<usize> {
+    let name = match choice {
+        Choice::Start { .. } | Choice::State => return None,
+        Choice::DrinkPotion { .. } => "DrinkPotion",
+        Choice::DiscardPotion { .. } => "DiscardPotion",
+        Choice::PlayCard { .. } => "PlayCard",
+        Choice::Event(_) => "Event",
+        Choice::NavigateToNode(_) => "NavigateToNode",
+        Choice::TakeReward(_) => "TakeReward",
+        Choice::AddCardToDeck(_) => "AddCardToDeck",
+        Choice::SelectCards(_) => "SelectCards",
+        Choice::Scry(_) => "Scry",
+        Choice::BuyCard(_) => "BuyCard",
+        Choice::BuyRelic(_) => "BuyRelic",
+        Choice::BuyPotion(_) => "BuyPotion",
+        Choice::BuyRemoveCard(_) => "BuyRemoveCard",
+        Choice::DeckSelect(..) => "DeckSelect",
+        Choice::OpenChest => "OpenChest",
+        Choice::Rest => "Rest",
+        Choice::Smith => "Smith",
+        Choice::Lift => "Lift",
+        Choice::Dig => "Dig",
+        Choice::Recall => "Recall",
+        Choice::Toke => "Toke",
+        Choice::EnterShop => "EnterShop",
+        Choice::End => "End",
+        Choice::Proceed => "Proceed",
+        Choice::Skip => "Skip",
+        Choice::SingingBowl => "SingingBowl",
+        Choice::WishPlated => "WishPlated",
+        Choice::WishStrength => "WishStrength",
+        Choice::WishGold => "WishGold",
+        Choice::StanceCalm => "StanceCalm",
+        Choice::StanceWrath => "StanceWrath",
+    };
+    ACTION_KINDS.iter().position(|(a, _)| *a == name)
+}
+
+// The action index of each of the choices, or None if it doesn't fit in the action space
+pub fn actions(state: &FloorState, choices: &[Choice]) -> Vec<Option<usize>> {
+    let mut ordinals = [0; ACTION_KINDS.len()];
+    choices
+        .iter()
+        .map(|choice| {
+            let kind = kind(choice)?;
+            let ordinal = ordinals[kind];
+            ordinals[kind] += 1;
+
+            let slot = match choice {
+                Choice::DrinkPotion { slot, target } => {
+                    slot * TARGETS + target_slot(state, target)?
+                }
+                Choice::DiscardPotion { slot } => *slot,
+                Choice::PlayCard { card, target } => {
+                    hand_slot(state, card.uuid)? * TARGETS + target_slot(state, target)?
+                }
+                Choice::NavigateToNode(x) => *x as usize,
+                Choice::TakeReward(index)
+                | Choice::BuyCard(index)
+                | Choice::BuyRelic(index)
+                | Choice::BuyPotion(index) => *index,
+                Choice::Event(name) => event_slot(state, name)?,
+                Choice::SelectCards(cards) | Choice::Scry(cards) => offered_slots(state, cards)?,
+                Choice::BuyRemoveCard(card) => deck_slot(state, card.uuid)?,
+                Choice::DeckSelect(cards, _) => {
+                    let mut slots = cards
+                        .iter()
+                        .map(|card| deck_slot(state, card.uuid))
+                        .collect::<Option<Vec<usize>>>()?;
+                    slots.sort_unstable();
+                    slots
+                        .iter()
+                        .enumerate()
+                        .map(|(index, slot)| binomial(*slot, index + 1))
+                        .sum()
+                }
+                _ => ordinal,
+            };
+
+            let (_, len) = ACTION_KINDS[kind];
+            if slot < len {
+                let offset: usize = ACTION_KINDS[..kind].iter().map(|(_, len)| len).sum();
+                Some(offset + slot)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn hand_slot(state: &FloorState, card: Uuid) -> Option<usize> {
+    if let FloorState::Battle(battle) = state {
+        let mut hand: Vec<_> = battle.hand.iter().map(|uuid| &battle.cards[uuid]).collect();
+        hand.sort_by(|a, b| {
+            (&a.base.name, a.upgrades, a.uuid).cmp(&(&b.base.name, b.upgrades, b.uuid))
+        });
+        hand.iter().position(|a| a.uuid == card)
+    } else {
+        None
+    }
+}
+
+fn event_slot(state: &FloorState, name: &str) -> Option<usize> {
+    if let FloorState::Event(event) = state {
+        event.base.choices.iter().position(|a| a.name == name)
+    } else {
+        None
+    }
+}
+
+fn offered_slots(state: &FloorState, cards: &[CardReference]) -> Option<usize> {
+    let offered = match state {
+        FloorState::Battle(battle) => &battle.card_choose.as_ref()?.choices,
+        _ => return None,
+    };
+    cards.iter().try_fold(0, |slots, card| {
+        let position = offered.iter().position(|a| a.uuid == card.uuid)?;
+        // Far past the end of the block, and too far to shift
+        if position < usize::BITS as usize {
+            Some(slots | 1 << position)
+        } else {
+            None
+        }
+    })
+}
+
+fn deck_slot(state: &FloorState, card: Uuid) -> Option<usize> {
+    let mut deck: Vec<&Card> = state.game_state().deck.values().collect();
+    deck.sort_by(|a, b| {
+        (&a.base.name, a.upgrades, a.uuid).cmp(&(&b.base.name, b.upgrades, b.uuid))
+    });
+    deck.iter().position(|a| a.uuid == card)
+}
+
+// The number of ways to choose k of n
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+    (0..k).fold(1, |total, i| total * (n - i) / (i + 1))
+}
+
+fn target_slot(state: &FloorState, target: &Option<MonsterReference>) -> Option<usize> {
+    let target = match target {
+        Some(target) => target,
+        None => return Some(0),
+    };
+    if let FloorState::Battle(battle) = state {
+        let mut monsters: Vec<_> = battle.monsters.values().collect();
+        monsters.sort_by_key(|a| a.position);
+        monsters
+            .iter()
+            .position(|a| a.uuid == target.uuid)
+            .map(|a| a + 1)
+    } else {
+        None
+    }
+}
+
+// A fight to start a battle environment from. Without a deck or relics, the class's starters are
+// used.
+#[derive(Clone, Debug)]
+pub struct Encounter {
+    pub class: Class,
+    pub asc: u8,
+    pub deck: Option<Vec<String>>, // Card names, with a + after upgraded cards, e.g. Bash+
+    pub relics: Option<Vec<String>>,
+    pub hp: Option<u16>,
+    pub monsters: MonsterSet,
+    pub fight_type: FightType,
+}
+
+impl Encounter {
+    // A boss from any of the acts, or else a single monster
+    pub fn named(class: Class, asc: u8, name: &str) -> Option<Encounter> {
+        let (monsters, fight_type) = match ACTS
+            .iter()
+            .flat_map(|act| &act.bosses)
+            .find(|boss| boss.name == name)
+        {
+            Some(boss) => (boss.monsters.clone(), FightType::Boss),
+            None if MONSTERS.contains_key(name) => {
+                (MonsterSet::Fixed(vec![name.to_string()]), FightType::Common)
+            }
+            None => return None,
+        };
+
+        Some(Encounter {
+            class,
+            asc,
+            deck: None,
+            relics: None,
+            hp: None,
+            monsters,
+            fight_type,
+        })
+    }
+
+    fn game_state(&self) -> GameState {
+        let mut game_state = GameState::new(self.class, self.asc);
+        if let Some(deck) = &self.deck {
+            game_state.deck = deck
+                .iter()
+                .map(|name| {
+                    let card = match name.strip_suffix('+') {
+                        Some(name) => {
+                            let mut card = Card::by_name(name);
+                            card.upgrade();
+                            card
+                        }
+                        None => Card::by_name(name),
+                    };
+                    (card.uuid, card)
+                })
+                .collect();
+        }
+        if let Some(relics) = &self.relics {
+            game_state.relics = relics.iter().map(|name| Relic::by_name(name)).collect();
+            game_state.seen_relics = game_state.relics.iter().map(|a| a.base).collect();
+        }
+        if let Some(hp) = self.hp {
+            game_state.hp.amount = hp.min(game_state.hp.max);
+        }
+        game_state
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Run,
+    Battle,
+}
+
+pub struct Step {
+    pub observation: Vec<f64>,
+    pub reward: f64, // The change in the evaluation of the state
+    pub done: bool,
+}
+
+pub struct Environment {
+    mode: Mode,
+    state: FloorState,
+    choices: Vec<Choice>,
+    actions: Vec<Option<usize>>,
+    rng: StdRng,
+    evaluator: Arc<dyn Evaluator>,
+    value: f64,
+}
+
+impl Environment {
+    pub fn new(evaluator: Arc<dyn Evaluator>) -> Self {
+        Self {
+            mode: Mode::Run,
+            state: FloorState::Menu,
+            choices: vec![],
+            actions: vec![],
+            rng: StdRng::seed_from_u64(0),
+            evaluator,
+            value: 0.0,
+        }
+    }
+
+    // The seed decides every random outcome of the run, given the same actions. The uuids of new
+    // cards and monsters come from the thread's generator, which is reseeded from the environment's
+    // before every change, so other code on the thread doesn't change the run.
+    pub fn reset(&mut self, class: Class, asc: u8, seed: u64) -> Vec<f64> {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.mode = Mode::Run;
+        self.state = FloorState::Menu;
+        self.apply(Choice::Start {
+            player_class: class,
+            ascension: Some(asc),
+        });
+        self.observation()
+    }
+
+    pub fn step(&mut self, action: usize) -> Result<Step, String> {
+        let index = self
+            .actions
+            .iter()
+            .position(|a| *a == Some(action))
+            .ok_or_else(|| {
+                format!(
+                    "{} is not a legal action",
+                    action_name(action).unwrap_or_else(|| action.to_string())
+                )
+            })?;
+
+        let previous = self.value;
+        self.apply(self.choices[index].clone());
+        Ok(Step {
+            observation: self.observation(),
+            reward: self.value - previous,
+            done: self.done(),
+        })
+    }
+
+    pub fn reset_battle(&mut self, encounter: &Encounter, seed: u64) -> Vec<f64> {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.mode = Mode::Battle;
+
+        probability::seed_thread(self.rng.gen());
+        let mut probability = Probability::with_seed(self.rng.gen());
+        let monsters = predictor::eval_monster_set(&encounter.monsters, &mut probability);
+        let battle = BattleState::new(
+            encounter.game_state(),
+            &monsters,
+            encounter.fight_type,
+            &mut probability,
+        );
+        self.set_state(FloorState::Battle(battle));
+        self.observation()
+    }
+
+    fn apply(&mut self, choice: Choice) {
+        probability::seed_thread(self.rng.gen());
+        let mut possibility = GamePossibility {
+            state: self.state.clone(),
+            probability: Probability::with_seed(self.rng.gen()),
+        };
+        predictor::predict_outcome(choice, &mut possibility);
+        self.set_state(possibility.state);
+    }
+
+    fn set_state(&mut self, state: FloorState) {
+        self.state = state;
+        self.value = self.value();
+        self.choices = if self.done() {
+            vec![]
+        } else {
+            enumerator::all_choices(&self.state)
+        };
+        self.actions = actions(&self.state, &self.choices);
+    }
+
+    pub fn observation(&self) -> Vec<f64> {
+        encoder::encode(&self.state)
+    }
+
+    pub fn mask(&self) -> Vec<bool> {
+        let mut mask = vec![false; action_count()];
+        for action in self.actions.iter().flatten() {
+            mask[*action] = true;
+        }
+        mask
+    }
+
+    fn value(&self) -> f64 {
+        match (self.mode, &self.state) {
+            (Mode::Run, state) => self.evaluator.evaluate(state),
+            (Mode::Battle, FloorState::Battle(battle)) => battle.player.hp.amount as f64,
+            (Mode::Battle, FloorState::GameOver(false, _)) => 0.0,
+            (Mode::Battle, FloorState::GameOver(true, _)) | (Mode::Battle, FloorState::Menu) => {
+                self.value
+            }
+            (Mode::Battle, state) => state.game_state().hp.amount as f64,
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        match (self.mode, &self.state) {
+            (_, FloorState::GameOver(..)) => true,
+            (Mode::Battle, FloorState::Battle(battle)) => battle.battle_over,
+            (Mode::Battle, _) => true,
+            (Mode::Run, _) => false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn state(&self) -> &FloorState {
+        &self.state
+    }
+
+    pub fn choice(&self, action: usize) -> Option<&Choice> {
+        self.actions
+            .iter()
+            .position(|a| *a == Some(action))
+            .map(|index| &self.choices[index])
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new(Arc::new(FloorEvaluator))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use crate::models::acts::MonsterSet;
+    use crate::models::choices::Choice;
+    use crate::models::core::{Class, DeckOperation};
+    use crate::spireai::encoder::LAYOUT;
+    use crate::state::floor::FloorState;
+    use crate::state::game::GameState;
+
+    use crate::state::probability::new_uuid;
+
+    use super::{action_count, action_name, actions, Encounter, Environment, Mode};
+
+    // Always takes the last legal action, so that the run doesn't end at the first choice
+    fn play(env: &mut Environment, steps: usize) -> Vec<Vec<f64>> {
+        let mut observations = vec![env.observation()];
+        for _ in 0..steps {
+            if env.done() {
+                break;
+            }
+            let action = match env.mask().iter().rposition(|a| *a) {
+                Some(action) => action,
+                None => break,
+            };
+            observations.push(env.step(action).unwrap().observation);
+        }
+        observations
+    }
+
+    #[test]
+    fn test_action_names() {
+        let names: Vec<String> = (0..action_count())
+            .map(|a| action_name(a).unwrap())
+            .collect();
+        assert_eq!(names[0], "DrinkPotion:0");
+        assert_eq!(names.last().unwrap(), "StanceWrath:0");
+        assert_eq!(action_name(action_count()), None);
+    }
+
+    #[test]
+    fn test_slots_ignore_choice_order() {
+        let state = FloorState::Map(GameState::new(Class::Ironclad, 0));
+        let mut choices: Vec<Choice> = state
+            .game_state()
+            .deck()
+            .combinations(2)
+            .map(|cards| Choice::DeckSelect(cards, DeckOperation::Remove))
+            .collect();
+        let forward = actions(&state, &choices);
+        choices.reverse();
+        let mut backward = actions(&state, &choices);
+        backward.reverse();
+
+        assert_eq!(forward, backward);
+        assert_eq!(forward.iter().flatten().unique().count(), 45);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut env = Environment::default();
+        let observation = env.reset(Class::Ironclad, 0, 7);
+        assert_eq!(observation.len(), LAYOUT.len());
+        assert!(!env.done());
+
+        let mask = env.mask();
+        assert_eq!(mask.len(), action_count());
+        let legal: Vec<usize> = (0..mask.len()).filter(|a| mask[*a]).collect();
+        assert!(!legal.is_empty());
+        for action in &legal {
+            assert!(env.choice(*action).is_some());
+        }
+
+        let illegal = (0..mask.len()).find(|a| !mask[*a]).unwrap();
+        assert!(env.step(illegal).is_err());
+
+        let step = env.step(legal[0]).unwrap();
+        assert_eq!(step.observation, env.observation());
+        assert_eq!(step.done, env.done());
+    }
+
+    #[test]
+    fn test_battle() {
+        let boss = Encounter::named(Class::Silent, 0, "Hexaghost").unwrap();
+        assert_eq!(
+            boss.monsters,
+            MonsterSet::Fixed(vec![String::from("Hexaghost")])
+        );
+        assert!(Encounter::named(Class::Silent, 0, "Nobody").is_none());
+
+        let mut encounter = Encounter::named(Class::Ironclad, 0, "Jaw Worm").unwrap();
+        encounter.deck = Some(vec![String::from("Strike"), String::from("Bash+")]);
+        encounter.hp = Some(50);
+
+        let mut env = Environment::default();
+        let observation = env.reset_battle(&encounter, 3);
+        assert_eq!(env.mode(), Mode::Battle);
+        assert!(!env.done());
+        assert_eq!(observation[LAYOUT.index_of("battle:hp").unwrap()], 0.5);
+        assert_eq!(
+            observation[LAYOUT.index_of("monster.0:present").unwrap()],
+            1.0
+        );
+        assert!(env.mask().iter().any(|a| *a));
+    }
+
+    #[test]
+    fn test_same_seed_same_run() {
+        let mut first = Environment::default();
+        first.reset(Class::Silent, 0, 42);
+        let trajectory = play(&mut first, 40);
+
+        // Draws from the thread's generator in between shouldn't matter
+        new_uuid();
+        let mut second = Environment::default();
+        second.reset(Class::Silent, 0, 42);
+        assert_eq!(play(&mut second, 40), trajectory);
+        assert!(first.state() == second.state());
+    }
+}
@@ -0,0 +1,197 @@
+use ron::de::from_reader;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs::File, path::Path};
+
+use crate::models::core::{CardType, Rarity};
+use crate::state::floor::FloorState;
+
+pub const DEATH_VALUE: f64 = 0.0;
+pub const WIN_VALUE: f64 = 10000.0;
+pub const HEART_VALUE: f64 = 20000.0;
+
+// Estimates how good a state is for the run. Values are on the scale of 100 per floor climbed.
+pub trait Evaluator: Send + Sync {
+    // Only called for states where the run is still going
+    fn evaluate_run(&self, state: &FloorState) -> f64;
+
+    fn evaluate(&self, state: &FloorState) -> f64 {
+        match state {
+            FloorState::GameOver(true, true) => HEART_VALUE,
+            FloorState::GameOver(true, false) => WIN_VALUE,
+            FloorState::GameOver(false, _) | FloorState::Menu => DEATH_VALUE,
+            _ => self.evaluate_run(state),
+        }
+    }
+}
+
+// The original heuristic: how far the run got, and how much hp is left
+pub struct FloorEvaluator;
+
+impl Evaluator for FloorEvaluator {
+    fn evaluate_run(&self, state: &FloorState) -> f64 {
+        let game_state = state.game_state();
+        game_state.map.floor as f64 * 100.0 + game_state.hp.amount as f64
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Features {
+    pub floor: f64,
+    pub hp: f64,
+    pub max_hp: f64,
+    pub gold: f64,
+    pub relics: f64,
+    pub potions: f64,
+    pub keys: f64,
+    pub deck_size: f64,
+    pub upgrades: f64,
+    pub starter_cards: f64,
+    pub curses: f64,
+}
+
+impl Features {
+    pub fn new(state: &FloorState) -> Self {
+        let game_state = state.game_state();
+        let hp = match state {
+            FloorState::Battle(battle) => battle.player.hp,
+            _ => game_state.hp,
+        };
+        let cards = || game_state.deck.values();
+
+        Features {
+            floor: game_state.map.floor as f64,
+            hp: hp.amount as f64,
+            max_hp: hp.max as f64,
+            gold: game_state.gold as f64,
+            relics: game_state.relics.len() as f64,
+            potions: game_state.potions.iter().filter(|a| a.is_some()).count() as f64,
+            keys: game_state
+                .keys
+                .map(|k| (k.ruby as u8 + k.emerald as u8 + k.sapphire as u8) as f64)
+                .unwrap_or(0.0),
+            deck_size: game_state.deck.len() as f64,
+            upgrades: cards().filter(|a| a.upgrades > 0).count() as f64,
+            starter_cards: cards().filter(|a| a.base.rarity == Rarity::Starter).count() as f64,
+            curses: cards().filter(|a| a.base._type == CardType::Curse).count() as f64,
+        }
+    }
+}
+
+// One weight per feature, plus a constant
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Weights {
+    pub bias: f64,
+    pub floor: f64,
+    pub hp: f64,
+    pub max_hp: f64,
+    pub gold: f64,
+    pub relics: f64,
+    pub potions: f64,
+    pub keys: f64,
+    pub deck_size: f64,
+    pub upgrades: f64,
+    pub starter_cards: f64,
+    pub curses: f64,
+}
+
+impl Weights {
+    // Hand tuned so that a floor is worth about as much as a relic, or 100 hp
+    pub fn heuristic() -> Self {
+        Weights {
+            bias: 0.0,
+            floor: 100.0,
+            hp: 1.0,
+            max_hp: 0.5,
+            gold: 0.1,
+            relics: 25.0,
+            potions: 8.0,
+            keys: 40.0,
+            deck_size: -1.0,
+            upgrades: 6.0,
+            starter_cards: -4.0,
+            curses: -25.0,
+        }
+    }
+
+    pub fn apply(&self, features: &Features) -> f64 {
+        self.bias
+            + self.floor * features.floor
+            + self.hp * features.hp
+            + self.max_hp * features.max_hp
+            + self.gold * features.gold
+            + self.relics * features.relics
+            + self.potions * features.potions
+            + self.keys * features.keys
+            + self.deck_size * features.deck_size
+            + self.upgrades * features.upgrades
+            + self.starter_cards * features.starter_cards
+            + self.curses * features.curses
+    }
+}
+
+pub struct LinearEvaluator {
+    pub weights: Weights,
+}
+
+impl LinearEvaluator {
+    pub fn heuristic() -> Self {
+        LinearEvaluator {
+            weights: Weights::heuristic(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let weights = from_reader(file)?;
+        Ok(LinearEvaluator { weights })
+    }
+}
+
+impl Evaluator for LinearEvaluator {
+    fn evaluate_run(&self, state: &FloorState) -> f64 {
+        self.weights.apply(&Features::new(state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::models::core::Class;
+    use crate::state::floor::FloorState;
+    use crate::state::game::GameState;
+
+    use super::{Evaluator, FloorEvaluator, LinearEvaluator, Weights};
+
+    #[test]
+    fn can_parse() -> Result<(), String> {
+        match LinearEvaluator::load(&Path::new("data").join("weights.ron")) {
+            Ok(evaluator) => {
+                assert_eq!(evaluator.weights, Weights::heuristic());
+                Ok(())
+            }
+            Err(err) => Err(format!("{:?}", err)),
+        }
+    }
+
+    #[test]
+    fn test_terminal_states() {
+        let evaluators: Vec<Box<dyn Evaluator>> = vec![
+            Box::new(FloorEvaluator),
+            Box::new(LinearEvaluator::heuristic()),
+        ];
+        let mut game_state = GameState::new(Class::Ironclad, 0);
+        game_state.map.floor = 5;
+        let running = FloorState::Map(game_state);
+
+        for evaluator in evaluators {
+            let death = evaluator.evaluate(&FloorState::GameOver(false, false));
+            let win = evaluator.evaluate(&FloorState::GameOver(true, false));
+            let heart = evaluator.evaluate(&FloorState::GameOver(true, true));
+            let run = evaluator.evaluate(&running);
+
+            assert_eq!(evaluator.evaluate(&FloorState::Menu), death);
+            assert!(death < run && run < win && win < heart);
+        }
+    }
+}
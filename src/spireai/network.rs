@@ -0,0 +1,240 @@
+use ron::de::from_reader;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs::File, path::Path};
+
+use super::encoder::{self, Layout, LAYOUT};
+use super::evaluator::Evaluator;
+use crate::state::floor::FloorState;
+
+// Bumped whenever the file layout or the meaning of the inputs changes
+pub const NETWORK_VERSION: u32 = 3;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Activation {
+    Linear,
+    Relu,
+    Tanh,
+}
+
+impl Activation {
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Activation::Linear => value,
+            Activation::Relu => value.max(0.0),
+            Activation::Tanh => value.tanh(),
+        }
+    }
+
+    // Written in terms of the activation's output, which is what training keeps around
+    pub fn derivative(self, output: f64) -> f64 {
+        match self {
+            Activation::Linear => 1.0,
+            Activation::Relu => {
+                if output > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Activation::Tanh => 1.0 - output * output,
+        }
+    }
+}
+
+// A fully connected layer. There is one row of weights per output.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+    pub weights: Vec<Vec<f64>>,
+    pub biases: Vec<f64>,
+    pub activation: Activation,
+}
+
+impl Layer {
+    fn inputs(&self) -> usize {
+        self.weights.first().map(|a| a.len()).unwrap_or(0)
+    }
+
+    fn outputs(&self) -> usize {
+        self.biases.len()
+    }
+
+    pub fn forward(&self, input: &[f64], output: &mut Vec<f64>) {
+        output.clear();
+        output.extend(self.weights.iter().zip(&self.biases).map(|(row, bias)| {
+            let sum: f64 = row.iter().zip(input).map(|(w, x)| w * x).sum();
+            self.activation.apply(sum + bias)
+        }));
+    }
+}
+
+// The inputs are scaled by the mean and scale recorded during training before the first layer.
+// The network has a single output, which is multiplied by output_scale to get a value.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Network {
+    pub version: u32,
+    pub layout: Layout, // The encoding the network was trained on
+    pub input_mean: Vec<f64>,
+    pub input_scale: Vec<f64>,
+    pub layers: Vec<Layer>,
+    pub output_scale: f64,
+}
+
+impl Network {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let network: Network = from_reader(file)?;
+        network.validate()?;
+        Ok(network)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version != NETWORK_VERSION {
+            return Err(format!(
+                "Network version {} is not supported, expected {}",
+                self.version, NETWORK_VERSION
+            ));
+        }
+        // Two encodings can have the same length and still put features in different places
+        if self.layout != *LAYOUT {
+            return Err(String::from(
+                "Network was trained on a different encoding of the state",
+            ));
+        }
+        let inputs = LAYOUT.len();
+        if self.input_mean.len() != inputs || self.input_scale.len() != inputs {
+            return Err(format!(
+                "Network input normalization has {} means and {} scales, expected {}",
+                self.input_mean.len(),
+                self.input_scale.len(),
+                inputs
+            ));
+        }
+
+        let mut size = inputs;
+        for (index, layer) in self.layers.iter().enumerate() {
+            if layer.weights.len() != layer.outputs()
+                || layer.weights.iter().any(|row| row.len() != size)
+            {
+                return Err(format!(
+                    "Layer {} does not take {} inputs to {} outputs",
+                    index,
+                    size,
+                    layer.outputs()
+                ));
+            }
+            size = layer.outputs();
+        }
+
+        if size != 1 {
+            return Err(format!("Network has {} outputs, expected 1", size));
+        }
+        Ok(())
+    }
+
+    pub fn normalize(&self, features: &[f64]) -> Vec<f64> {
+        features
+            .iter()
+            .zip(&self.input_mean)
+            .zip(&self.input_scale)
+            .map(|((x, mean), scale)| (x - mean) * scale)
+            .collect()
+    }
+
+    pub fn predict(&self, features: &[f64]) -> f64 {
+        let mut input = self.normalize(features);
+        let mut output = Vec::with_capacity(input.len());
+        for layer in &self.layers {
+            debug_assert_eq!(layer.inputs(), input.len());
+            layer.forward(&input, &mut output);
+            std::mem::swap(&mut input, &mut output);
+        }
+
+        input[0] * self.output_scale
+    }
+}
+
+pub struct NetworkEvaluator {
+    pub network: Network,
+}
+
+impl NetworkEvaluator {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(NetworkEvaluator {
+            network: Network::load(path)?,
+        })
+    }
+}
+
+impl Evaluator for NetworkEvaluator {
+    fn evaluate_run(&self, state: &FloorState) -> f64 {
+        self.network.predict(&encoder::encode(state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Activation, Layer, Network, LAYOUT, NETWORK_VERSION};
+
+    fn network(layers: Vec<Layer>) -> Network {
+        Network {
+            version: NETWORK_VERSION,
+            layout: LAYOUT.clone(),
+            input_mean: vec![0.0; LAYOUT.len()],
+            input_scale: vec![1.0; LAYOUT.len()],
+            layers,
+            output_scale: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_predict() {
+        let mut first = vec![vec![0.0; LAYOUT.len()]; 2];
+        first[0][0] = 1.0;
+        first[1][1] = -1.0;
+        let network = network(vec![
+            Layer {
+                weights: first,
+                biases: vec![0.5, 0.0],
+                activation: Activation::Relu,
+            },
+            Layer {
+                weights: vec![vec![2.0, 3.0]],
+                biases: vec![1.0],
+                activation: Activation::Linear,
+            },
+        ]);
+        assert_eq!(network.validate(), Ok(()));
+
+        let mut features = vec![0.0; LAYOUT.len()];
+        features[0] = 2.0;
+        features[1] = 4.0;
+        // relu(2 + 0.5) * 2 + relu(-4) * 3 + 1
+        assert_eq!(network.predict(&features), 6.0);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut old = network(vec![Layer {
+            weights: vec![vec![0.0; LAYOUT.len()]],
+            biases: vec![0.0],
+            activation: Activation::Linear,
+        }]);
+        old.version = NETWORK_VERSION + 1;
+        assert!(old.validate().is_err());
+
+        let mismatched = network(vec![Layer {
+            weights: vec![vec![0.0; LAYOUT.len() + 1]],
+            biases: vec![0.0],
+            activation: Activation::Linear,
+        }]);
+        assert!(mismatched.validate().is_err());
+
+        let mut relabelled = network(vec![Layer {
+            weights: vec![vec![0.0; LAYOUT.len()]],
+            biases: vec![0.0],
+            activation: Activation::Linear,
+        }]);
+        relabelled.layout.sections[0].labels[0] = String::from("renamed");
+        assert!(relabelled.validate().is_err());
+    }
+}
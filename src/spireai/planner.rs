@@ -0,0 +1,289 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::evaluator::{Evaluator, FloorEvaluator};
+use super::{enumerator, predictor};
+use crate::models::choices::Choice;
+use crate::state::battle::BattleState;
+use crate::state::floor::{FloorState, GamePossibility};
+use crate::state::probability::Probability;
+
+// Plans the rest of the player's turn by trying every order of the cards and potions that can be
+// played. A line stops being extended once something random happens (e.g. a card draw), or the
+// game asks for a card selection, since the rest of the turn depends on the outcome. Cards the
+// player can't afford are never enumerated, so lines end once the energy runs out.
+//
+// Lines that reach the same state are only searched once, and the end of the turn is scored by
+// taking the damage the monsters intend to deal off of the player's hp. Rollouts follow the plan
+// when their config has a planner.
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlannerConfig {
+    pub max_states: usize, // Lines are cut short once this many states have been searched
+}
+
+impl Default for PlannerConfig {
+    fn default() -> Self {
+        Self { max_states: 20_000 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TurnPlan {
+    // Ends with End, unless the line reaches a random outcome, a selection or the end of the battle
+    pub choices: Vec<Choice>,
+    pub value: f64,
+    pub states: usize,
+}
+
+// Scores battles by the player's hp against what is left of the monsters, and anything else with
+// the fallback evaluator. Dying is worse than any other line, and winning is better than any line
+// that doesn't win.
+pub struct TurnEvaluator {
+    pub fallback: FloorEvaluator,
+}
+
+const PLAYER_HP_WEIGHT: f64 = 3.0;
+const MONSTER_WEIGHT: f64 = 10.0; // For every monster left alive, on top of its hp
+const VICTORY_VALUE: f64 = 1000.0;
+
+impl Evaluator for TurnEvaluator {
+    fn evaluate(&self, state: &FloorState) -> f64 {
+        match state {
+            FloorState::GameOver(false, _) => f64::MIN,
+            FloorState::GameOver(true, _) => VICTORY_VALUE,
+            _ => self.evaluate_run(state),
+        }
+    }
+
+    fn evaluate_run(&self, state: &FloorState) -> f64 {
+        match state {
+            FloorState::Battle(battle) if battle.player.hp.amount == 0 => f64::MIN,
+            FloorState::Battle(battle) => {
+                let monsters: f64 = battle
+                    .available_monsters()
+                    .filter_map(|monster| battle.get_monster(monster))
+                    .map(|monster| monster.creature.hp.amount as f64 + MONSTER_WEIGHT)
+                    .sum();
+                let victory = if battle.battle_over {
+                    VICTORY_VALUE
+                } else {
+                    0.0
+                };
+                battle.player.hp.amount as f64 * PLAYER_HP_WEIGHT - monsters + victory
+            }
+            // The battle was won, and the rewards are up
+            FloorState::BattleRewards(rewards) => {
+                VICTORY_VALUE + rewards.game_state.hp.amount as f64 * PLAYER_HP_WEIGHT
+            }
+            _ => self.fallback.evaluate_run(state),
+        }
+    }
+}
+
+impl Default for TurnEvaluator {
+    fn default() -> Self {
+        TurnEvaluator {
+            fallback: FloorEvaluator,
+        }
+    }
+}
+
+pub fn plan_turn(
+    battle: &BattleState,
+    evaluator: &dyn Evaluator,
+    config: &PlannerConfig,
+) -> TurnPlan {
+    let mut planner = Planner {
+        evaluator,
+        config,
+        seen: HashSet::new(),
+        best: TurnPlan {
+            choices: vec![Choice::End],
+            value: f64::MIN,
+            states: 0,
+        },
+    };
+    planner.search(battle, &mut vec![]);
+
+    TurnPlan {
+        states: planner.seen.len(),
+        ..planner.best
+    }
+}
+
+struct Planner<'a> {
+    evaluator: &'a dyn Evaluator,
+    config: &'a PlannerConfig,
+    seen: HashSet<BattleState>,
+    best: TurnPlan,
+}
+
+impl<'a> Planner<'a> {
+    fn search(&mut self, battle: &BattleState, line: &mut Vec<Choice>) {
+        line.push(Choice::End);
+        let value = self.evaluator.evaluate(&end_of_turn(battle));
+        self.consider(line, value);
+        line.pop();
+
+        if self.seen.len() >= self.config.max_states {
+            return;
+        }
+
+        let state = FloorState::Battle(battle.clone());
+        for choice in enumerator::all_choices(&state) {
+            if !matches!(choice, Choice::PlayCard { .. } | Choice::DrinkPotion { .. }) {
+                continue;
+            }
+
+            let mut possibility = GamePossibility {
+                state: state.clone(),
+                probability: Probability::new(),
+            };
+            predictor::predict_outcome(choice.clone(), &mut possibility);
+            let deterministic = possibility.probability.probability > 0.99999;
+
+            line.push(choice);
+            match possibility.state {
+                FloorState::Battle(next)
+                    if deterministic && !next.battle_over && next.card_choose.is_none() =>
+                {
+                    if self.seen.insert(transposition_key(&next)) {
+                        self.search(&next, line);
+                    }
+                }
+                FloorState::Battle(next) if !next.battle_over => {
+                    let value = self.evaluator.evaluate(&end_of_turn(&next));
+                    self.consider(line, value);
+                }
+                outcome => {
+                    let value = self.evaluator.evaluate(&outcome);
+                    self.consider(line, value);
+                }
+            }
+            line.pop();
+        }
+    }
+
+    fn consider(&mut self, line: &[Choice], value: f64) {
+        if value > self.best.value {
+            self.best.choices = line.to_vec();
+            self.best.value = value;
+        }
+    }
+}
+
+// The order of the discard pile rarely matters, so playing the same cards in a different order
+// reaches the same state
+fn transposition_key(battle: &BattleState) -> BattleState {
+    let mut key = battle.clone();
+    key.discard.sort();
+    key
+}
+
+// What the battle will look like once the monsters have attacked, ignoring anything else that
+// happens at the end of the turn
+fn end_of_turn(battle: &BattleState) -> FloorState {
+    let unblocked = battle.incoming_damage().saturating_sub(battle.player.block);
+    if unblocked >= battle.player.hp.amount {
+        return FloorState::GameOver(false, false);
+    }
+
+    let mut battle = battle.clone();
+    battle.player.hp.amount -= unblocked;
+    battle.player.block = 0;
+    FloorState::Battle(battle)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::choices::Choice;
+    use crate::models::core::{Class, FightType};
+    use crate::state::battle::BattleState;
+    use crate::state::core::Card;
+    use crate::state::game::GameState;
+    use crate::state::probability::Probability;
+
+    use super::{plan_turn, PlannerConfig, TurnEvaluator};
+
+    fn battle(cards: &[&str]) -> BattleState {
+        let mut game_state = GameState::new(Class::Ironclad, 0);
+        game_state.deck = cards
+            .iter()
+            .map(|name| {
+                let card = Card::by_name(name);
+                (card.uuid, card)
+            })
+            .collect();
+        BattleState::new(
+            game_state,
+            &[String::from("Jaw Worm")],
+            FightType::Common,
+            &mut Probability::new(),
+        )
+    }
+
+    fn played(choice: &Choice) -> Option<&str> {
+        match choice {
+            Choice::PlayCard { card, .. } => Some(card.base.name.as_str()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_plan_spends_energy() {
+        let battle = battle(&["Strike"; 5]);
+
+        let evaluator = TurnEvaluator::default();
+        let plan = plan_turn(&battle, &evaluator, &PlannerConfig::default());
+
+        let strikes = plan
+            .choices
+            .iter()
+            .filter(|a| matches!(a, Choice::PlayCard { .. }))
+            .count();
+        assert_eq!(strikes, 3);
+        assert_eq!(plan.choices.last(), Some(&Choice::End));
+        assert!(plan.value > f64::MIN);
+
+        // The search stops expanding lines once it has seen enough states
+        let limited = plan_turn(&battle, &evaluator, &PlannerConfig { max_states: 1 });
+        assert!(limited.states < plan.states);
+    }
+
+    #[test]
+    fn test_plan_takes_lethal() {
+        let mut battle = battle(&["Strike", "Defend"]);
+        battle.energy = 1;
+        for (_, monster) in battle.monsters.iter_mut() {
+            monster.creature.hp.amount = 6;
+        }
+
+        let plan = plan_turn(
+            &battle,
+            &TurnEvaluator::default(),
+            &PlannerConfig::default(),
+        );
+        assert_eq!(plan.choices.iter().find_map(played), Some("Strike"));
+        assert!(plan.value >= super::VICTORY_VALUE);
+    }
+
+    #[test]
+    fn test_plan_blocks_lethal_damage() {
+        // Jaw Worm opens with an 11 damage attack, which only a Defend survives
+        let mut battle = battle(&["Strike", "Defend"]);
+        battle.energy = 1;
+        battle.player.hp.amount = 10;
+        assert!(battle.incoming_damage() >= 10);
+
+        let plan = plan_turn(
+            &battle,
+            &TurnEvaluator::default(),
+            &PlannerConfig::default(),
+        );
+        assert_eq!(plan.choices.iter().find_map(played), Some("Defend"));
+        assert!(plan.value > f64::MIN);
+    }
+}
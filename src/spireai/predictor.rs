@@ -9,8 +9,10 @@ use crate::state::core::{Card, Reward, RewardState};
 use crate::state::event::{EventScreenState, EventState};
 use crate::state::floor::{BattleRewardsState, ChestState, FloorState, RestScreenState, RestState};
 use crate::state::map::MapNodeIcon;
+use crate::state::probability::new_uuid;
 use crate::state::shop::{ShopScreenState, ShopState};
 use im::{vector, Vector};
+use itertools::Itertools;
 use models::choices::Choice;
 
 pub fn predict_outcome(choice: Choice, possibility: &mut GamePossibility) {
@@ -49,7 +51,7 @@ pub fn predict_outcome(choice: Choice, possibility: &mut GamePossibility) {
                     let state = possibility.state.game_state_mut();
                     for card in cards {
                         let mut new_card = state.deck[&card.uuid].clone();
-                        new_card.uuid = Uuid::new_v4();
+                        new_card.uuid = new_uuid();
                         state.deck.insert(new_card.uuid, new_card);
                     }
                 }
@@ -1042,7 +1044,7 @@ fn normal_fight(mut state: GameState, probability: &mut Probability) -> FloorSta
     ))
 }
 
-fn eval_monster_set(set: &MonsterSet, probability: &mut Probability) -> Vec<String> {
+pub fn eval_monster_set(set: &MonsterSet, probability: &mut Probability) -> Vec<String> {
     match set {
         MonsterSet::ChooseN { n, choices } => {
             probability.choose_multiple(choices.to_vec(), *n as usize)
@@ -1051,3 +1053,56 @@ fn eval_monster_set(set: &MonsterSet, probability: &mut Probability) -> Vec<Stri
         MonsterSet::RandomSet(sets) => probability.choose(sets.to_vec()).unwrap(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::models::buffs::VULNERABLE;
+    use crate::models::choices::Choice;
+    use crate::models::core::{Class, FightType};
+    use crate::spireai::enumerator;
+    use crate::state::battle::BattleState;
+    use crate::state::core::Card;
+    use crate::state::floor::{FloorState, GamePossibility};
+    use crate::state::game::GameState;
+    use crate::state::probability::Probability;
+
+    #[test]
+    fn test_new_buffs_have_their_amount() {
+        let mut game_state = GameState::new(Class::Ironclad, 0);
+        let bash = Card::by_name("Bash");
+        game_state.deck = vec![(bash.uuid, bash)].into_iter().collect();
+        let battle = BattleState::new(
+            game_state,
+            &[String::from("Jaw Worm")],
+            FightType::Common,
+            &mut Probability::new(),
+        );
+        let state = FloorState::Battle(battle);
+        let choice = enumerator::all_choices(&state)
+            .into_iter()
+            .find(|a| {
+                matches!(
+                    a,
+                    Choice::PlayCard {
+                        target: Some(_),
+                        ..
+                    }
+                )
+            })
+            .unwrap();
+
+        let mut possibility = GamePossibility {
+            state,
+            probability: Probability::new(),
+        };
+        super::predict_outcome(choice, &mut possibility);
+
+        match possibility.state {
+            FloorState::Battle(battle) => {
+                let monster = battle.monsters.values().next().unwrap();
+                assert_eq!(monster.creature.get_buff_amount(VULNERABLE), 2);
+            }
+            other => panic!("Expected a battle, got {:?}", other),
+        }
+    }
+}
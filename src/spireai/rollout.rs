@@ -0,0 +1,342 @@
+use std::cmp::Ordering;
+use std::panic::{self, AssertUnwindSafe};
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use super::evaluator::Evaluator;
+use super::planner::{self, PlannerConfig, TurnEvaluator};
+use super::{appraiser, enumerator, predictor, route, shopper};
+use crate::models::cards::BaseCard;
+use crate::models::choices::Choice;
+use crate::models::core::{CardType, Effect, FightType};
+use crate::state::battle::BattleState;
+use crate::state::floor::{FloorState, GamePossibility};
+use crate::state::probability::{self, Probability};
+use crate::state::shop::ShopScreenState;
+
+// Plays a state forward with cheap rules of thumb instead of searching, so that a new leaf of the
+// tree is valued by how the fight (or floor, or run) is likely to go rather than by how it looks
+// right now.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Horizon {
+    Battle, // Until the battle is won or lost. Leaves outside of a battle aren't played out.
+    Floor,  // Until the player is back on the map
+    Run,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RolloutConfig {
+    pub horizon: Horizon,
+    pub max_steps: usize,
+    // Plays battles by planning each turn, instead of with the rules of thumb. Slower, but it
+    // blocks and finishes off monsters when it matters.
+    pub planner: Option<PlannerConfig>,
+}
+
+impl Default for RolloutConfig {
+    fn default() -> Self {
+        Self {
+            horizon: Horizon::Battle,
+            max_steps: 500,
+            planner: None,
+        }
+    }
+}
+
+pub fn rollout(state: &FloorState, config: &RolloutConfig, evaluator: &dyn Evaluator) -> f64 {
+    evaluator.evaluate(&play_out(state, config))
+}
+
+// The state the rollout stops in
+pub fn play_out(state: &FloorState, config: &RolloutConfig) -> FloorState {
+    let mut state = state.clone();
+    if config.horizon == Horizon::Battle && !in_battle(&state) {
+        return state;
+    }
+
+    let mut plan = vec![];
+    for _ in 0..config.max_steps {
+        let choices = enumerator::all_choices(&state);
+        if choices.is_empty() {
+            break;
+        }
+
+        // Parts of the predictor are unfinished, so a panic ends the rollout where it is
+        let next = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut possibility = GamePossibility {
+                state: state.clone(),
+                probability: Probability::new(),
+            };
+            let choice = match &config.planner {
+                Some(planner) => planned_choice(&state, choices, planner, &mut plan),
+                None => choose(&state, choices),
+            };
+            predictor::predict_outcome(choice, &mut possibility);
+            possibility.state
+        }));
+        state = match next {
+            Ok(next) => next,
+            Err(_) => break,
+        };
+
+        let over = match config.horizon {
+            Horizon::Battle => !in_battle(&state),
+            Horizon::Floor => matches!(state, FloorState::Map(_)),
+            Horizon::Run => false,
+        };
+        if over || matches!(state, FloorState::GameOver(..)) {
+            break;
+        }
+    }
+
+    state
+}
+
+fn in_battle(state: &FloorState) -> bool {
+    matches!(state, FloorState::Battle(battle) if !battle.battle_over)
+}
+
+// Follows the plan for the turn while its next choice is available, and plans again once it isn't,
+// e.g. after a card draw. The plan is kept in reverse, so that the next choice is last.
+fn planned_choice(
+    state: &FloorState,
+    choices: Vec<Choice>,
+    config: &PlannerConfig,
+    plan: &mut Vec<Choice>,
+) -> Choice {
+    let battle = match state {
+        FloorState::Battle(battle) if battle.card_choose.is_none() && !battle.battle_over => battle,
+        _ => {
+            plan.clear();
+            return choose(state, choices);
+        }
+    };
+
+    if !plan.last().map_or(false, |a| choices.contains(a)) {
+        *plan = planner::plan_turn(battle, &TurnEvaluator::default(), config).choices;
+        plan.reverse();
+    }
+    plan.pop().unwrap_or(Choice::End)
+}
+
+pub fn choose(state: &FloorState, choices: Vec<Choice>) -> Choice {
+    match state {
+        FloorState::Battle(battle) if battle.card_choose.is_none() && !battle.battle_over => {
+            choose_play(battle, choices)
+        }
+        _ => choose_other(state, choices),
+    }
+}
+
+// Blocks if the monsters are about to kill the player, and drinks a potion if there's no block to
+// play. Potions are also drunk against bosses. Otherwise attacks the weakest monster, and once
+// there's nothing left to attack with, plays the rest of the hand before ending the turn.
+fn choose_play(battle: &BattleState, choices: Vec<Choice>) -> Choice {
+    let plays: Vec<(&Choice, &'static BaseCard)> = choices
+        .iter()
+        .filter_map(|choice| match choice {
+            Choice::PlayCard { card, .. } => Some((choice, card.base)),
+            _ => None,
+        })
+        .collect();
+
+    let potion = choices
+        .iter()
+        .find(|choice| matches!(choice, Choice::DrinkPotion { .. }));
+
+    let lethal = battle.incoming_damage() >= battle.player.hp.amount + battle.player.block;
+    if lethal {
+        if let Some((choice, _)) = plays.iter().find(|(_, card)| blocks(card)) {
+            return (*choice).clone();
+        }
+    }
+    if lethal || battle.fight_type == FightType::Boss {
+        if let Some(potion) = potion {
+            return potion.clone();
+        }
+    }
+
+    let attack = plays
+        .iter()
+        .filter(|(_, card)| card._type == CardType::Attack)
+        .min_by_key(|(choice, _)| match choice {
+            Choice::PlayCard {
+                target: Some(monster),
+                ..
+            } => battle
+                .get_monster(*monster)
+                .map_or(u16::MAX, |a| a.creature.hp.amount),
+            _ => u16::MAX,
+        });
+    if let Some((choice, _)) = attack {
+        return (*choice).clone();
+    }
+
+    match plays
+        .iter()
+        .find(|(_, card)| matches!(card._type, CardType::Skill | CardType::Power))
+    {
+        Some((choice, _)) => (*choice).clone(),
+        None => Choice::End,
+    }
+}
+
+fn blocks(card: &BaseCard) -> bool {
+    card.on_play
+        .iter()
+        .any(|effect| matches!(effect, Effect::Block { .. }))
+}
+
+// Takes every reward, the best rated card offer (which may be to skip it), the best route and the
+// best shop basket, and otherwise picks at random. Potions are left for choose_play, and never
+// thrown away.
+fn choose_other(state: &FloorState, choices: Vec<Choice>) -> Choice {
+    if let Some(choice) = choices
+        .iter()
+        .find(|choice| matches!(choice, Choice::TakeReward(_)))
+    {
+        return choice.clone();
+    }
+
+    let offered = choices
+        .iter()
+        .any(|choice| matches!(choice, Choice::AddCardToDeck(_)));
+    if offered {
+        let best = choices
+            .iter()
+            .filter_map(|choice| appraiser::rate_choice(choice, state).map(|a| (choice, a)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        if let Some((choice, _)) = best {
+            return choice.clone();
+        }
+    }
+
+    if let FloorState::Shop(shop) = state {
+        if shop.screen_state == ShopScreenState::InShop {
+            let choice = shopper::next_choice(shop);
+            if choices.contains(&choice) {
+                return choice;
+            }
+        }
+    }
+
+    if let FloorState::Map(game_state) = state {
+        if let Some((choice, _)) = route::rate_navigation(game_state)
+            .into_iter()
+            .find(|(choice, _)| choices.contains(choice))
+        {
+            return choice;
+        }
+    }
+
+    let kept: Vec<&Choice> = choices
+        .iter()
+        .filter(|choice| {
+            !matches!(
+                choice,
+                Choice::DiscardPotion { .. } | Choice::DrinkPotion { .. }
+            )
+        })
+        .collect();
+    probability::with_rng(|rng| match kept.choose(rng) {
+        Some(choice) => (*choice).clone(),
+        None => choices.choose(rng).unwrap().clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::choices::Choice;
+    use crate::models::core::{Class, FightType};
+    use crate::spireai::enumerator;
+    use crate::spireai::evaluator::{Evaluator, FloorEvaluator};
+    use crate::spireai::planner::PlannerConfig;
+    use crate::state::battle::BattleState;
+    use crate::state::core::Card;
+    use crate::state::floor::FloorState;
+    use crate::state::game::GameState;
+    use crate::state::probability::Probability;
+
+    use super::{choose, play_out, rollout, Horizon, RolloutConfig};
+
+    fn battle(cards: &[&str]) -> BattleState {
+        let mut game_state = GameState::new(Class::Ironclad, 0);
+        game_state.deck = cards
+            .iter()
+            .map(|name| {
+                let card = Card::by_name(name);
+                (card.uuid, card)
+            })
+            .collect();
+        BattleState::new(
+            game_state,
+            &[String::from("Jaw Worm")],
+            FightType::Common,
+            &mut Probability::new(),
+        )
+    }
+
+    #[test]
+    fn test_choose_attacks() {
+        let state = FloorState::Battle(battle(&["Strike", "Defend"]));
+        let choices = enumerator::all_choices(&state);
+        match choose(&state, choices) {
+            Choice::PlayCard { card, .. } => assert_eq!(card.base.name, "Strike"),
+            choice => panic!("Expected a strike, got {:?}", choice),
+        }
+    }
+
+    #[test]
+    fn test_choose_blocks_lethal_damage() {
+        // Jaw Worm opens with an 11 damage attack
+        let mut battle = battle(&["Strike", "Defend"]);
+        battle.player.hp.amount = 5;
+        let state = FloorState::Battle(battle);
+        let choices = enumerator::all_choices(&state);
+        match choose(&state, choices) {
+            Choice::PlayCard { card, .. } => assert_eq!(card.base.name, "Defend"),
+            choice => panic!("Expected a defend, got {:?}", choice),
+        }
+    }
+
+    #[test]
+    fn test_battle_rollout_finishes() {
+        let state = FloorState::Battle(battle(&["Strike"; 10]));
+        let config = RolloutConfig {
+            horizon: Horizon::Battle,
+            max_steps: 1000,
+            ..RolloutConfig::default()
+        };
+        // Ten strikes against a Jaw Worm win comfortably
+        let end = play_out(&state, &config);
+        assert!(
+            matches!(end, FloorState::BattleRewards(_)),
+            "Expected to win, ended in {:?}",
+            end
+        );
+
+        let map = FloorState::Map(GameState::new(Class::Ironclad, 0));
+        assert_eq!(
+            rollout(&map, &config, &FloorEvaluator),
+            FloorEvaluator.evaluate(&map)
+        );
+    }
+
+    #[test]
+    fn test_planned_rollout_wins() {
+        let config: RolloutConfig =
+            ron::de::from_str("(horizon: Battle, planner: Some((max_states: 500)))").unwrap();
+        assert_eq!(config.planner, Some(PlannerConfig { max_states: 500 }));
+
+        let state = FloorState::Battle(battle(&["Strike"; 10]));
+        let end = play_out(&state, &config);
+        assert!(
+            matches!(end, FloorState::BattleRewards(_)),
+            "Expected to win, ended in {:?}",
+            end
+        );
+    }
+}
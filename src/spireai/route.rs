@@ -0,0 +1,337 @@
+use std::cmp::Ordering;
+
+use super::appraiser;
+use crate::models::choices::Choice;
+use crate::state::floor::KeyState;
+use crate::state::game::GameState;
+use crate::state::map::{MapNode, MapNodeIcon, MapState};
+
+// Finds the best path from the current map node to the boss. Paths are walked with a rough
+// estimate of the hp and gold the player will have along the way, so that the same elite is
+// welcome on a full health bar and avoided on an empty one. Deck strength scales how much hp the
+// fights are expected to cost. Only the best walk that reaches each node is carried on from it, so
+// the map is planned in a single pass instead of walking every path.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RouteCounts {
+    pub monsters: u8,
+    pub elites: u8,
+    pub burning_elites: u8,
+    pub campfires: u8,
+    pub shops: u8,
+    pub unknowns: u8,
+    pub chests: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub nodes: Vec<usize>, // Map indices, starting with the next node
+    pub counts: RouteCounts,
+    pub score: f64,
+}
+
+impl Route {
+    // The choice that takes the first step of the route
+    pub fn choice(&self, map: &MapState) -> Choice {
+        Choice::NavigateToNode(map.nodes[self.nodes[0]].unwrap().x)
+    }
+}
+
+// Hp is tracked as a fraction of max hp, and value is on the scale of a card reward
+const MONSTER_DAMAGE: f64 = 0.08;
+const ELITE_DAMAGE: f64 = 0.22;
+const BURNING_ELITE_DAMAGE: f64 = 0.3;
+const UNKNOWN_DAMAGE: f64 = 0.03;
+const REST_HEAL: f64 = 0.3;
+const REST_BELOW: f64 = 0.5; // Rest instead of upgrading below this much hp
+
+const MONSTER_VALUE: f64 = 1.0;
+const ELITE_VALUE: f64 = 3.0;
+const UPGRADE_VALUE: f64 = 1.0;
+const HEAL_VALUE: f64 = 3.0; // For healing all of the player's hp
+const UNKNOWN_VALUE: f64 = 1.0;
+const CHEST_VALUE: f64 = 2.0;
+const SHOP_VALUE: f64 = 3.0; // For a shop visited with SHOP_GOLD
+const KEY_VALUE: f64 = 4.0;
+const DEATH_VALUE: f64 = -50.0;
+const HP_VALUE: f64 = 5.0; // For arriving at the boss with full hp
+const ROUTE_MARGIN: f64 = 3.0; // Routes further behind the best one aren't worth searching
+
+const SHOP_GOLD: f64 = 250.0;
+const MONSTER_GOLD: f64 = 15.0;
+const ELITE_GOLD: f64 = 30.0;
+const DECK_QUALITY_SCALE: f64 = 10.0;
+
+// The best route through each of the next nodes, best first
+pub fn plan_routes(game_state: &GameState) -> Vec<Route> {
+    let map = &game_state.map;
+    let strength = (1.0 + appraiser::deck_quality(game_state) / DECK_QUALITY_SCALE)
+        .max(0.5)
+        .min(2.0);
+    let mut routes: Vec<Route> = next_nodes(map)
+        .into_iter()
+        .filter_map(|start| best_route(map, start, game_state, strength))
+        .collect();
+    routes.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    routes
+}
+
+// The score of the best route through each of the next nodes
+pub fn rate_navigation(game_state: &GameState) -> Vec<(Choice, f64)> {
+    plan_routes(game_state)
+        .into_iter()
+        .map(|route| (route.choice(&game_state.map), route.score))
+        .collect()
+}
+
+// The navigation choices whose best route comes within ROUTE_MARGIN of the best one
+pub fn worth_exploring(game_state: &GameState) -> Vec<Choice> {
+    let ratings = rate_navigation(game_state);
+    let best = ratings.first().map_or(0.0, |a| a.1);
+    ratings
+        .into_iter()
+        .filter(|(_, score)| *score >= best - ROUTE_MARGIN)
+        .map(|(choice, _)| choice)
+        .collect()
+}
+
+fn next_nodes(map: &MapState) -> Vec<usize> {
+    match map.current_node() {
+        None => map
+            .nodes
+            .iter()
+            .flatten()
+            .take_while(|a| a.y == 0)
+            .map(|a| a.index())
+            .collect(),
+        Some(node) => children(map, node),
+    }
+}
+
+fn children(map: &MapState, node: MapNode) -> Vec<usize> {
+    let index = node.index();
+    [
+        (node.left, index + 6),
+        (node.up, index + 7),
+        (node.right, index + 8),
+    ]
+    .iter()
+    .filter(|(edge, child)| *edge && map.nodes.get(*child).map_or(false, |a| a.is_some()))
+    .map(|(_, child)| *child)
+    .collect()
+}
+
+// Children always come later in the map than their parents, so visiting nodes in order of index
+// settles the best walk into a node before it is carried on to the node's children
+fn best_route(
+    map: &MapState,
+    start: usize,
+    game_state: &GameState,
+    strength: f64,
+) -> Option<Route> {
+    let mut best: Vec<Option<(Walk, Vec<usize>)>> = vec![None; map.nodes.len()];
+    let mut walk = Walk::new(game_state);
+    walk.step(map.nodes[start].unwrap().icon, strength);
+    best[start] = Some((walk, vec![start]));
+
+    let mut ends = Vec::new();
+    for index in start..map.nodes.len() {
+        let (walk, path) = match best[index].take() {
+            Some(best) => best,
+            None => continue,
+        };
+        let next = children(map, map.nodes[index].unwrap());
+        if next.is_empty() {
+            ends.push((walk.score(), path));
+            continue;
+        }
+
+        for child in next {
+            let mut walk = walk.clone();
+            walk.step(map.nodes[child].unwrap().icon, strength);
+            if best[child]
+                .as_ref()
+                .map_or(true, |(a, _)| walk.score() > a.score())
+            {
+                let mut path = path.clone();
+                path.push(child);
+                best[child] = Some((walk, path));
+            }
+        }
+    }
+
+    ends.into_iter()
+        .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+        .map(|(score, nodes)| Route {
+            counts: counts(map, &nodes),
+            nodes,
+            score,
+        })
+}
+
+fn counts(map: &MapState, path: &[usize]) -> RouteCounts {
+    let mut counts = RouteCounts::default();
+    for index in path {
+        let count = match map.nodes[*index].unwrap().icon {
+            MapNodeIcon::Monster => &mut counts.monsters,
+            MapNodeIcon::Elite => &mut counts.elites,
+            MapNodeIcon::BurningElite => &mut counts.burning_elites,
+            MapNodeIcon::Campfire => &mut counts.campfires,
+            MapNodeIcon::Shop => &mut counts.shops,
+            MapNodeIcon::Question => &mut counts.unknowns,
+            MapNodeIcon::Chest => &mut counts.chests,
+        };
+        *count += 1;
+    }
+    counts
+}
+
+// Spends hp on fights and gold at shops along a route. Keys are only worth going for when the
+// run is collecting them.
+#[derive(Clone, Copy, Debug)]
+struct Walk {
+    hp: f64,
+    gold: f64,
+    keys: KeyState,
+    value: f64,
+    dead: bool,
+}
+
+impl Walk {
+    fn new(game_state: &GameState) -> Self {
+        Self {
+            hp: game_state.hp.amount as f64 / game_state.hp.max.max(1) as f64,
+            gold: game_state.gold as f64,
+            keys: game_state.keys.unwrap_or(KeyState {
+                ruby: true,
+                emerald: true,
+                sapphire: true,
+            }),
+            value: 0.0,
+            dead: false,
+        }
+    }
+
+    fn step(&mut self, icon: MapNodeIcon, strength: f64) {
+        if self.dead {
+            return;
+        }
+
+        match icon {
+            MapNodeIcon::Monster => {
+                self.hp -= MONSTER_DAMAGE / strength;
+                self.gold += MONSTER_GOLD;
+                self.value += MONSTER_VALUE;
+            }
+            MapNodeIcon::Elite => {
+                self.hp -= ELITE_DAMAGE / strength;
+                self.gold += ELITE_GOLD;
+                self.value += ELITE_VALUE;
+            }
+            MapNodeIcon::BurningElite => {
+                self.hp -= BURNING_ELITE_DAMAGE / strength;
+                self.gold += ELITE_GOLD;
+                self.value += ELITE_VALUE;
+                if !self.keys.emerald {
+                    self.keys.emerald = true;
+                    self.value += KEY_VALUE;
+                }
+            }
+            MapNodeIcon::Campfire => {
+                if !self.keys.ruby && self.hp >= REST_BELOW {
+                    self.keys.ruby = true;
+                    self.value += KEY_VALUE;
+                } else if self.hp < REST_BELOW {
+                    let healed = REST_HEAL.min(1.0 - self.hp);
+                    self.hp += healed;
+                    self.value += healed * HEAL_VALUE;
+                } else {
+                    self.value += UPGRADE_VALUE;
+                }
+            }
+            MapNodeIcon::Shop => {
+                self.value += SHOP_VALUE * (self.gold / SHOP_GOLD).min(1.0);
+                self.gold = 0.0;
+            }
+            MapNodeIcon::Question => {
+                self.hp -= UNKNOWN_DAMAGE / strength;
+                self.value += UNKNOWN_VALUE;
+            }
+            MapNodeIcon::Chest => {
+                // The sapphire key is taken instead of the relic
+                if !self.keys.sapphire {
+                    self.keys.sapphire = true;
+                    self.value += KEY_VALUE;
+                } else {
+                    self.value += CHEST_VALUE;
+                }
+            }
+        }
+
+        self.dead = self.hp <= 0.0;
+    }
+
+    fn score(&self) -> f64 {
+        if self.dead {
+            DEATH_VALUE + self.value
+        } else {
+            self.value + self.hp * HP_VALUE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::choices::Choice;
+    use crate::models::core::Class;
+    use crate::state::game::GameState;
+    use crate::state::map::{MapNode, MapNodeIcon};
+
+    use super::{plan_routes, rate_navigation, worth_exploring};
+
+    fn node(x: u8, y: u8, left: bool, up: bool, right: bool, icon: MapNodeIcon) -> Option<MapNode> {
+        Some(MapNode {
+            y,
+            x,
+            left,
+            up,
+            right,
+            icon,
+        })
+    }
+
+    // Two starting monsters, where the left one leads to an elite and the right one to a campfire
+    fn game_state(hp: u16) -> GameState {
+        let mut game_state = GameState::new(Class::Ironclad, 0);
+        game_state.map.nodes = [None; 105];
+        game_state.map.nodes[0] = node(0, 0, false, true, false, MapNodeIcon::Monster);
+        game_state.map.nodes[1] = node(1, 0, false, true, false, MapNodeIcon::Monster);
+        game_state.map.nodes[7] = node(0, 1, false, false, false, MapNodeIcon::Elite);
+        game_state.map.nodes[8] = node(1, 1, false, false, false, MapNodeIcon::Campfire);
+        game_state.hp.amount = hp;
+        game_state
+    }
+
+    #[test]
+    fn test_routes_depend_on_hp() {
+        let healthy = game_state(80);
+        let routes = plan_routes(&healthy);
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].nodes, vec![0, 7]);
+        assert_eq!(routes[0].counts.elites, 1);
+        assert!(routes[0].score >= routes[1].score);
+
+        let hurt = game_state(15);
+        assert_eq!(rate_navigation(&hurt)[0].0, Choice::NavigateToNode(1));
+    }
+
+    #[test]
+    fn test_hopeless_routes_not_explored() {
+        let healthy = game_state(80);
+        assert_eq!(worth_exploring(&healthy).len(), 2);
+
+        // The elite would kill the player before the campfire could heal them
+        let hurt = game_state(15);
+        assert_eq!(worth_exploring(&hurt), vec![Choice::NavigateToNode(1)]);
+    }
+}
@@ -0,0 +1,310 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rand::Rng;
+
+use super::encoder::LAYOUT;
+use super::evaluator::Evaluator;
+use super::{encoder, enumerator, predictor, MonteCarloTree, SearchConfig};
+use crate::models::choices::Choice;
+use crate::state::floor::{FloorState, GamePossibility};
+use crate::state::probability::{self, Probability};
+
+// Plays whole runs against the predictor instead of the game, and records every decision so that
+// value and policy models can be trained on them.
+
+#[derive(Clone, Copy, Debug)]
+pub enum Policy {
+    Search(SearchConfig),
+    Random,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SelfPlayConfig {
+    pub policy: Policy,
+    // Runs that go on longer than this are stopped and marked as unfinished
+    pub max_decisions: usize,
+}
+
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        Self {
+            policy: Policy::Search(SearchConfig::default()),
+            max_decisions: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunOutcome {
+    // False if the run hit the decision limit, or the predictor couldn't simulate a choice
+    pub finished: bool,
+    pub won: bool,
+    pub beat_heart: bool,
+    pub floor: i8,
+    pub value: f64,
+}
+
+// Totals over a batch of runs, to compare how well different settings play
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BatchStats {
+    pub runs: usize,
+    pub won: usize,
+    pub beat_heart: usize,
+    pub unfinished: usize,
+    pub floors: i64, // Summed over the runs
+    pub best_floor: i8,
+    pub decisions: usize,
+    pub value: f64,
+}
+
+impl BatchStats {
+    pub fn add(&mut self, outcome: &RunOutcome, decisions: usize) {
+        self.runs += 1;
+        self.won += outcome.won as usize;
+        self.beat_heart += outcome.beat_heart as usize;
+        self.unfinished += !outcome.finished as usize;
+        self.floors += outcome.floor.max(0) as i64;
+        self.best_floor = self.best_floor.max(outcome.floor);
+        self.decisions += decisions;
+        self.value += outcome.value;
+    }
+}
+
+impl fmt::Display for BatchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let runs = self.runs.max(1) as f64;
+        write!(
+            f,
+            "{} runs: {} won ({} beat the heart), {} died, {} unfinished. Average floor {:.1}, best floor {}, {:.1} decisions and {:.1} value per run",
+            self.runs,
+            self.won,
+            self.beat_heart,
+            self.runs - self.won - self.unfinished,
+            self.unfinished,
+            self.floors as f64 / runs,
+            self.best_floor,
+            self.decisions as f64 / runs,
+            self.value / runs
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub run: usize,
+    pub decision: usize,
+    // The encoded state, as (index, value) pairs for the values that aren't zero
+    pub features: Vec<(usize, f64)>,
+    pub choices: Vec<String>,
+    // Share of the search's visits that went to each choice. Uniform for the random policy.
+    pub visits: Vec<f64>,
+    pub chosen: usize,
+    pub outcome: RunOutcome,
+}
+
+pub fn play_run(run: usize, config: &SelfPlayConfig, evaluator: Arc<dyn Evaluator>) -> Vec<Record> {
+    let mut state = FloorState::Menu;
+    let mut tree: Option<MonteCarloTree> = None;
+    let mut records = Vec::new();
+    let mut floor = -1;
+
+    let finished = loop {
+        if let FloorState::GameOver(..) = state {
+            break true;
+        }
+        if records.len() >= config.max_decisions {
+            break false;
+        }
+        if !matches!(state, FloorState::Menu) {
+            floor = state.game_state().map.floor;
+        }
+
+        let choices = enumerator::all_choices(&state);
+        if choices.is_empty() {
+            break false;
+        }
+
+        // Parts of the predictor are unfinished, so a panic ends the run instead of the whole batch
+        let step = panic::catch_unwind(AssertUnwindSafe(|| {
+            let (visits, chosen) = match config.policy {
+                Policy::Search(search) => {
+                    search_visits(&mut tree, &state, &choices, &search, &evaluator)
+                }
+                Policy::Random => (
+                    vec![1.0 / choices.len() as f64; choices.len()],
+                    probability::with_rng(|rng| rng.gen_range(0..choices.len())),
+                ),
+            };
+
+            let next = apply(&state, choices[chosen].clone());
+            if let Some(tree) = &mut tree {
+                tree.new_root(Arc::new(Box::new(next.clone())));
+            }
+            (visits, chosen, next)
+        }));
+
+        let (visits, chosen, next) = match step {
+            Ok(step) => step,
+            Err(_) => break false,
+        };
+
+        records.push(Record {
+            run,
+            decision: records.len(),
+            features: sparse(&encoder::encode(&state)),
+            choices: choices.iter().map(|a| format!("{:?}", a)).collect(),
+            visits,
+            chosen,
+            outcome: RunOutcome::default(), // Filled in once the run is over
+        });
+        state = next;
+    };
+
+    let (won, beat_heart) = match state {
+        FloorState::GameOver(won, beat_heart) => (won, beat_heart),
+        _ => (false, false),
+    };
+    let outcome = RunOutcome {
+        finished,
+        won,
+        beat_heart,
+        floor,
+        value: evaluator.evaluate(&state),
+    };
+    for record in &mut records {
+        record.outcome = outcome;
+    }
+
+    records
+}
+
+// Searches from the state, reusing the tree from the last decision where it can.
+// Returns the visit distribution over the choices, and the most visited one.
+fn search_visits(
+    tree: &mut Option<MonteCarloTree>,
+    state: &FloorState,
+    choices: &[Choice],
+    config: &SearchConfig,
+    evaluator: &Arc<dyn Evaluator>,
+) -> (Vec<f64>, usize) {
+    let tree = tree.get_or_insert_with(|| {
+        MonteCarloTree::new(Arc::new(Box::new(state.clone())), evaluator.clone())
+    });
+    tree.search(config);
+
+    let visits: Vec<f64> = choices
+        .iter()
+        .map(|choice| tree.root.get_outcomes(choice).map_or(0.0, |a| a.visits))
+        .collect();
+    let total: f64 = visits.iter().sum::<f64>().max(1.0);
+    let chosen = (0..visits.len())
+        .max_by(|a, b| visits[*a].partial_cmp(&visits[*b]).unwrap())
+        .unwrap();
+
+    (visits.iter().map(|a| a / total).collect(), chosen)
+}
+
+fn apply(state: &FloorState, choice: Choice) -> FloorState {
+    let mut possibility = GamePossibility {
+        state: state.clone(),
+        probability: Probability::new(),
+    };
+    predictor::predict_outcome(choice, &mut possibility);
+    possibility.state
+}
+
+fn sparse(features: &[f64]) -> Vec<(usize, f64)> {
+    features
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value != 0.0)
+        .map(|(index, value)| (index, *value))
+        .collect()
+}
+
+// One JSON object per line
+pub fn write_records<W: Write>(records: &[Record], writer: &mut W) -> Result<(), Box<dyn Error>> {
+    for record in records {
+        serde_json::to_writer(&mut *writer, record)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// The layout of the encoding is kept next to the records, so that they aren't read with another
+pub fn layout_path(records: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.layout.ron", records.display()))
+}
+
+pub fn write_layout(records: &Path) -> Result<(), Box<dyn Error>> {
+    let layout = ron::ser::to_string_pretty(&*LAYOUT, ron::ser::PrettyConfig::default())?;
+    std::fs::write(layout_path(records), layout)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{play_run, write_records, BatchStats, Policy, RunOutcome, SelfPlayConfig};
+    use crate::spireai::evaluator::FloorEvaluator;
+
+    #[test]
+    fn test_records() {
+        let config = SelfPlayConfig {
+            policy: Policy::Random,
+            max_decisions: 5,
+        };
+        let records = play_run(3, &config, Arc::new(FloorEvaluator));
+
+        assert!(!records.is_empty() && records.len() <= 5);
+        for (index, record) in records.iter().enumerate() {
+            assert_eq!(record.run, 3);
+            assert_eq!(record.decision, index);
+            assert_eq!(record.choices.len(), record.visits.len());
+            assert!(record.chosen < record.choices.len());
+            assert_eq!(record.outcome, records[0].outcome);
+        }
+
+        let mut output = Vec::new();
+        write_records(&records, &mut output).unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), records.len());
+        assert_eq!(
+            lines[0]["choices"].as_array().unwrap().len(),
+            records[0].choices.len()
+        );
+    }
+
+    #[test]
+    fn test_batch_stats() {
+        let mut stats = BatchStats::default();
+        let won = RunOutcome {
+            finished: true,
+            won: true,
+            beat_heart: false,
+            floor: 51,
+            value: 10000.0,
+        };
+        stats.add(&won, 300);
+        stats.add(&RunOutcome::default(), 10);
+
+        assert_eq!(stats.runs, 2);
+        assert_eq!(stats.unfinished, 1);
+        assert_eq!(stats.best_floor, 51);
+        assert_eq!(
+            stats.to_string(),
+            "2 runs: 1 won (0 beat the heart), 0 died, 1 unfinished. Average floor 25.5, best floor 51, 155.0 decisions and 5000.0 value per run"
+        );
+    }
+}
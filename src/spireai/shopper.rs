@@ -0,0 +1,345 @@
+use std::cmp::Ordering;
+
+use super::{appraiser, route};
+use crate::models::choices::Choice;
+use crate::models::relics::{self, BaseRelic};
+use crate::state::core::Relic;
+use crate::state::game::{DeckCard, GameState};
+use crate::state::shop::ShopState;
+
+// Plans everything to buy in a shop at once, instead of one purchase at a time. Every basket that
+// the gold covers is rated with the appraiser, and gold that isn't spent is worth more when the
+// route passes another shop. Discount relics are bought first, since they make the rest of the
+// basket cheaper.
+//
+// Indices shift as items are bought (unless The Courier restocks them), so only the first
+// purchase of a basket should be acted on before planning again.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Purchase {
+    Card(usize),
+    Relic(usize),
+    Potion(usize),
+    Purge(DeckCard),
+}
+
+impl Purchase {
+    pub fn choice(self) -> Choice {
+        match self {
+            Purchase::Card(index) => Choice::BuyCard(index),
+            Purchase::Relic(index) => Choice::BuyRelic(index),
+            Purchase::Potion(index) => Choice::BuyPotion(index),
+            Purchase::Purge(card) => Choice::BuyRemoveCard(card),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Basket {
+    pub purchases: Vec<Purchase>,
+    pub cost: u16,
+    pub value: f64, // Includes the value of the gold left over
+}
+
+const SAVED_GOLD_VALUE: f64 = 0.05; // For every gold kept for a shop later on the route
+const SPARE_GOLD_VALUE: f64 = 0.01; // For every gold kept when there is no shop ahead
+const COURIER_RESTOCK_VALUE: f64 = 1.0; // For every item The Courier replaces
+const MEMBERSHIP_DISCOUNT: f64 = 0.5;
+const COURIER_DISCOUNT: f64 = 0.8;
+
+struct Item {
+    purchase: Purchase,
+    price: u16,
+    value: f64,
+    discount: Option<&'static BaseRelic>,
+}
+
+// Searched once for every set of discount relics, since prices only depend on which of those are
+// bought. A basket stops growing once it costs more than the gold, and is dropped once even every
+// item left couldn't make it better than the best basket so far.
+pub fn plan_basket(shop: &ShopState) -> Basket {
+    let game_state = &shop.game_state;
+    let (discounts, items): (Vec<Item>, Vec<Item>) =
+        items(shop).into_iter().partition(|a| a.discount.is_some());
+    let gold_value = gold_value(game_state);
+
+    let mut best = Basket {
+        purchases: vec![],
+        cost: 0,
+        value: game_state.gold as f64 * gold_value,
+    };
+    for mask in 0u32..(1 << discounts.len()) {
+        let relics: Vec<&Item> = discounts
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| mask & (1 << index) != 0)
+            .map(|(_, item)| item)
+            .collect();
+        let cost: u16 = relics.iter().map(|a| a.price).sum();
+        if cost > game_state.gold {
+            continue;
+        }
+
+        let courier = game_state.has_relic(relics::THE_COURIER)
+            || relics
+                .iter()
+                .any(|a| a.discount == Some(relics::THE_COURIER));
+        let worths: Vec<f64> = items.iter().map(|a| worth(a, courier)).collect();
+        let mut remaining = vec![0.0; items.len() + 1];
+        for index in (0..items.len()).rev() {
+            remaining[index] = remaining[index + 1] + worths[index];
+        }
+
+        let mut search = Search {
+            items: &items,
+            prices: prices(shop, &relics, &items),
+            worths,
+            remaining,
+            gold: game_state.gold,
+            free_slots: game_state.potions.iter().filter(|a| a.is_none()).count(),
+            gold_value,
+            best,
+        };
+        let value = relics.iter().map(|a| worth(a, courier)).sum();
+        search.extend(0, &mut relics.clone(), cost, value, 0);
+        best = search.best;
+    }
+
+    best
+}
+
+struct Search<'a> {
+    items: &'a [Item],
+    prices: Vec<u16>,
+    worths: Vec<f64>,
+    remaining: Vec<f64>, // The worth of every item from each index on
+    gold: u16,
+    free_slots: usize,
+    gold_value: f64,
+    best: Basket,
+}
+
+impl<'a> Search<'a> {
+    fn extend(
+        &mut self,
+        next: usize,
+        basket: &mut Vec<&'a Item>,
+        cost: u16,
+        value: f64,
+        potions: usize,
+    ) {
+        let spare = (self.gold - cost) as f64 * self.gold_value;
+        if value + spare > self.best.value {
+            let mut ordered = basket.clone();
+            ordered.sort_by(|a, b| {
+                b.discount
+                    .is_some()
+                    .cmp(&a.discount.is_some())
+                    .then(b.value.partial_cmp(&a.value).unwrap_or(Ordering::Equal))
+            });
+            self.best = Basket {
+                purchases: ordered.iter().map(|a| a.purchase).collect(),
+                cost,
+                value: value + spare,
+            };
+        }
+        if next == self.items.len() || value + self.remaining[next] + spare <= self.best.value {
+            return;
+        }
+
+        let item = &self.items[next];
+        let price = self.prices[next];
+        let potion = matches!(item.purchase, Purchase::Potion(_)) as usize;
+        if cost + price <= self.gold && potions + potion <= self.free_slots {
+            basket.push(item);
+            self.extend(
+                next + 1,
+                basket,
+                cost + price,
+                value + self.worths[next],
+                potions + potion,
+            );
+            basket.pop();
+        }
+        self.extend(next + 1, basket, cost, value, potions);
+    }
+}
+
+// The Courier replaces everything bought other than a removal
+fn worth(item: &Item, courier: bool) -> f64 {
+    if courier && !matches!(item.purchase, Purchase::Purge(_)) {
+        item.value + COURIER_RESTOCK_VALUE
+    } else {
+        item.value
+    }
+}
+
+// The first purchase of the best basket, or leaving the shop once there's nothing worth buying
+pub fn next_choice(shop: &ShopState) -> Choice {
+    match plan_basket(shop).purchases.first() {
+        Some(purchase) => purchase.choice(),
+        None => Choice::Proceed,
+    }
+}
+
+// Every purchase of the best basket, in case buying them in another order works out better
+pub fn worth_buying(shop: &ShopState) -> Vec<Choice> {
+    plan_basket(shop)
+        .purchases
+        .into_iter()
+        .map(Purchase::choice)
+        .collect()
+}
+
+// Everything in the shop that is worth having. Only the most useless card in the deck is
+// considered for removal.
+fn items(shop: &ShopState) -> Vec<Item> {
+    let game_state = &shop.game_state;
+    let mut items = Vec::new();
+    for (index, (offer, price)) in shop.cards.iter().enumerate() {
+        items.push(Item {
+            purchase: Purchase::Card(index),
+            price: *price,
+            value: appraiser::rate_card(offer.base, offer.upgraded, game_state),
+            discount: None,
+        });
+    }
+    for (index, (relic, price)) in shop.relics.iter().enumerate() {
+        items.push(Item {
+            purchase: Purchase::Relic(index),
+            price: *price,
+            value: appraiser::rate_relic(relic, game_state),
+            discount: if *relic == relics::MEMBERSHIP_CARD || *relic == relics::THE_COURIER {
+                Some(*relic)
+            } else {
+                None
+            },
+        });
+    }
+    for (index, (potion, price)) in shop.potions.iter().enumerate() {
+        items.push(Item {
+            purchase: Purchase::Potion(index),
+            price: *price,
+            value: appraiser::rate_potion(potion, game_state),
+            discount: None,
+        });
+    }
+    if shop.can_purge {
+        let purge = game_state
+            .removable_cards()
+            .map(|card| (card, appraiser::rate_removal(card, game_state)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        if let Some((card, value)) = purge {
+            items.push(Item {
+                purchase: Purchase::Purge(card),
+                price: shop.purge_cost(),
+                value,
+                discount: None,
+            });
+        }
+    }
+
+    items.retain(|a| a.value > 0.0);
+    items
+}
+
+// Prices in the shop already include the discounts of relics the player has. Discount relics
+// are bought first, and take their discount off of everything after them.
+fn prices(shop: &ShopState, relics: &[&Item], items: &[Item]) -> Vec<u16> {
+    if relics.is_empty() {
+        return items.iter().map(|a| a.price).collect();
+    }
+
+    let discount: f64 = relics
+        .iter()
+        .map(|a| {
+            if a.discount == Some(relics::MEMBERSHIP_CARD) {
+                MEMBERSHIP_DISCOUNT
+            } else {
+                COURIER_DISCOUNT
+            }
+        })
+        .product();
+    let mut discounted = shop.clone();
+    for relic in relics.iter().filter_map(|a| a.discount) {
+        discounted.game_state.relics.push_back(Relic::new(relic));
+    }
+    let purge_cost = discounted.purge_cost();
+
+    items
+        .iter()
+        .map(|item| match item.purchase {
+            Purchase::Purge(_) => purge_cost,
+            _ => (item.price as f64 * discount).ceil() as u16,
+        })
+        .collect()
+}
+
+fn gold_value(game_state: &GameState) -> f64 {
+    let shop_ahead = route::plan_routes(game_state)
+        .first()
+        .map_or(false, |route| route.counts.shops > 0);
+    if shop_ahead {
+        SAVED_GOLD_VALUE
+    } else {
+        SPARE_GOLD_VALUE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use im::vector;
+
+    use crate::models::choices::Choice;
+    use crate::models::core::Class;
+    use crate::models::{cards, relics};
+    use crate::state::core::CardOffer;
+    use crate::state::game::GameState;
+    use crate::state::shop::{ShopScreenState, ShopState};
+
+    use super::{next_choice, plan_basket, worth_buying, Purchase};
+
+    fn shop(gold: u16) -> ShopState {
+        let mut game_state = GameState::new(Class::Ironclad, 0);
+        game_state.gold = gold;
+        let offer = CardOffer {
+            base: cards::by_name("Pommel Strike"),
+            upgraded: false,
+        };
+        ShopState {
+            generated: true,
+            updated: false,
+            cards: vector![(offer, 60), (offer, 60)],
+            potions: vector![],
+            relics: vector![(relics::MEMBERSHIP_CARD, 60)],
+            can_purge: false,
+            game_state,
+            screen_state: ShopScreenState::InShop,
+        }
+    }
+
+    #[test]
+    fn test_discount_first() {
+        // Without the discount, the gold only covers two of the three
+        let basket = plan_basket(&shop(120));
+        assert_eq!(basket.purchases.len(), 3);
+        assert_eq!(basket.purchases[0], Purchase::Relic(0));
+        assert_eq!(basket.cost, 120);
+        assert_eq!(next_choice(&shop(120)), Choice::BuyRelic(0));
+
+        assert_eq!(next_choice(&shop(10)), Choice::Proceed);
+        assert!(worth_buying(&shop(10)).is_empty());
+        assert_eq!(worth_buying(&shop(120)).len(), 3);
+    }
+
+    #[test]
+    fn test_large_shop() {
+        // Far too many baskets to try them all, but only the ones the gold covers are searched
+        let mut shop = shop(130);
+        let offer = shop.cards[0].0;
+        shop.cards = (0..40).map(|_| (offer, 60)).collect();
+        let basket = plan_basket(&shop);
+        assert!(basket.cost <= 130);
+        assert!(basket.purchases.len() <= 3);
+    }
+}
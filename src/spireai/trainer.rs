@@ -0,0 +1,429 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use super::encoder::{self, Layout, LAYOUT};
+use super::evaluator::Evaluator;
+use super::network::{Activation, Layer, Network, NETWORK_VERSION};
+use super::selfplay::{self, Record};
+use crate::comm::interop;
+use crate::comm::request::Request;
+use crate::comm::transcript;
+use crate::state::floor::FloorState;
+use crate::state::HashMap;
+
+// Fits value networks to recorded runs. Every state in a run is labelled with the value of the
+// state the run ended in, so the network learns to predict how a run will turn out.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub group: usize, // Samples from the same run share a group, and are held out together
+    pub features: Vec<f64>,
+    pub target: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrainConfig {
+    // Sizes of the hidden layers. Without any, the network is a linear model.
+    pub hidden: Vec<usize>,
+    pub activation: Activation,
+    pub learning_rate: f64,
+    pub epochs: usize,
+    pub holdout: f64, // Fraction of the runs kept out of training to measure the loss on
+    pub seed: Option<u64>, // Makes the split, the initial weights and the order repeatable
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        Self {
+            hidden: vec![64],
+            activation: Activation::Relu,
+            learning_rate: 0.001,
+            epochs: 10,
+            holdout: 0.1,
+            seed: None,
+        }
+    }
+}
+
+// Root mean squared error, on the scale of the evaluation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EpochLoss {
+    pub epoch: usize,
+    pub train: f64,
+    pub holdout: Option<f64>,
+}
+
+// Self-play records are read from .jsonl files, anything else is read as a transcript
+pub fn load(paths: &[PathBuf], evaluator: &dyn Evaluator) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let mut samples: Vec<Sample> = Vec::new();
+    for path in paths {
+        let mut loaded = if path.extension().map_or(false, |a| a == "jsonl") {
+            read_records(path)?
+        } else {
+            read_transcript(path, evaluator)?
+        };
+
+        // Keep the runs of different files apart
+        let offset = samples.iter().map(|a| a.group + 1).max().unwrap_or(0);
+        for sample in &mut loaded {
+            sample.group += offset;
+        }
+        samples.extend(loaded);
+    }
+    Ok(samples)
+}
+
+pub fn read_records(path: &Path) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let layout_path = selfplay::layout_path(path);
+    let layout: Layout = ron::de::from_reader(File::open(&layout_path).map_err(|err| {
+        format!(
+            "Unable to open the layout {}: {}",
+            layout_path.display(),
+            err
+        )
+    })?)?;
+    if layout != *LAYOUT {
+        return Err(format!(
+            "{} was recorded with a different encoding of the state",
+            path.display()
+        )
+        .into());
+    }
+
+    let file = File::open(path)?;
+    let mut samples = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: Record = serde_json::from_str(&line)?;
+        let mut features = vec![0.0; LAYOUT.len()];
+        for (index, value) in record.features {
+            *features.get_mut(index).ok_or_else(|| {
+                format!(
+                    "Feature {} is outside of the layout, the records are from a different encoding",
+                    index
+                )
+            })? = value;
+        }
+
+        samples.push(Sample {
+            group: record.run,
+            features,
+            target: record.outcome.value,
+        });
+    }
+    Ok(samples)
+}
+
+// A transcript can hold several runs, which are split up at the menu and game over screens.
+// States that can't be imported are skipped.
+pub fn read_transcript(
+    path: &Path,
+    evaluator: &dyn Evaluator,
+) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let entries = transcript::read(path)?;
+    let mut uuid_map = HashMap::default();
+    let mut runs: Vec<Vec<FloorState>> = vec![vec![]];
+
+    for line in transcript::requests(&entries) {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(_) => continue,
+        };
+        if request.error.is_some() {
+            continue;
+        }
+
+        // Names missing from the data still panic while they are looked up
+        let previous = runs.last().unwrap().last().unwrap_or(&FloorState::Menu);
+        let state = match panic::catch_unwind(AssertUnwindSafe(|| {
+            interop::import_state(&request.game_state, previous, &mut uuid_map)
+        })) {
+            Ok(Ok(state)) => state,
+            _ => continue,
+        };
+
+        let run = runs.last_mut().unwrap();
+        match state {
+            FloorState::Menu => {}
+            _ if run.last() == Some(&state) => {}
+            _ => run.push(state.clone()),
+        }
+        if matches!(state, FloorState::Menu | FloorState::GameOver(..)) && !run.is_empty() {
+            runs.push(vec![]);
+        }
+    }
+
+    let mut samples = Vec::new();
+    for (group, run) in runs.iter().enumerate() {
+        let target = match run.last() {
+            Some(last) => evaluator.evaluate(last),
+            None => continue,
+        };
+        for state in run {
+            if !matches!(state, FloorState::GameOver(..)) {
+                samples.push(Sample {
+                    group,
+                    features: encoder::encode(state),
+                    target,
+                });
+            }
+        }
+    }
+    Ok(samples)
+}
+
+// Splits off a random selection of whole runs to measure the loss on
+pub fn split<'a>(
+    samples: &'a [Sample],
+    holdout: f64,
+    rng: &mut StdRng,
+) -> (Vec<&'a Sample>, Vec<&'a Sample>) {
+    let mut groups: Vec<usize> = samples.iter().map(|a| a.group).collect();
+    groups.sort_unstable();
+    groups.dedup();
+    groups.shuffle(rng);
+
+    let held =
+        ((groups.len() as f64 * holdout).round() as usize).min(groups.len().saturating_sub(1));
+    let held_groups = &groups[..held];
+    samples
+        .iter()
+        .partition(|sample| !held_groups.contains(&sample.group))
+}
+
+pub fn train<F>(samples: &[Sample], config: &TrainConfig, mut on_epoch: F) -> Network
+where
+    F: FnMut(&EpochLoss),
+{
+    assert!(!samples.is_empty(), "Nothing to train on");
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let (training, holdout) = split(samples, config.holdout, &mut rng);
+    let mut network = initial_network(&training, config, &mut rng);
+
+    for epoch in 0..config.epochs {
+        let mut order = training.clone();
+        order.shuffle(&mut rng);
+        for sample in order {
+            step(&mut network, sample, config.learning_rate);
+        }
+
+        on_epoch(&EpochLoss {
+            epoch,
+            train: loss(&network, &training),
+            holdout: if holdout.is_empty() {
+                None
+            } else {
+                Some(loss(&network, &holdout))
+            },
+        });
+    }
+
+    network
+}
+
+// Inputs are normalized to the training set, and the output is scaled to the size of the targets
+fn initial_network(samples: &[&Sample], config: &TrainConfig, rng: &mut StdRng) -> Network {
+    let inputs = samples[0].features.len();
+    let count = samples.len() as f64;
+    let mut input_mean = vec![0.0; inputs];
+    let mut input_scale = vec![0.0; inputs];
+    for sample in samples {
+        for (mean, x) in input_mean.iter_mut().zip(&sample.features) {
+            *mean += x / count;
+        }
+    }
+    for sample in samples {
+        for ((variance, mean), x) in input_scale
+            .iter_mut()
+            .zip(&input_mean)
+            .zip(&sample.features)
+        {
+            *variance += (x - mean) * (x - mean) / count;
+        }
+    }
+    for scale in &mut input_scale {
+        *scale = if *scale > 1e-12 {
+            1.0 / scale.sqrt()
+        } else {
+            1.0
+        };
+    }
+
+    let output_scale = (samples.iter().map(|a| a.target * a.target).sum::<f64>() / count)
+        .sqrt()
+        .max(1.0);
+
+    let mut size = inputs;
+    let mut layers = Vec::new();
+    for (index, outputs) in config.hidden.iter().copied().chain(Some(1)).enumerate() {
+        let range = (6.0 / (size + outputs) as f64).sqrt();
+        layers.push(Layer {
+            weights: (0..outputs)
+                .map(|_| (0..size).map(|_| rng.gen_range(-range..range)).collect())
+                .collect(),
+            biases: vec![0.0; outputs],
+            activation: if index == config.hidden.len() {
+                Activation::Linear
+            } else {
+                config.activation
+            },
+        });
+        size = outputs;
+    }
+
+    Network {
+        version: NETWORK_VERSION,
+        layout: LAYOUT.clone(),
+        input_mean,
+        input_scale,
+        layers,
+        output_scale,
+    }
+}
+
+// One step of stochastic gradient descent on the squared error of a single sample
+fn step(network: &mut Network, sample: &Sample, learning_rate: f64) {
+    let mut outputs = vec![network.normalize(&sample.features)];
+    for layer in &network.layers {
+        let mut output = Vec::with_capacity(layer.biases.len());
+        layer.forward(outputs.last().unwrap(), &mut output);
+        outputs.push(output);
+    }
+
+    let last = network.layers.len() - 1;
+    let output = outputs[last + 1][0];
+    let error = output - sample.target / network.output_scale;
+    let mut deltas = vec![error * network.layers[last].activation.derivative(output)];
+
+    for index in (0..network.layers.len()).rev() {
+        let input = &outputs[index];
+
+        // The deltas of the layer below are found before this layer's weights change
+        let next_deltas: Vec<f64> = if index > 0 {
+            let layer = &network.layers[index];
+            let activation = network.layers[index - 1].activation;
+            (0..input.len())
+                .map(|j| {
+                    let sum: f64 = deltas
+                        .iter()
+                        .zip(&layer.weights)
+                        .map(|(delta, row)| delta * row[j])
+                        .sum();
+                    sum * activation.derivative(input[j])
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let layer = &mut network.layers[index];
+        for ((delta, row), bias) in deltas.iter().zip(&mut layer.weights).zip(&mut layer.biases) {
+            for (weight, x) in row.iter_mut().zip(input) {
+                *weight -= learning_rate * delta * x;
+            }
+            *bias -= learning_rate * delta;
+        }
+        deltas = next_deltas;
+    }
+}
+
+fn loss(network: &Network, samples: &[&Sample]) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|a| (network.predict(&a.features) - a.target).powi(2))
+        .sum();
+    (total / samples.len() as f64).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{read_records, split, train, Sample, TrainConfig, LAYOUT};
+    use crate::spireai::selfplay;
+
+    // A noisy line through the first feature
+    fn samples(count: usize) -> Vec<Sample> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..count)
+            .map(|group| {
+                let x = rng.gen_range(0.0..10.0);
+                let mut features = vec![0.0; LAYOUT.len()];
+                features[0] = x;
+                features[1] = rng.gen_range(0.0..1.0);
+                Sample {
+                    group,
+                    features,
+                    target: 50.0 * x + 100.0 + rng.gen_range(-1.0..1.0),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_keeps_groups_together() {
+        let mut samples = samples(20);
+        for sample in &mut samples {
+            sample.group /= 2;
+        }
+        let (training, holdout) = split(&samples, 0.2, &mut StdRng::seed_from_u64(5));
+
+        assert_eq!(training.len() + holdout.len(), 20);
+        assert_eq!(holdout.len(), 4);
+        assert!(holdout
+            .iter()
+            .all(|a| training.iter().all(|b| a.group != b.group)));
+    }
+
+    #[test]
+    fn test_linear_fit() {
+        let config = TrainConfig {
+            hidden: vec![],
+            learning_rate: 0.01,
+            epochs: 30,
+            seed: Some(11),
+            ..TrainConfig::default()
+        };
+        let mut losses = vec![];
+        let network = train(&samples(200), &config, |loss| losses.push(*loss));
+
+        assert_eq!(network.validate(), Ok(()));
+        assert_eq!(losses.len(), 30);
+        let last = losses.last().unwrap();
+        assert!(last.train < 10.0, "Training loss is {}", last.train);
+        assert!(last.holdout.unwrap() < 10.0);
+    }
+
+    #[test]
+    fn test_records_need_their_layout() {
+        let path = std::env::temp_dir().join(format!("spireai-{}.jsonl", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "").unwrap();
+        assert!(read_records(&path).is_err());
+
+        let mut layout = LAYOUT.clone();
+        layout.version += 1;
+        let serialized = ron::ser::to_string(&layout).unwrap();
+        std::fs::write(selfplay::layout_path(&path), serialized).unwrap();
+        assert!(read_records(&path).is_err());
+
+        selfplay::write_layout(&path).unwrap();
+        assert_eq!(read_records(&path).unwrap(), vec![]);
+
+        std::fs::remove_file(selfplay::layout_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
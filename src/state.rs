@@ -6,3 +6,11 @@ pub mod game;
 pub mod map;
 pub mod probability;
 pub mod shop;
+
+use rustc_hash::FxHasher;
+use std::hash::BuildHasherDefault;
+
+// im's maps hash with a random seed by default, which changes the order they are iterated in (and
+// so which card or monster a random pick lands on) from one run to the next
+pub type HashMap<K, V> = im::HashMap<K, V, BuildHasherDefault<FxHasher>>;
+pub type HashSet<A> = im::HashSet<A, BuildHasherDefault<FxHasher>>;
@@ -1,6 +1,6 @@
 use std::ops::Range;
 
-use im::{vector, HashMap, HashSet, Vector};
+use im::{vector, Vector};
 use itertools::Itertools;
 use uuid::Uuid;
 
@@ -27,6 +27,7 @@ use super::{
     core::{Buff, Card, Creature, HpRange, Monster, Orb, Vars},
     game::{random_potion, GameState},
     probability::Probability,
+    HashMap, HashSet,
 };
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -161,8 +162,8 @@ impl BattleState {
             draw_bottom_known: Vector::new(),
             draw_inserted: Vector::new(),
             discard: Vector::new(),
-            exhaust: HashSet::new(),
-            hand: HashSet::new(),
+            exhaust: HashSet::default(),
+            hand: HashSet::default(),
             orbs: Vector::new(),
             player: Creature::player(state.hp),
             energy: 0,
@@ -2439,6 +2440,60 @@ impl BattleState {
             }
     }
 
+    // The most damage the monsters' intents can deal to the player, before block. When a monster
+    // has several possible moves (Runic Dome), the worst of them is counted.
+    pub fn incoming_damage(&self) -> u16 {
+        let vulnerable = if self.player.has_buff(buffs::VULNERABLE) {
+            if self.game_state.has_relic(relics::ODD_MUSHROOM) {
+                1.25
+            } else {
+                1.5
+            }
+        } else {
+            1.0
+        };
+        let intangible = self.player.has_buff(buffs::INTANGIBLE);
+
+        self.available_monsters()
+            .map(|monster_ref| {
+                let monster = self.get_monster(monster_ref).unwrap();
+                let binding = Binding::Creature(monster_ref.creature_ref());
+                let strength = monster.creature.get_buff_amount(buffs::STRENGTH);
+                let weak = if !monster.creature.has_buff(buffs::WEAK) {
+                    1.0
+                } else if self.game_state.has_relic(relics::PAPER_KRANE) {
+                    0.6
+                } else {
+                    0.75
+                };
+
+                monster
+                    .current_move_options
+                    .iter()
+                    .map(|(monster_move, _)| {
+                        monster_move
+                            .effects
+                            .iter()
+                            .map(|effect| match effect {
+                                Effect::AttackDamage { amount, times, .. } => {
+                                    let hit = (self.eval_amount(amount, binding) + strength).max(0);
+                                    let hit = if intangible {
+                                        hit.min(1) as u16
+                                    } else {
+                                        (hit as f64 * weak * vulnerable).floor() as u16
+                                    };
+                                    hit * self.eval_amount(times, binding).max(0) as u16
+                                }
+                                _ => 0,
+                            })
+                            .sum::<u16>()
+                    })
+                    .max()
+                    .unwrap_or(0)
+            })
+            .sum()
+    }
+
     pub fn move_out(&mut self, card: CardReference) {
         match card.location {
             CardLocation::DiscardPile => self
@@ -1,6 +1,6 @@
 use std::ptr;
 
-use im::{HashMap, Vector};
+use im::Vector;
 use uuid::Uuid;
 
 use crate::{
@@ -21,7 +21,8 @@ use crate::{
     },
 };
 
-use super::probability::Probability;
+use super::probability::{new_uuid, Probability};
+use super::HashMap;
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct Vars {
@@ -202,7 +203,7 @@ pub struct Card {
 impl Card {
     pub fn duplicate(&self) -> Self {
         let mut card = self.clone();
-        card.uuid = Uuid::new_v4();
+        card.uuid = new_uuid();
         card.bottled = false;
         card
     }
@@ -222,9 +223,11 @@ impl Card {
     }
 
     pub fn new(base: &'static BaseCard) -> Self {
-        let uuid = Uuid::new_v4();
+        let uuid = new_uuid();
 
         let cost = match base.cost {
+            // Unplayable cards have no cost, which the game reports as -2
+            _ if base.playable_if == Condition::Never => 0,
             Amount::Fixed(cost) => cost as u8,
             Amount::Upgradable { amount, .. } => amount as u8,
             Amount::X => 0,
@@ -337,12 +340,14 @@ impl Buff {
     }
 
     pub fn new(base: &'static BaseBuff, amount: i16) -> Self {
-        Buff {
+        let mut buff = Buff {
             base,
-            uuid: Uuid::new_v4(),
+            uuid: new_uuid(),
             vars: Vars::new(),
             card_stasis: None,
-        }
+        };
+        buff.vars.x = amount;
+        buff
     }
 
     pub fn reference(&self, creature: CreatureReference) -> BuffReference {
@@ -371,7 +376,7 @@ impl Relic {
     }
 
     pub fn new(base: &'static BaseRelic) -> Self {
-        let uuid = Uuid::new_v4();
+        let uuid = new_uuid();
         let mut relic = Relic {
             base,
             uuid,
@@ -430,7 +435,7 @@ impl Monster {
     }
 
     pub fn create(base: &'static BaseMonster, max_hp: u16) -> Self {
-        let uuid = Uuid::new_v4();
+        let uuid = new_uuid();
         let reference = MonsterReference { base, uuid };
 
         Monster {
@@ -441,7 +446,7 @@ impl Monster {
             targetable: true,
             intent: Intent::None,
             vars: Vars::new(),
-            whens: HashMap::new(),
+            whens: HashMap::default(),
             phase: 0,
             index: 0,
             current_move_options: Vector::new(),
@@ -1,4 +1,4 @@
-use im::{vector, HashMap, Vector};
+use im::{vector, Vector};
 use itertools::Itertools;
 use uuid::Uuid;
 
@@ -20,6 +20,7 @@ use super::{
     floor::KeyState,
     map::MapState,
     probability::Probability,
+    HashMap,
 };
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
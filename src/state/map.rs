@@ -1,7 +1,7 @@
-use im::{HashMap, HashSet};
 use itertools::Itertools;
 
 use super::probability::Probability;
+use super::{HashMap, HashSet};
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct MapState {
@@ -33,7 +33,7 @@ impl MapHistory {
             unknown_normal_count: 0,
             unknown_shop_count: 0,
             unknown_treasure_count: 0,
-            event_history: HashSet::new(),
+            event_history: HashSet::default(),
             last_shop: false,
         }
     }
@@ -189,7 +189,7 @@ impl MapState {
         let elites = ((count as f64) * (if more_elites { 0.128 } else { 0.08 })).round() as u8;
         let monsters = count - shops - rests - events - elites;
 
-        let mut all_options: HashMap<MapNodeIcon, u8> = HashMap::new();
+        let mut all_options: HashMap<MapNodeIcon, u8> = HashMap::default();
         all_options.insert(MapNodeIcon::Shop, shops);
         all_options.insert(MapNodeIcon::Campfire, rests);
         all_options.insert(MapNodeIcon::Question, events);
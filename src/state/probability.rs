@@ -1,12 +1,36 @@
 use rand::{
-    prelude::{IteratorRandom, SliceRandom, ThreadRng},
-    Rng,
+    prelude::{IteratorRandom, SliceRandom, StdRng},
+    Rng, SeedableRng,
 };
+use std::cell::RefCell;
+use uuid::{Builder, Uuid, Variant, Version};
+
+// Everything random outside of a Probability (uuids, the order choices are tried in, rollouts)
+// draws from this, so that seeding it makes a thread play the same way every time
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+pub fn seed_thread(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+pub fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+pub fn new_uuid() -> Uuid {
+    let bytes: [u8; 16] = with_rng(|rng| rng.gen());
+    Builder::from_bytes(bytes)
+        .set_variant(Variant::RFC4122)
+        .set_version(Version::Random)
+        .build()
+}
 
 #[derive(Clone, Debug)]
 pub struct Probability {
     pub probability: f64,
-    rng: ThreadRng,
+    rng: StdRng, // Unlike ThreadRng, this can be sent to the search threads
 }
 
 impl PartialEq for Probability {
@@ -77,7 +101,15 @@ impl Probability {
 
     pub fn new() -> Probability {
         Probability {
-            rng: ThreadRng::default(),
+            rng: with_rng(|rng| StdRng::from_rng(rng).unwrap()),
+            probability: 1.0,
+        }
+    }
+
+    // Rolls the same outcomes every time for the same seed
+    pub fn with_seed(seed: u64) -> Probability {
+        Probability {
+            rng: StdRng::seed_from_u64(seed),
             probability: 1.0,
         }
     }